@@ -0,0 +1,215 @@
+use std::str::FromStr;
+
+use log::LevelFilter;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("unknown argument: {0}")]
+    UnknownArgument(String),
+    #[error("missing value for {0}")]
+    MissingValue(String),
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+}
+
+const BENCH_ITERATIONS: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // Runs the daemon
+    Run,
+    // Prints the effective config and exits
+    PrintConfig,
+    // Prints the workspace history of a program
+    History(String),
+    // Applies every pruning rule to the storage
+    Compact,
+    // Prints the events recorded before nest last stopped
+    ReplayLastCrash,
+    // Explains what every filter decides for a class
+    Match(String),
+    // Prints the floating windows hyprland currently knows about
+    Floats,
+    // Prints the open windows that are not on the workspace nest would predict
+    Diff,
+    // Asks the running nest to move every open window to its prediction
+    Apply { dry_run: bool },
+    // Confirms the newest move an `ask` class is waiting on, or the one of the given class
+    Confirm(Option<String>),
+    // Clears the floating geometry of a program, keeping its workspace history
+    ResetFloat(String),
+    // Times the scoring of every stored program, running it the given number of times
+    Bench(usize),
+    // Prints what was changed through the command socket since nest started
+    Overrides,
+    // Undoes everything changed through the command socket
+    ClearOverrides,
+    // Prints a short status of the running nest, as json for status bars
+    Status { json: bool },
+    // Checks the pid file to tell whether nest is running, exits with 1 when it is not
+    DaemonStatus,
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub command: Command,
+    // Replaces the log level from the config for this run
+    pub log_level: Option<LevelFilter>,
+    // Never writes to the config dir, for when it is not writable
+    pub read_only: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.peekable();
+        let mut parsed = Args {
+            command: Command::Run,
+            log_level: None,
+            read_only: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "config" | "--print-config" => parsed.command = Command::PrintConfig,
+                "history" => match args.next() {
+                    Some(class) => parsed.command = Command::History(class),
+                    None => return Err(Error::MissingValue(arg)),
+                },
+                "match" => match args.next() {
+                    Some(class) => parsed.command = Command::Match(class),
+                    None => return Err(Error::MissingValue(arg)),
+                },
+                "floats" => parsed.command = Command::Floats,
+                "diff" => parsed.command = Command::Diff,
+                "confirm" => {
+                    parsed.command = Command::Confirm(args.next_if(|val| !val.starts_with("--")))
+                }
+                "apply" => parsed.command = Command::Apply { dry_run: false },
+                "--dry-run" => match &mut parsed.command {
+                    Command::Apply { dry_run } => *dry_run = true,
+                    _ => return Err(Error::UnknownArgument(arg)),
+                },
+                "reset-float" => match args.next() {
+                    Some(class) => parsed.command = Command::ResetFloat(class),
+                    None => return Err(Error::MissingValue(arg)),
+                },
+                "bench" => match args.next_if(|val| !val.starts_with("--")) {
+                    Some(count) => match count.parse() {
+                        Ok(iterations) => parsed.command = Command::Bench(iterations),
+                        Err(_) => return Err(Error::InvalidValue(arg, count)),
+                    },
+                    None => parsed.command = Command::Bench(BENCH_ITERATIONS),
+                },
+                "overrides" => parsed.command = Command::Overrides,
+                "clear-overrides" => parsed.command = Command::ClearOverrides,
+                "status" => parsed.command = Command::Status { json: false },
+                "daemon-status" => parsed.command = Command::DaemonStatus,
+                "--json" => match &mut parsed.command {
+                    Command::Status { json } => *json = true,
+                    _ => return Err(Error::UnknownArgument(arg)),
+                },
+                "--log-level" => match args.next() {
+                    Some(level) => match LevelFilter::from_str(&level) {
+                        Ok(val) => parsed.log_level = Some(val),
+                        Err(_) => return Err(Error::InvalidValue(arg, level)),
+                    },
+                    None => return Err(Error::MissingValue(arg)),
+                },
+                "--read-only" => parsed.read_only = true,
+                "compact" => parsed.command = Command::Compact,
+                "replay-last-crash" => parsed.command = Command::ReplayLastCrash,
+                _ => return Err(Error::UnknownArgument(arg)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|val| val.to_string()))
+    }
+
+    #[test]
+    fn runs_without_arguments() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.command, Command::Run);
+        assert_eq!(args.log_level, None);
+        assert!(!args.read_only);
+    }
+
+    #[test]
+    fn commands_with_values() {
+        assert_eq!(
+            parse(&["history", "kitty"]).unwrap().command,
+            Command::History("kitty".to_string())
+        );
+        assert!(matches!(parse(&["history"]), Err(Error::MissingValue(_))));
+        assert_eq!(parse(&["confirm"]).unwrap().command, Command::Confirm(None));
+        assert_eq!(
+            parse(&["confirm", "kitty"]).unwrap().command,
+            Command::Confirm(Some("kitty".to_string()))
+        );
+    }
+
+    #[test]
+    fn flags_after_commands() {
+        let args = parse(&["apply", "--dry-run", "--read-only"]).unwrap();
+        assert_eq!(args.command, Command::Apply { dry_run: true });
+        assert!(args.read_only);
+        assert_eq!(
+            parse(&["status", "--json"]).unwrap().command,
+            Command::Status { json: true }
+        );
+        assert!(matches!(
+            parse(&["--dry-run"]),
+            Err(Error::UnknownArgument(_))
+        ));
+        // A flag is not taken as the class to confirm
+        let args = parse(&["confirm", "--read-only"]).unwrap();
+        assert_eq!(args.command, Command::Confirm(None));
+        assert!(args.read_only);
+    }
+
+    #[test]
+    fn bench_count() {
+        assert_eq!(
+            parse(&["bench"]).unwrap().command,
+            Command::Bench(BENCH_ITERATIONS)
+        );
+        assert_eq!(parse(&["bench", "10"]).unwrap().command, Command::Bench(10));
+        let args = parse(&["bench", "--read-only"]).unwrap();
+        assert_eq!(args.command, Command::Bench(BENCH_ITERATIONS));
+        assert!(matches!(
+            parse(&["bench", "many"]),
+            Err(Error::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn log_level() {
+        assert_eq!(
+            parse(&["--log-level", "debug"]).unwrap().log_level,
+            Some(LevelFilter::Debug)
+        );
+        assert!(matches!(
+            parse(&["--log-level", "loud"]),
+            Err(Error::InvalidValue(_, _))
+        ));
+        assert!(matches!(
+            parse(&["--log-level"]),
+            Err(Error::MissingValue(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_argument() {
+        assert!(matches!(
+            parse(&["frobnicate"]),
+            Err(Error::UnknownArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,491 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::{
+    config::{Config, OnClose, PositionMode, ProgramFilter},
+    pid::Liveness,
+    state::{
+        ClientInfo, FloatingWindow, Matcher, MonitorInfo, Program, Scoring, SessionOverrides,
+        Status, WorkspaceId, calculate_workspace, client_monitor, fmt_address, matching_entry,
+        score_workspaces,
+    },
+};
+use chrono::{DateTime, Local, Utc};
+
+// Timeline of where a program was moved, oldest first, kept to plain ascii
+pub fn history(programs: &[Program], class: &str) -> String {
+    let program = match programs.iter().find(|program| program.class == class) {
+        Some(val) => val,
+        None => return format!("No program of type {class}\n"),
+    };
+    if program.workspaces.is_empty() {
+        return format!("{class} has no history\n");
+    }
+
+    let mut workspaces = program.workspaces.clone();
+    workspaces.sort_by_key(|workspace| workspace.timestamp);
+
+    let spark: String = workspaces
+        .iter()
+        .map(|workspace| spark_char(workspace.workspace_id))
+        .collect();
+    let mut out = format!("{class} [{spark}]\n");
+    for workspace in &workspaces {
+        let time = match DateTime::from_timestamp(workspace.timestamp, 0) {
+            Some(val) => val
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            None => workspace.timestamp.to_string(),
+        };
+        out.push_str(&format!("{time}  {}\n", workspace.workspace_id));
+    }
+    out
+}
+
+// Clears the floating geometry of a program, returns false if there is no such program
+pub fn reset_float(programs: &mut [Program], class: &str) -> bool {
+    match programs.iter_mut().find(|program| program.class == class) {
+        Some(program) => {
+            program.floating_window = None;
+            true
+        }
+        None => false,
+    }
+}
+
+// Recorded events with readable times, the raw lines are kept so they can be attached to a report
+pub fn replay(events: &[String]) -> String {
+    if events.is_empty() {
+        return "No events recorded, set record_events under [debug] to keep them\n".to_string();
+    }
+    let mut out = String::new();
+    for event in events {
+        let time = event
+            .split_once(';')
+            .and_then(|(timestamp, _)| timestamp.parse().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+        match time {
+            Some(val) => out.push_str(&format!(
+                "{}  {event}\n",
+                val.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+            )),
+            None => out.push_str(&format!("{event}\n")),
+        }
+    }
+    out
+}
+
+// Every floating client and how it relates to the geometry nest remembers for its class,
+// `manages` is the floating filter
+pub fn floats(
+    clients: &[ClientInfo],
+    monitors: &[MonitorInfo],
+    programs: &[Program],
+    manages: impl Fn(&str) -> bool,
+) -> String {
+    let floating: Vec<&ClientInfo> = clients.iter().filter(|client| client.floating).collect();
+    if floating.is_empty() {
+        return "No floating windows\n".to_string();
+    }
+    let mut out = String::new();
+    for client in floating {
+        let window = FloatingWindow::new(client.at, client.size, PositionMode::Exact, None);
+        // Remembered geometry is compared in pixels, whatever mode it was stored in
+        let remembered = programs
+            .iter()
+            .find(|program| program.class == client.class)
+            .and_then(|program| program.floating_window.as_ref())
+            .map(|val| {
+                let (at, size) = val.pixels(client_monitor(client, monitors));
+                FloatingWindow::new(at, size, PositionMode::Exact, None)
+            });
+        let status = match remembered {
+            _ if !manages(&client.class) => "filtered".to_string(),
+            Some(val) if val.approx_eq(&window) => "tracked".to_string(),
+            Some(val) => format!("tracked, remembered at {:?} size {:?}", val.at, val.size),
+            None => "untracked".to_string(),
+        };
+        out.push_str(&format!(
+            "{} {} at {:?} size {:?} ({status})\n",
+            client.class,
+            fmt_address(&client.address),
+            client.at,
+            client.size
+        ));
+    }
+    out
+}
+
+// Open windows sitting somewhere else than their prediction, with how sure the prediction is.
+// Windows are given as their class and the workspace they are on
+pub fn diff(windows: &[(String, WorkspaceId)], programs: &[Program], scoring: &Scoring) -> String {
+    let boosts = HashMap::new();
+    let now = Utc::now();
+    let mut rows: Vec<(&str, WorkspaceId, WorkspaceId, f64)> = Vec::new();
+    let mut unpredicted = 0;
+    for (class, current) in windows {
+        let workspaces = match programs.iter().find(|program| &program.class == class) {
+            Some(val) => &val.workspaces,
+            None => {
+                unpredicted += 1;
+                continue;
+            }
+        };
+        let (predicted, score) = match calculate_workspace(workspaces, scoring, &boosts, now) {
+            Some(val) => val,
+            None => {
+                unpredicted += 1;
+                continue;
+            }
+        };
+        let current = *current;
+        if current == predicted {
+            continue;
+        }
+        // Confidence is the share of the total score the prediction holds
+        let total: f64 = score_workspaces(workspaces, scoring, &boosts, now)
+            .values()
+            .sum();
+        let confidence = if total > 0.0 { score / total } else { 0.0 };
+        rows.push((class, current, predicted, confidence));
+    }
+
+    let mut out = if rows.is_empty() {
+        "Every window is where nest would put it\n".to_string()
+    } else {
+        rows.sort_by(|a, b| b.3.total_cmp(&a.3));
+        let width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(5);
+        let mut out = format!(
+            "{:<width$}  {:>7}  {:>9}  {:>10}\n",
+            "class", "current", "predicted", "confidence"
+        );
+        for (class, current, predicted, confidence) in rows {
+            out.push_str(&format!(
+                "{class:<width$}  {:>7}  {:>9}  {:>9.0}%\n",
+                current.to_string(),
+                predicted.to_string(),
+                confidence * 100.0
+            ));
+        }
+        out
+    };
+    if unpredicted > 0 {
+        out.push_str(&format!("{unpredicted} windows without a prediction\n"));
+    }
+    out
+}
+
+// What every filter decides for a class and which entry decided it, for when a rule does not
+// seem to apply
+pub fn match_class(config: &Config, programs: &[Program], class: &str) -> String {
+    let explain = |filter: &ProgramFilter| {
+        let decision = Matcher::new(&filter.programs).decision(class, &filter.mode);
+        let reason = match matching_entry(&filter.programs, class) {
+            Some(entry) => format!("{:?} list, matched by {entry}", filter.mode),
+            None => format!("{:?} list, no entry matches", filter.mode),
+        };
+        (decision, reason)
+    };
+    let program = programs.iter().find(|program| program.class == class);
+    let class_override = config.overrides.get(class);
+    let mut out = String::new();
+
+    let (tracked, reason) = explain(&config.workspace.filter);
+    let workspace = if !config.workspace.enabled {
+        "off (workspace.enabled)".to_string()
+    } else if !tracked {
+        format!("ignored ({reason})")
+    } else {
+        let mut ask = String::new();
+        if Matcher::new(&config.workspace.ask).matches(class) {
+            ask.push_str(", after nest confirm");
+        }
+        if Matcher::new(&config.workspace.freeze_learning).matches(class) {
+            ask.push_str(", learning frozen");
+        }
+        let prediction = program.and_then(|program| {
+            calculate_workspace(
+                &program.workspaces,
+                &Scoring::new(config),
+                &HashMap::new(),
+                Utc::now(),
+            )
+        });
+        match prediction {
+            Some((workspace_id, score)) => {
+                format!("moved to {workspace_id} (score {score:.2}{ask}, {reason})")
+            }
+            None => format!(
+                "tracked, no history yet so on_no_prediction = {:?}{ask} ({reason})",
+                config.workspace.on_no_prediction
+            ),
+        }
+    };
+    out.push_str(&format!("workspace   {workspace}\n"));
+
+    let (managed, reason) = explain(&config.floating.filter);
+    let floating = if !config.floating.enabled {
+        "off (floating.enabled)".to_string()
+    } else if !managed {
+        format!("ignored ({reason})")
+    } else if program.is_some_and(|program| program.floating_window.is_some()) {
+        format!("managed, geometry stored ({reason})")
+    } else {
+        format!("managed, no geometry stored ({reason})")
+    };
+    out.push_str(&format!("floating    {floating}\n"));
+
+    let (eligible, reason) = explain(&config.restore.filter);
+    let restore = match class_override.and_then(|val| val.on_close.clone()) {
+        Some(on_close) if on_close != OnClose::Restore => {
+            format!("replaced by on_close = {}", String::from(on_close))
+        }
+        _ => {
+            let enabled = match class_override.and_then(|val| val.restore) {
+                Some(val) => val,
+                None => config.restore.enabled,
+            };
+            let timeout = match class_override.and_then(|val| val.restore_timeout) {
+                Some(val) => val,
+                None => config.restore.timeout,
+            };
+            if !enabled {
+                "off (restore.enabled or overrides)".to_string()
+            } else if !eligible {
+                format!("ignored ({reason})")
+            } else {
+                format!("eligible when closed within {timeout}s ({reason})")
+            }
+        }
+    };
+    out.push_str(&format!("restore     {restore}\n"));
+
+    for (name, enabled, filter) in [
+        (
+            "properties",
+            config.properties.enabled,
+            &config.properties.filter,
+        ),
+        ("split", config.split.enabled, &config.split.filter),
+    ] {
+        let (managed, reason) = explain(filter);
+        let decision = if !enabled {
+            format!("off ({name}.enabled)")
+        } else if managed {
+            format!("managed ({reason})")
+        } else {
+            format!("ignored ({reason})")
+        };
+        out.push_str(&format!("{name:<12}{decision}\n"));
+    }
+    out
+}
+
+// Runs the scoring over every program `iterations` times, slowest program first
+pub fn bench(programs: &[Program], scoring: &Scoring, iterations: usize) -> String {
+    if programs.is_empty() {
+        return "No programs stored\n".to_string();
+    }
+    let iterations = iterations.max(1);
+    let boosts = HashMap::new();
+    let now = Utc::now();
+    let mut rows: Vec<(&str, usize, f64)> = Vec::with_capacity(programs.len());
+    let start = Instant::now();
+    for program in programs {
+        let program_start = Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(calculate_workspace(
+                &program.workspaces,
+                scoring,
+                &boosts,
+                now,
+            ));
+        }
+        let per_call = program_start.elapsed().as_secs_f64() * 1e6 / iterations as f64;
+        rows.push((&program.class, program.workspaces.len(), per_call));
+    }
+    let total = start.elapsed();
+    rows.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    let width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(5);
+    let mut out = format!("{:<width$}  {:>7}  {:>10}\n", "class", "entries", "us/call");
+    for (class, entries, per_call) in rows {
+        out.push_str(&format!(
+            "{class:<width$}  {entries:>7}  {per_call:>10.3}\n"
+        ));
+    }
+    let calls = programs.len() * iterations;
+    out.push_str(&format!(
+        "{calls} calls in {:.3}ms, {:.3}us per call\n",
+        total.as_secs_f64() * 1e3,
+        total.as_secs_f64() * 1e6 / calls as f64
+    ));
+    out
+}
+
+pub fn overrides(overrides: &SessionOverrides) -> String {
+    if overrides.disabled.is_empty() && overrides.boosts.is_empty() {
+        return "No session overrides\n".to_string();
+    }
+    let mut out = String::new();
+    for class in &overrides.disabled {
+        out.push_str(&format!("disabled  {class}\n"));
+    }
+    for boost in &overrides.boosts {
+        out.push_str(&format!(
+            "boost     workspace {} by {} for {}s\n",
+            boost.workspace, boost.weight, boost.remaining
+        ));
+    }
+    out
+}
+
+pub fn status(status: &Status) -> String {
+    if !status.healthy && status.workspace.is_none() {
+        return "nest is not running\n".to_string();
+    }
+    let mut out = String::new();
+    if let Some(workspace) = status.workspace {
+        out.push_str(&format!("workspace  {workspace}\n"));
+    }
+    out.push_str(&format!("programs   {}\n", status.programs));
+    match status.last_save_age {
+        Some(age) => out.push_str(&format!("last save  {age}s ago\n")),
+        None => out.push_str("last save  never\n"),
+    }
+    out.push_str(&format!("healthy    {}\n", status.healthy));
+    out
+}
+
+// Whether nest is running according to its pid file, followed by its status when it answered
+pub fn daemon_status(liveness: Liveness, status: Option<&Status>) -> String {
+    match liveness {
+        Liveness::Running(pid) => {
+            let mut out = format!("nest is running (pid {pid})\n");
+            match status {
+                Some(val) => out.push_str(&self::status(val)),
+                None => out.push_str("command socket not reachable\n"),
+            }
+            out
+        }
+        Liveness::Stale(pid) => {
+            format!("nest is not running, the pid file is stale (pid {pid} is gone)\n")
+        }
+        Liveness::NotRunning => "nest is not running\n".to_string(),
+    }
+}
+
+// One character per entry, 1-9 then a-z for workspaces 10-35, anything else is `?`
+fn spark_char(workspace_id: WorkspaceId) -> char {
+    match workspace_id.0 {
+        id @ 1..=9 => char::from(b'0' + id as u8),
+        id @ 10..=35 => char::from(b'a' + (id - 10) as u8),
+        _ => '?',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn programs(lines: &[&str]) -> Vec<Program> {
+        lines
+            .iter()
+            .map(|line| Program::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn history_is_sorted_oldest_first() {
+        let programs = programs(&["kitty:[12;200,3;100,-98;300]&[]"]);
+        let out = history(&programs, "kitty");
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("kitty [3c?]"));
+        let ids: Vec<&str> = lines.map(|line| line.rsplit(' ').next().unwrap()).collect();
+        assert_eq!(ids, vec!["3", "12", "-98"]);
+    }
+
+    #[test]
+    fn history_without_entries() {
+        let programs = programs(&["kitty:[]&[1;1;1;1]"]);
+        assert_eq!(history(&programs, "kitty"), "kitty has no history\n");
+        assert_eq!(history(&programs, "foot"), "No program of type foot\n");
+    }
+
+    // Entries made just now, repeated ids weigh more
+    fn learned(class: &str, ids: &[i32]) -> Program {
+        let now = chrono::Utc::now().timestamp();
+        let entries: Vec<String> = ids.iter().map(|id| format!("{id};{now}")).collect();
+        Program::from_str(&format!("{class}:[{}]&[]", entries.join(","))).unwrap()
+    }
+
+    #[test]
+    fn diff_lists_misplaced_windows() {
+        let programs = vec![
+            learned("kitty", &[2, 2, 2, 5]),
+            learned("firefox", &[1, 3, 3]),
+        ];
+        let windows = vec![
+            ("kitty".to_string(), WorkspaceId(5)),
+            ("firefox".to_string(), WorkspaceId(1)),
+            ("foot".to_string(), WorkspaceId(1)),
+        ];
+        let out = diff(&windows, &programs, &Scoring::default());
+        let lines: Vec<Vec<&str>> = out
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines[0],
+            vec!["class", "current", "predicted", "confidence"]
+        );
+        assert_eq!(lines[1], vec!["kitty", "5", "2", "75%"]);
+        // Most confident first
+        assert_eq!(lines[2], vec!["firefox", "1", "3", "67%"]);
+        assert_eq!(lines[3].join(" "), "1 windows without a prediction");
+    }
+
+    #[test]
+    fn diff_without_misplaced_windows() {
+        let programs = vec![learned("kitty", &[2])];
+        let windows = vec![("kitty".to_string(), WorkspaceId(2))];
+        assert_eq!(
+            diff(&windows, &programs, &Scoring::default()),
+            "Every window is where nest would put it\n"
+        );
+    }
+
+    #[test]
+    fn bench_reports_every_program() {
+        let programs = vec![learned("kitty", &[1, 2, 3]), learned("foot", &[1])];
+        let out = bench(&programs, &Scoring::default(), 3);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("class"));
+        let classes: Vec<&str> = lines[1..3]
+            .iter()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert!(classes.contains(&"kitty") && classes.contains(&"foot"));
+        assert!(lines[3].starts_with("6 calls in "));
+    }
+
+    #[test]
+    fn bench_runs_at_least_once() {
+        let programs = vec![learned("kitty", &[1])];
+        assert!(bench(&programs, &Scoring::default(), 0).contains("1 calls in "));
+        assert_eq!(bench(&[], &Scoring::default(), 10), "No programs stored\n");
+    }
+
+    #[test]
+    fn spark_chars() {
+        assert_eq!(spark_char(WorkspaceId(1)), '1');
+        assert_eq!(spark_char(WorkspaceId(9)), '9');
+        assert_eq!(spark_char(WorkspaceId(10)), 'a');
+        assert_eq!(spark_char(WorkspaceId(35)), 'z');
+        assert_eq!(spark_char(WorkspaceId(36)), '?');
+        assert_eq!(spark_char(WorkspaceId(0)), '?');
+    }
+}
@@ -1,12 +1,25 @@
 use std::{
+    collections::BTreeMap,
     fs::{File, create_dir_all},
-    io::{Read, Write},
-    path::Path,
+    io::{ErrorKind, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+// True if an io error came from a directory or file nest is not allowed to write to
+pub fn is_read_only(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+    )
+}
+
+// Hyprland events nest can react to
+pub const EVENTS: [&str; 4] = ["open", "move", "close", "workspace"];
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("could not find config directory")]
@@ -17,37 +30,220 @@ pub enum Error {
     TomlSer(#[from] toml::ser::Error),
     #[error("failed to read config: {0}")]
     TomlDe(#[from] toml::de::Error),
+    #[error("{0} is not writable, fix its permissions or start nest with --read-only")]
+    ReadOnly(PathBuf),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Config {
     pub workspace: WorkspaceConfig,
     pub floating: FloatingConfig,
     pub restore: RestoreConfig,
+    pub properties: PropertiesConfig,
+    pub split: SplitConfig,
+    pub telemetry: TelemetryConfig,
+    pub log: LogConfig,
+    pub notifications: NotificationConfig,
+    pub schedule: ScheduleConfig,
+    pub debug: DebugConfig,
+    pub storage: StorageConfig,
     pub save_frequency: u64,
+    pub reconcile_frequency: u64,
     pub log_level: String,
+    pub strict_classes: bool,
+    pub events: Vec<String>,
+    pub rename: Vec<Rename>,
+    pub group: Vec<ClassGroup>,
+    pub overrides: BTreeMap<String, ClassOverride>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct WorkspaceConfig {
+    pub enabled: bool,
     pub filter: ProgramFilter,
     pub buffer: usize,
     pub tau: f64,
+    pub ignore_transient: bool,
+    pub aggregate: bool,
+    pub learn_special: bool,
+    pub portable: bool,
+    pub learn_from_focus: bool,
+    pub focus_weight: f64,
+    pub verify_moves: bool,
+    pub monitor_tie_break: TieBreak,
+    pub restore_focus_after_startup: bool,
+    pub on_no_prediction: NoPrediction,
+    pub default_workspace: i32,
+    pub self_move_grace_ms: i64,
+    pub time_of_day_weight: f64,
+    pub fast_adapt: bool,
+    pub fast_adapt_secs: i64,
+    pub fast_adapt_weight: f64,
+    pub min_id: i32,
+    pub max_id: i32,
+    pub new_program_target: String,
+    pub ask: Vec<String>,
+    pub ask_timeout_secs: i64,
+    pub freeze_learning: Vec<String>,
+    pub learn_interval_secs: i64,
+    pub move_cooldown_ms: i64,
+    pub learn_user_moves_only: bool,
+    pub per_monitor: bool,
+    pub on_monitor_change: MonitorChange,
+    pub monitor_change_debounce_ms: u64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct FloatingConfig {
+    pub enabled: bool,
+    pub filter: ProgramFilter,
+    pub frequency: u64,
+    pub position_mode: PositionMode,
+    pub capture_interval_secs: i64,
+    pub monitor: FloatingMonitorConfig,
+    // Moved to [floating.monitor], still read so older configs keep working
+    #[serde(skip_serializing)]
+    pub min_size: Option<(i16, i16)>,
+    #[serde(skip_serializing)]
+    pub max_size: Option<(i16, i16)>,
+}
+
+// How restored floating windows are fitted to a monitor, each step works on the result of the
+// one before: pick the monitor, center windows that are off it, clamp to it, then min/max size
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FloatingMonitorConfig {
+    pub restore: MonitorRestore,
+    // 0 keeps the remembered size
+    pub default_size: (i16, i16),
+    pub clamp: bool,
+    pub min_size: (i16, i16),
+    pub max_size: (i16, i16),
+}
+
+impl WorkspaceConfig {
+    // Workspaces predictions may use, a min_id or max_id of 0 or less means no limit on that side
+    pub fn range(&self) -> RangeInclusive<i32> {
+        let min = if self.min_id <= 0 {
+            i32::MIN
+        } else {
+            self.min_id
+        };
+        let max = if self.max_id <= 0 {
+            i32::MAX
+        } else {
+            self.max_id
+        };
+        min..=max
+    }
+}
+
+// Window properties set through `setprop` on the command socket, reapplied on open
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PropertiesConfig {
+    pub enabled: bool,
+    pub filter: ProgramFilter,
+}
+
+// Split direction of tiled windows, learned by polling and restored on open
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SplitConfig {
+    pub enabled: bool,
     pub filter: ProgramFilter,
     pub frequency: u64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct RestoreConfig {
+    pub enabled: bool,
     pub filter: ProgramFilter,
     pub timeout: i64,
+    pub require_recent_activity_secs: i64,
+    pub persist_windows: bool,
+    pub on_zombie: OnZombie,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LogConfig {
+    pub timestamp_format: String,
+    pub dir: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NotificationConfig {
+    pub max_per_minute: u32,
+    pub milestones: bool,
+    pub confidence_threshold: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub quiet_hours: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DebugConfig {
+    pub record_events: bool,
+    pub event_buffer: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub fsync: bool,
+    pub compress: bool,
+    pub min_save_interval_secs: i64,
+    pub compact_on_startup: bool,
+    pub max_age_days: i64,
+}
+
+// Settings for a single program class, anything left out uses the global value
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ClassOverride {
+    pub buffer: Option<usize>,
+    pub restore: Option<bool>,
+    pub restore_timeout: Option<i64>,
+    pub on_close: Option<OnClose>,
+    pub capture_interval_secs: Option<i64>,
+    pub learn_interval_secs: Option<i64>,
+    // Workspace for the nth open window, keyed by n as a string since toml keys are strings
+    pub nth: BTreeMap<String, i32>,
+}
+
+// A program that changed class, its history is moved over on startup
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+// Classes matching `pattern` are learned as one program, named by `name` ($1 style captures)
+// or else by the first capture of the pattern
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ClassGroup {
+    pub pattern: String,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ProgramFilter {
     pub mode: FilterMode,
     pub programs: Vec<String>,
@@ -59,59 +255,472 @@ pub enum FilterMode {
     Exclude,
 }
 
+// Which monitor wins when a workspace is shown on more than one, e.g. when mirroring
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    // The monitor that currently has focus
+    ActiveMonitor,
+    // The monitor with the lowest id
+    LowestMonitor,
+}
+
+// Which monitor a floating window is restored on
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorRestore {
+    // The monitor that was focused when the window opened
+    Opened,
+    // The monitor focused when the geometry is applied
+    Focused,
+}
+
+// How floating geometry is stored
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionMode {
+    // Pixels in the global layout
+    Exact,
+    // Pixels from the top left corner of the monitor
+    Relative,
+    // Share of the monitor size, survives resolution changes
+    Percent,
+}
+
+// What happens when a window of a class closes, written as "restore", "none" or "exec:<cmd>"
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum OnClose {
+    // Go back to the workspace it was opened from, the default
+    Restore,
+    None,
+    // Runs a shell command with NEST_CLASS and NEST_ADDRESS set
+    Exec(String),
+}
+
+impl TryFrom<String> for OnClose {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "restore" => Ok(OnClose::Restore),
+            "none" => Ok(OnClose::None),
+            _ => match value.strip_prefix("exec:") {
+                Some(command) if !command.trim().is_empty() => {
+                    Ok(OnClose::Exec(command.to_string()))
+                }
+                _ => Err(format!(
+                    "invalid on_close {value}, expected restore, none or exec:<cmd>"
+                )),
+            },
+        }
+    }
+}
+
+impl From<OnClose> for String {
+    fn from(value: OnClose) -> Self {
+        match value {
+            OnClose::Restore => "restore".to_string(),
+            OnClose::None => "none".to_string(),
+            OnClose::Exec(command) => format!("exec:{command}"),
+        }
+    }
+}
+
+// What happens when a window turns out to have closed without a close event
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnZombie {
+    // Drop it without restoring anything
+    Skip,
+    // Restore it as if the close event had arrived
+    Restore,
+}
+
+// What nest does once monitors were added or removed, e.g. when docking. Switching config
+// profiles waits on profiles, nest has a single config for now
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorChange {
+    Nothing,
+    // Move every open window to its prediction, like `nest apply`
+    Reapply,
+}
+
+// What happens to a window nest has no history to place with
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoPrediction {
+    // Move it to the workspace that is active
+    Current,
+    // Move it to `default_workspace`
+    Default,
+    // Leave it where it opened
+    Leave,
+    // Leave it where it opened and send a notification
+    Notify,
+}
+
 impl Config {
-    pub fn new(app_name: &str, file_name: &str) -> Result<Self, Error> {
-        let config_dir = match dirs::config_dir() {
-            Some(val) => val,
-            None => return Err(Error::MissingConfig),
+    // A read only config dir is never written to, a missing config then means the defaults
+    pub fn new(app_name: &str, file_name: &str, read_only: bool) -> Result<Self, Error> {
+        let config_path = if read_only {
+            Self::dir(app_name)?.join(file_name)
+        } else {
+            Self::path(app_name, file_name)?
         };
-        let app_dir = config_dir.join(app_name);
-        create_dir_all(&app_dir)?;
-        let config_path = app_dir.join(file_name);
 
         if !Path::exists(&config_path) {
-            let mut file = File::create(&config_path)?;
             let config = Config::default();
-            let toml = toml::to_string(&config)?;
-            file.write_all(toml.as_bytes())?;
+            if !read_only {
+                config.write(app_name, file_name)?;
+            }
             Ok(config)
         } else {
-            let mut buf = String::new();
-            let mut file = File::open(&config_path)?;
-            file.read_to_string(&mut buf)?;
-            let config = toml::from_str(&buf)?;
-            Ok(config)
+            Self::read(&config_path)
+        }
+    }
+
+    fn read(config_path: &Path) -> Result<Self, Error> {
+        let mut buf = String::new();
+        let mut file = File::open(config_path)?;
+        file.read_to_string(&mut buf)?;
+        let mut config: Config = toml::from_str(&buf)?;
+        config.validate();
+        Ok(config)
+    }
+
+    // Writes the config back to disk, any comments in the existing file are lost
+    pub fn write(&self, app_name: &str, file_name: &str) -> Result<(), Error> {
+        self.write_to(Self::path(app_name, file_name)?)
+    }
+
+    fn write_to(&self, config_path: PathBuf) -> Result<(), Error> {
+        let toml = toml::to_string(self)?;
+        let mut file = match File::create(&config_path) {
+            Ok(val) => val,
+            Err(err) if is_read_only(&err) => return Err(Error::ReadOnly(config_path)),
+            Err(err) => return Err(Error::IO(err)),
+        };
+        file.write_all(toml.as_bytes())?;
+        Ok(())
+    }
+
+    fn dir(app_name: &str) -> Result<PathBuf, Error> {
+        match dirs::config_dir() {
+            Some(val) => Ok(val.join(app_name)),
+            None => Err(Error::MissingConfig),
+        }
+    }
+
+    fn path(app_name: &str, file_name: &str) -> Result<PathBuf, Error> {
+        let app_dir = Self::dir(app_name)?;
+        match create_dir_all(&app_dir) {
+            Ok(()) => Ok(app_dir.join(file_name)),
+            Err(err) if is_read_only(&err) => Err(Error::ReadOnly(app_dir)),
+            Err(err) => Err(Error::IO(err)),
+        }
+    }
+
+    // True if the handler for a kind of hyprland event should be registered
+    pub fn reacts_to(&self, kind: &str) -> bool {
+        self.events.iter().any(|val| val == kind)
+    }
+
+    // Entries of `events` nest has no handler for
+    pub fn unknown_events(&self) -> Vec<&String> {
+        self.events
+            .iter()
+            .filter(|kind| !EVENTS.contains(&kind.as_str()))
+            .collect()
+    }
+
+    // Keys that moved, as (old, new), which are set in the loaded config
+    pub fn deprecated_keys(&self) -> Vec<(&'static str, &'static str)> {
+        let mut keys = Vec::new();
+        if self.floating.min_size.is_some() {
+            keys.push(("floating.min_size", "floating.monitor.min_size"));
+        }
+        if self.floating.max_size.is_some() {
+            keys.push(("floating.max_size", "floating.monitor.max_size"));
+        }
+        keys
+    }
+
+    // Clamps values that would otherwise break nest at runtime
+    pub fn validate(&mut self) {
+        // A buffer of zero would drain every entry, leaving nothing to predict from
+        self.workspace.buffer = self.workspace.buffer.max(1);
+        for class in self.overrides.values_mut() {
+            if let Some(buffer) = &mut class.buffer {
+                *buffer = (*buffer).max(1);
+            }
+        }
+        // Past 1 moves made twelve hours apart would count against their workspace
+        self.workspace.time_of_day_weight = self.workspace.time_of_day_weight.clamp(0.0, 1.0);
+
+        // The old keys still apply until the config is rewritten
+        let monitor = &mut self.floating.monitor;
+        if let Some(size) = self.floating.min_size {
+            monitor.min_size = size;
         }
+        if let Some(size) = self.floating.max_size {
+            monitor.max_size = size;
+        }
+        // clamp panics if min is larger than max
+        monitor.max_size.0 = monitor.max_size.0.max(monitor.min_size.0);
+        monitor.max_size.1 = monitor.max_size.1.max(monitor.min_size.1);
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            workspace: WorkspaceConfig {
-                filter: ProgramFilter {
-                    mode: FilterMode::Exclude,
-                    programs: Vec::new(),
-                },
-                buffer: 30,
-                tau: 604800.0,
+            workspace: WorkspaceConfig::default(),
+            floating: FloatingConfig::default(),
+            restore: RestoreConfig::default(),
+            properties: PropertiesConfig::default(),
+            split: SplitConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            log: LogConfig::default(),
+            notifications: NotificationConfig::default(),
+            schedule: ScheduleConfig::default(),
+            debug: DebugConfig::default(),
+            storage: StorageConfig::default(),
+            save_frequency: 10,
+            reconcile_frequency: 300,
+            log_level: log::LevelFilter::Info.as_str().to_string(),
+            strict_classes: false,
+            events: EVENTS.iter().map(|val| val.to_string()).collect(),
+            rename: Vec::new(),
+            group: Vec::new(),
+            overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            filter: ProgramFilter {
+                mode: FilterMode::Exclude,
+                programs: Vec::new(),
             },
-            floating: FloatingConfig {
-                filter: ProgramFilter {
-                    mode: FilterMode::Include,
-                    programs: Vec::new(),
-                },
-                frequency: 5,
+            buffer: 30,
+            tau: 604800.0,
+            ignore_transient: false,
+            aggregate: false,
+            learn_special: false,
+            portable: false,
+            learn_from_focus: false,
+            focus_weight: 0.25,
+            verify_moves: false,
+            monitor_tie_break: TieBreak::ActiveMonitor,
+            restore_focus_after_startup: false,
+            on_no_prediction: NoPrediction::Current,
+            default_workspace: 1,
+            self_move_grace_ms: 500,
+            time_of_day_weight: 0.0,
+            fast_adapt: false,
+            fast_adapt_secs: 30,
+            fast_adapt_weight: 5.0,
+            min_id: 0,
+            max_id: 0,
+            new_program_target: "current".to_string(),
+            ask: Vec::new(),
+            ask_timeout_secs: 30,
+            freeze_learning: Vec::new(),
+            learn_interval_secs: 0,
+            move_cooldown_ms: 0,
+            learn_user_moves_only: false,
+            per_monitor: false,
+            on_monitor_change: MonitorChange::Nothing,
+            monitor_change_debounce_ms: 2000,
+        }
+    }
+}
+
+impl Default for FloatingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            filter: ProgramFilter {
+                mode: FilterMode::Include,
+                programs: Vec::new(),
             },
-            restore: RestoreConfig {
-                filter: ProgramFilter {
-                    mode: FilterMode::Include,
-                    programs: Vec::new(),
-                },
-                timeout: 120,
+            frequency: 5,
+            position_mode: PositionMode::Exact,
+            capture_interval_secs: 0,
+            monitor: FloatingMonitorConfig::default(),
+            min_size: None,
+            max_size: None,
+        }
+    }
+}
+
+impl Default for FloatingMonitorConfig {
+    fn default() -> Self {
+        Self {
+            restore: MonitorRestore::Opened,
+            default_size: (0, 0),
+            clamp: false,
+            min_size: (50, 50),
+            max_size: (i16::MAX, i16::MAX),
+        }
+    }
+}
+
+impl Default for PropertiesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            filter: ProgramFilter {
+                mode: FilterMode::Include,
+                programs: Vec::new(),
             },
-            save_frequency: 10,
-            log_level: log::LevelFilter::Info.as_str().to_string(),
         }
     }
 }
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            filter: ProgramFilter {
+                mode: FilterMode::Include,
+                programs: Vec::new(),
+            },
+            frequency: 5,
+        }
+    }
+}
+
+impl Default for RestoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            filter: ProgramFilter {
+                mode: FilterMode::Include,
+                programs: Vec::new(),
+            },
+            timeout: 120,
+            require_recent_activity_secs: 0,
+            persist_windows: false,
+            on_zombie: OnZombie::Skip,
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "telemetry.csv".to_string(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            timestamp_format: "%Y-%m-%dT%H:%M:%S%.3f%:z".to_string(),
+            dir: String::new(),
+        }
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            max_per_minute: 5,
+            milestones: false,
+            confidence_threshold: 0.8,
+        }
+    }
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            record_events: false,
+            event_buffer: 200,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(toml: &str) -> Config {
+        let mut config: Config = toml::from_str(toml).unwrap();
+        config.validate();
+        config
+    }
+
+    #[test]
+    fn old_floating_size_keys_still_apply() {
+        let config = load("[floating]\nmin_size = [10, 20]\n");
+        assert_eq!(config.floating.monitor.min_size, (10, 20));
+        assert_eq!(
+            config.deprecated_keys(),
+            vec![("floating.min_size", "floating.monitor.min_size")]
+        );
+        // Writing the config moves them over
+        let written = toml::to_string(&config).unwrap();
+        let config = load(&written);
+        assert_eq!(config.floating.monitor.min_size, (10, 20));
+        assert!(config.deprecated_keys().is_empty());
+    }
+
+    #[test]
+    fn buffers_are_at_least_one() {
+        let config = load("[workspace]\nbuffer = 0\n[overrides.kitty]\nbuffer = 0\n");
+        assert_eq!(config.workspace.buffer, 1);
+        assert_eq!(config.overrides["kitty"].buffer, Some(1));
+        let config = load("[workspace]\nbuffer = 12\n");
+        assert_eq!(config.workspace.buffer, 12);
+    }
+
+    #[test]
+    fn time_of_day_weight_is_a_share() {
+        assert_eq!(
+            load("[workspace]\ntime_of_day_weight = 3.0\n")
+                .workspace
+                .time_of_day_weight,
+            1.0
+        );
+        assert_eq!(
+            load("[workspace]\ntime_of_day_weight = -1.0\n")
+                .workspace
+                .time_of_day_weight,
+            0.0
+        );
+    }
+
+    #[test]
+    fn min_size_wins_over_max_size() {
+        let config = load("[floating.monitor]\nmin_size = [500, 50]\nmax_size = [100, 100]\n");
+        assert_eq!(config.floating.monitor.max_size, (500, 100));
+    }
+
+    #[test]
+    fn write_then_read() {
+        let path = std::env::temp_dir().join(format!("nest-config-{}.toml", std::process::id()));
+        let mut config = Config::default();
+        config.workspace.buffer = 12;
+        config.overrides.insert(
+            "kitty".to_string(),
+            ClassOverride {
+                buffer: Some(4),
+                ..ClassOverride::default()
+            },
+        );
+        for config in [Config::default(), config] {
+            config.write_to(path.clone()).unwrap();
+            assert_eq!(Config::read(&path).unwrap(), config);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::{
+    config::Config, notifier::Notifier, recorder::Recorder, schedule::Schedule, state::State,
+    storage::Storage, telemetry::Telemetry, windows::WindowFile,
+};
+
+// Everything the event handlers and background tasks share, cloned once per handler.
+// New services belong here instead of another clone in main
+#[derive(Clone)]
+pub struct AppContext {
+    pub state: State,
+    pub config: Arc<Config>,
+    pub storage: Arc<Mutex<Storage>>,
+    pub notifier: Notifier,
+    pub telemetry: Telemetry,
+    pub recorder: Recorder,
+    pub schedule: Schedule,
+    pub window_file: WindowFile,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::atomic::Ordering};
+
+    use super::*;
+    use crate::{
+        state::{Program, StateBuilder},
+        storage::MemStore,
+    };
+
+    // Everything off, so nothing touches the config dir
+    fn context(store: MemStore) -> AppContext {
+        let config = Config::default();
+        AppContext {
+            state: StateBuilder::default().build(),
+            storage: Arc::new(Mutex::new(Storage::memory(store))),
+            notifier: Notifier::new(&config.notifications),
+            telemetry: Telemetry::disabled(),
+            recorder: Recorder::new("nest-test", "events.txt", &config.debug).unwrap(),
+            schedule: Schedule::new(&config.schedule).unwrap(),
+            window_file: WindowFile::new("nest-test", "windows.txt", false).unwrap(),
+            config: Arc::new(config),
+        }
+    }
+
+    // A handler works on a clone, what it does is seen by every other one
+    #[tokio::test]
+    async fn clones_share_the_services() {
+        let store = MemStore::default();
+        let context = context(store.clone());
+        let handler = context.clone();
+        tokio::spawn(async move {
+            handler
+                .state
+                .reload(vec![Program::from_str("kitty:[1;100]&[]").unwrap()], false)
+                .await;
+            handler.state.changed.store(true, Ordering::Relaxed);
+            handler
+                .recorder
+                .record("open", &["0xa", "kitty"])
+                .await
+                .unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(context.state.get_programs().await.len(), 1);
+
+        let saver = context.clone();
+        saver
+            .storage
+            .lock()
+            .await
+            .persist(&saver.state)
+            .await
+            .unwrap();
+        assert!(!context.state.changed.load(Ordering::Relaxed));
+        let saved = Storage::memory(store).read().unwrap();
+        assert_eq!(saved[0].to_string(), "kitty:[1;100]&[]");
+        assert!(!context.schedule.is_quiet());
+        assert!(context.window_file.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::sleep;
+
+// Lets only the last of a burst of events act, e.g. docking reports every monitor on its own.
+// Clones share the count
+#[derive(Clone, Debug, Default)]
+pub struct Debounce(Arc<AtomicU64>);
+
+impl Debounce {
+    // Waits out `wait`, true if no other event came in meanwhile
+    pub async fn settled(&self, wait: Duration) -> bool {
+        let event = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        sleep(wait).await;
+        self.0.load(Ordering::Relaxed) == event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_the_last_of_a_burst_settles() {
+        let debounce = Debounce::default();
+        let first = tokio::spawn({
+            let debounce = debounce.clone();
+            async move { debounce.settled(Duration::from_millis(100)).await }
+        });
+        sleep(Duration::from_millis(10)).await;
+        assert!(debounce.settled(Duration::from_millis(100)).await);
+        assert!(!first.await.unwrap());
+        // Once things are quiet the next event acts again
+        assert!(debounce.settled(Duration::from_millis(10)).await);
+    }
+}
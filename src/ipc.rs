@@ -0,0 +1,427 @@
+use std::{
+    fs::remove_file,
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering},
+};
+
+use log::{debug, error, info};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::Mutex,
+};
+
+use crate::{
+    state::{MonitorId, Placement, State, WorkspaceId},
+    storage::Storage,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find runtime directory")]
+    MissingRuntime,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+// Line based control socket, every response ends with an empty line as metrics span several
+pub struct Ipc {
+    listener: UnixListener,
+    state: State,
+    storage: Arc<Mutex<Storage>>,
+}
+
+pub fn socket_path(app_name: &str) -> Result<PathBuf, Error> {
+    match dirs::runtime_dir() {
+        Some(val) => Ok(val.join(format!("{app_name}.sock"))),
+        None => Err(Error::MissingRuntime),
+    }
+}
+
+// Sends a single command to a running nest and returns its response
+pub async fn send(app_name: &str, command: &str) -> Result<String, Error> {
+    let stream = UnixStream::connect(socket_path(app_name)?).await?;
+    request(stream, command).await
+}
+
+async fn request(stream: UnixStream, command: &str) -> Result<String, Error> {
+    let (read, mut write) = stream.into_split();
+    write.write_all(command.as_bytes()).await?;
+    write.write_all(b"\n").await?;
+    let mut lines = BufReader::new(read).lines();
+    let mut response = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() {
+            break;
+        }
+        response.push(line);
+    }
+    Ok(response.join("\n"))
+}
+
+impl Ipc {
+    pub fn bind(app_name: &str, state: State, storage: Arc<Mutex<Storage>>) -> Result<Self, Error> {
+        let path = socket_path(app_name)?;
+        // A socket left behind by a previous run would make the bind fail
+        if path.exists() {
+            remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!("Listening for commands on {}", path.display());
+        Ok(Self {
+            listener,
+            state,
+            storage,
+        })
+    }
+
+    pub async fn listen(self) {
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok((val, _)) => val,
+                Err(err) => {
+                    error!("Failed to accept connection: {err}");
+                    continue;
+                }
+            };
+            let state = self.state.clone();
+            let storage = self.storage.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(stream, state, storage).await {
+                    error!("Failed to handle command: {err}");
+                }
+            });
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    state: State,
+    storage: Arc<Mutex<Storage>>,
+) -> Result<(), Error> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        debug!("Received command {line}");
+        let response = execute(line.trim(), &state, &storage).await;
+        for line in response.lines().filter(|line| !line.is_empty()) {
+            write.write_all(line.as_bytes()).await?;
+            write.write_all(b"\n").await?;
+        }
+        write.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+async fn execute(command: &str, state: &State, storage: &Mutex<Storage>) -> String {
+    let mut args = command.split_whitespace();
+    match args.next() {
+        Some("save") => match storage.lock().await.persist(state).await {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error: {err}"),
+        },
+        // Classes are taken as hyprland reports them and grouped like an opened window
+        Some("forget") => match args.next() {
+            Some(class) if state.forget_program(&state.group(class)).await => "ok".to_string(),
+            Some(class) => format!("error: no program of type {class}"),
+            None => "error: missing class".to_string(),
+        },
+        Some("reset-float") => match args.next() {
+            Some(class) => match state.remove_floating_window(&state.group(class)).await {
+                Ok(()) => "ok".to_string(),
+                Err(_) => format!("error: no program of type {class}"),
+            },
+            None => "error: missing class".to_string(),
+        },
+        Some(command @ ("enable" | "disable")) => match args.next() {
+            Some(class) => {
+                state
+                    .set_program_enabled(&state.group(class), command == "enable")
+                    .await;
+                "ok".to_string()
+            }
+            None => "error: missing class".to_string(),
+        },
+        Some("workspace") => match args.next().map(|val| val.parse::<MonitorId>()) {
+            Some(Ok(monitor)) => match state.current_workspace_for_monitor(monitor).await {
+                Some(val) => val.to_string(),
+                None => format!("error: no monitor with id {monitor}"),
+            },
+            Some(Err(err)) => format!("error: {err}"),
+            None => state.current_workspace().to_string(),
+        },
+        Some("boost") => {
+            let workspace_id = args.next().map(|val| val.parse::<WorkspaceId>());
+            let weight = args.next().map(|val| val.parse::<f64>());
+            let secs = args.next().map(|val| val.parse::<i64>());
+            match (workspace_id, weight, secs) {
+                (Some(Ok(workspace_id)), Some(Ok(weight)), Some(Ok(secs))) => {
+                    state.boost(workspace_id, weight, secs).await;
+                    "ok".to_string()
+                }
+                _ => "error: usage boost <workspace> <weight> <secs>".to_string(),
+            }
+        }
+        Some("ping") => match serde_json::to_string(&state.health().await) {
+            Ok(val) => val,
+            Err(err) => format!("error: {err}"),
+        },
+        Some("setprop") => match (args.next(), args.next()) {
+            // These characters separate the fields of the storage file
+            (Some(property), Some(value))
+                if !format!("{property}{value}").contains([':', ';', '=', '&', '[', ']']) =>
+            {
+                match state.set_active_property(property, value).await {
+                    Ok(_) => "ok".to_string(),
+                    Err(err) => format!("error: {err}"),
+                }
+            }
+            _ => "error: usage setprop <property> <value>".to_string(),
+        },
+        Some("overrides") => match serde_json::to_string(&state.session_overrides().await) {
+            Ok(val) => val,
+            Err(err) => format!("error: {err}"),
+        },
+        Some("clear-overrides") => {
+            state.clear_session_overrides().await;
+            "ok".to_string()
+        }
+        Some("compact") => {
+            let report = state.compact().await;
+            match storage.lock().await.persist(state).await {
+                Ok(()) => format!("ok, {report}"),
+                Err(err) => format!("error: {err}"),
+            }
+        }
+        Some("apply") => {
+            let dry_run = args.next() == Some("dry-run");
+            match state.apply(dry_run).await {
+                Ok(applied) if applied.is_empty() => "ok, nothing to move".to_string(),
+                Ok(applied) => format!(
+                    "ok, {}: {}",
+                    if dry_run { "would move" } else { "moved" },
+                    applied
+                        .iter()
+                        .map(|val| val.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+                Err(err) => format!("error: {err}"),
+            }
+        }
+        Some("confirm") => match state.confirm(args.next()).await {
+            Ok((class, Placement::Moved(workspace_id))) => {
+                format!("ok, moved {class} to {workspace_id}")
+            }
+            Ok((class, Placement::Error(err))) => format!("error: could not move {class}: {err}"),
+            Ok((class, _)) => format!("error: could not move {class}"),
+            Err(err) => format!("error: {err}"),
+        },
+        Some("reload-storage") => {
+            let flags: Vec<&str> = args.collect();
+            if !flags.contains(&"force") && state.changed.load(Ordering::Relaxed) {
+                return "error: there are unsaved changes, save first or use reload-storage force"
+                    .to_string();
+            }
+            let mut storage = storage.lock().await;
+            let programs = match storage.reopen().and_then(|_| storage.read()) {
+                Ok(val) => val,
+                Err(err) => return format!("error: {err}"),
+            };
+            let count = state.reload(programs, flags.contains(&"merge")).await;
+            format!("ok, {count} programs")
+        }
+        // Several lines, the text format is what prometheus scrapers expect
+        Some("metrics") => state.metrics().await.trim_end().to_string(),
+        Some("status") => match serde_json::to_string(&state.status().await) {
+            Ok(val) => val,
+            Err(err) => format!("error: {err}"),
+        },
+        Some(other) => format!("error: unknown command {other}"),
+        None => "error: empty command".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        state::{Program, StateBuilder, Status},
+        storage::MemStore,
+    };
+
+    // A connection to a nest serving `state`
+    fn connect(state: &State) -> UnixStream {
+        connect_to(state, Storage::memory(MemStore::default()))
+    }
+
+    fn connect_to(state: &State, storage: Storage) -> UnixStream {
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_connection(
+            server,
+            state.clone(),
+            Arc::new(Mutex::new(storage)),
+        ));
+        client
+    }
+
+    #[tokio::test]
+    async fn multi_line_response() {
+        let state = StateBuilder::default().build();
+
+        // The uptime gauge may tick in between, the lines are what matters
+        let response = request(connect(&state), "metrics").await.unwrap();
+        assert_eq!(
+            response.lines().count(),
+            state.metrics().await.trim_end().lines().count()
+        );
+        assert!(response.starts_with("# HELP nest_windows_seen_total"));
+    }
+
+    #[tokio::test]
+    async fn single_line_response() {
+        let state = StateBuilder::default().build();
+        assert_eq!(
+            request(connect(&state), "forget kitty").await.unwrap(),
+            "error: no program of type kitty"
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_shape() {
+        let state = StateBuilder::default().build();
+        state
+            .reload(vec![Program::from_str("kitty:[1;100]&[]").unwrap()], false)
+            .await;
+        let response = request(connect(&state), "ping").await.unwrap();
+        assert_eq!(response.lines().count(), 1);
+
+        let ping: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(ping.as_object().unwrap().len(), 4);
+        assert!(ping["uptime"].is_i64());
+        assert_eq!(ping["programs"], 1);
+        // Not saved yet and not listening to hyprland
+        assert_eq!(ping["last_save"], Value::Null);
+        assert_eq!(ping["connected"], false);
+    }
+
+    #[tokio::test]
+    async fn status_fields() {
+        let state = StateBuilder::default().build();
+        state
+            .reload(vec![Program::from_str("kitty:[1;100]&[]").unwrap()], false)
+            .await;
+        let status: Value =
+            serde_json::from_str(&request(connect(&state), "status").await.unwrap()).unwrap();
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "workspace": state.current_workspace().0,
+                "programs": 1,
+                "last_save_age": null,
+                "healthy": false,
+            })
+        );
+
+        state.mark_saved();
+        let status: Value =
+            serde_json::from_str(&request(connect(&state), "status").await.unwrap()).unwrap();
+        assert!(status["last_save_age"].as_i64().is_some_and(|age| age >= 0));
+    }
+
+    // What `nest status --json` prints when nest is not running
+    #[test]
+    fn status_without_nest() {
+        assert_eq!(
+            serde_json::to_string(&Status::default()).unwrap(),
+            r#"{"workspace":null,"programs":0,"last_save_age":null,"healthy":false}"#
+        );
+    }
+
+    // What is in memory against a storage file that was edited by hand
+    async fn edited(name: &str) -> (State, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("nest-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Storage::in_dir(&dir, "storage.txt")
+            .unwrap()
+            .write(&[Program::from_str("kitty:[1;100]&[]").unwrap()])
+            .unwrap();
+        let state = StateBuilder::default().build();
+        state
+            .reload(
+                vec![
+                    Program::from_str("kitty:[2;200]&[]").unwrap(),
+                    Program::from_str("foot:[3;300]&[]").unwrap(),
+                ],
+                false,
+            )
+            .await;
+        state.changed.store(true, Ordering::Relaxed);
+        (state, dir)
+    }
+
+    async fn programs(state: &State) -> Vec<String> {
+        let mut programs: Vec<String> = state
+            .get_programs()
+            .await
+            .iter()
+            .map(Program::to_string)
+            .collect();
+        programs.sort();
+        programs
+    }
+
+    #[tokio::test]
+    async fn reload_storage_replaces() {
+        let (state, dir) = edited("reload-replace").await;
+        let storage = || Storage::in_dir(&dir, "storage.txt").unwrap();
+
+        // Unsaved changes are not thrown away unless asked to
+        assert_eq!(
+            request(connect_to(&state, storage()), "reload-storage")
+                .await
+                .unwrap(),
+            "error: there are unsaved changes, save first or use reload-storage force"
+        );
+        assert_eq!(
+            programs(&state).await,
+            vec!["foot:[3;300]&[]", "kitty:[2;200]&[]"]
+        );
+
+        assert_eq!(
+            request(connect_to(&state, storage()), "reload-storage force")
+                .await
+                .unwrap(),
+            "ok, 1 programs"
+        );
+        assert_eq!(programs(&state).await, vec!["kitty:[1;100]&[]"]);
+        assert!(!state.changed.load(Ordering::Relaxed));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_storage_merges() {
+        let (state, dir) = edited("reload-merge").await;
+        let storage = Storage::in_dir(&dir, "storage.txt").unwrap();
+        assert_eq!(
+            request(connect_to(&state, storage), "reload-storage force merge")
+                .await
+                .unwrap(),
+            "ok, 2 programs"
+        );
+        // The file wins for programs it has, the rest is kept and still has to be saved
+        assert_eq!(
+            programs(&state).await,
+            vec!["foot:[3;300]&[]", "kitty:[1;100]&[]"]
+        );
+        assert!(state.changed.load(Ordering::Relaxed));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
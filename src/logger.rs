@@ -1,43 +1,157 @@
-use std::fs::create_dir_all;
+use std::{fmt::Display, fs::create_dir_all, path::PathBuf};
 
-use log::LevelFilter;
+use chrono::{
+    DateTime, TimeZone,
+    format::{Item, StrftimeItems},
+};
+use log::{Level, LevelFilter};
 use thiserror::Error;
 
+use crate::config::is_read_only;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("could not find config directory")]
     MissingConfig,
+    #[error("could not find state directory")]
+    MissingState,
+    #[error("{0} is not writable, fix its permissions or start nest with --read-only")]
+    ReadOnly(PathBuf),
     #[error("fern failed to init")]
     Fern,
     #[error("io operation failed")]
     IO(#[from] std::io::Error),
+    #[error("invalid timestamp format: {0}")]
+    TimestampFormat(String),
 }
 
-pub fn setup_logger(app_name: &str, file_name: &str, log_level: LevelFilter) -> Result<(), Error> {
-    let config_dir = match dirs::config_dir() {
-        Some(val) => val,
-        None => return Err(Error::MissingConfig),
-    };
-    let app_dir = config_dir.join(app_name);
-    create_dir_all(&app_dir)?;
-    let output_path = app_dir.join(file_name);
+pub fn setup_logger(
+    app_name: &str,
+    file_name: &str,
+    log_level: LevelFilter,
+    timestamp_format: &str,
+    // None logs to stdout only
+    dir: Option<&str>,
+) -> Result<(), Error> {
+    check_format(timestamp_format)?;
+    let timestamp_format = timestamp_format.to_string();
 
-    match fern::Dispatch::new()
-        .format(|out, message, record| {
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| {
             out.finish(format_args!(
-                "[{} {} {}] {}",
-                chrono::Local::now(),
-                record.level(),
-                record.target(),
-                message
+                "{}",
+                line(
+                    &chrono::Local::now(),
+                    &timestamp_format,
+                    record.level(),
+                    record.target(),
+                    message
+                )
             ))
         })
         .level(log_level)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(output_path)?)
-        .apply()
-    {
+        .chain(std::io::stdout());
+    if let Some(dir) = dir {
+        let app_dir = log_dir(app_name, dir)?;
+        let output_path = app_dir.join(file_name);
+        let file = match create_dir_all(&app_dir).and_then(|_| fern::log_file(&output_path)) {
+            Ok(val) => val,
+            Err(err) if is_read_only(&err) => return Err(Error::ReadOnly(output_path)),
+            Err(err) => return Err(Error::IO(err)),
+        };
+        dispatch = dispatch.chain(file);
+    }
+
+    match dispatch.apply() {
         Ok(_) => Ok(()),
         Err(_) => Err(Error::Fern),
     }
 }
+
+// chrono panics while logging if the format is invalid, so it is checked up front
+fn check_format(timestamp_format: &str) -> Result<(), Error> {
+    if StrftimeItems::new(timestamp_format).any(|item| matches!(item, Item::Error)) {
+        return Err(Error::TimestampFormat(timestamp_format.to_string()));
+    }
+    Ok(())
+}
+
+fn line<Tz: TimeZone>(
+    time: &DateTime<Tz>,
+    timestamp_format: &str,
+    level: Level,
+    target: &str,
+    message: impl Display,
+) -> String
+where
+    Tz::Offset: Display,
+{
+    format!(
+        "[{} {level} {target}] {message}",
+        time.format(timestamp_format)
+    )
+}
+
+// An empty dir keeps the logs next to the config like before, `state` follows XDG and
+// relative paths are resolved against the config directory
+fn log_dir(app_name: &str, dir: &str) -> Result<PathBuf, Error> {
+    let config_dir = match dirs::config_dir() {
+        Some(val) => val.join(app_name),
+        None => return Err(Error::MissingConfig),
+    };
+    match dir {
+        "" => Ok(config_dir),
+        // Not every platform has a state dir, the cache dir is the next best place
+        "state" => match dirs::state_dir().or_else(dirs::cache_dir) {
+            Some(val) => Ok(val.join(app_name)),
+            None => Err(Error::MissingState),
+        },
+        _ => Ok(config_dir.join(dir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+    use crate::config::LogConfig;
+
+    #[test]
+    fn default_format_is_iso_8601() {
+        let time = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2024, 3, 9, 14, 5, 7)
+            .unwrap();
+        assert_eq!(
+            line(
+                &time,
+                &LogConfig::default().timestamp_format,
+                Level::Info,
+                "nest",
+                "State saved to storage"
+            ),
+            "[2024-03-09T14:05:07.000+02:00 INFO nest] State saved to storage"
+        );
+        assert_eq!(
+            line(
+                &time.with_timezone(&Utc),
+                "%H:%M",
+                Level::Debug,
+                "nest::ipc",
+                "ping"
+            ),
+            "[12:05 DEBUG nest::ipc] ping"
+        );
+    }
+
+    #[test]
+    fn invalid_formats_are_refused() {
+        assert!(check_format("%Y-%m-%dT%H:%M:%S%.3f%:z").is_ok());
+        assert!(matches!(
+            check_format("%Y-%Q"),
+            Err(Error::TimestampFormat(_))
+        ));
+        assert!(check_format("%").is_err());
+    }
+}
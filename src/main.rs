@@ -1,35 +1,72 @@
-use std::{cmp, collections::HashMap, f64, str::FromStr, sync::atomic, time::Duration};
+use std::{
+    str::FromStr,
+    sync::{Arc, atomic},
+    time::Duration,
+};
 
-use chrono::Utc;
 use hyprland::{
-    ctl::{
-        Color,
-        notify::{self, Icon},
-    },
-    data::Clients,
+    ctl::{Color, notify::Icon},
     error::HyprError,
     event_listener::AsyncEventListener,
-    shared::HyprData,
+    shared::Address,
 };
-use log::{LevelFilter, debug, error, info};
+use log::{LevelFilter, debug, error, info, warn};
 use thiserror::Error;
-use tokio::time::sleep;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Mutex,
+    time::sleep,
+};
 
 use crate::{
-    config::Config,
+    cli::{Args, Command},
+    config::{Config, MonitorChange, NoPrediction, NotificationConfig},
+    context::AppContext,
+    debounce::Debounce,
+    ipc::Ipc,
     logger::setup_logger,
-    state::{FloatingWindow, State, Workspace},
+    notifier::Notifier,
+    recorder::Recorder,
+    schedule::Schedule,
+    state::{
+        ClientInfo, CompactReport, Compaction, FloatPlacement, FloatingWindow, Grouping, Hypr,
+        Hyprland, Matcher, Moved, OpenOutcome, Placement, Program, RemoveResult, Scoring,
+        SessionOverrides, Sightings, Split, State, Status, WorkspaceId, client_monitor,
+        fmt_address, is_maximized, unmatched,
+    },
     storage::Storage,
+    telemetry::Telemetry,
+    windows::WindowFile,
 };
+mod cli;
+mod commands;
 mod config;
+mod context;
+mod debounce;
+mod ipc;
 mod logger;
+mod notifier;
+mod pid;
+mod recorder;
+mod schedule;
+mod seed;
 mod state;
 mod storage;
+mod telemetry;
+mod windows;
 
 const APP_NAME: &str = "nest";
 const STORAGE_FILE_NAME: &str = "storage.txt";
 const CONFIG_FILE_NAME: &str = "config.toml";
 const LOG_FILE_NAME: &str = "output.txt";
+const EVENTS_FILE_NAME: &str = "events.txt";
+const WINDOWS_FILE_NAME: &str = "windows.txt";
+const SEED_FILE_NAME: &str = "seed.toml";
+const SETTLE_DURATION: Duration = Duration::from_secs(2);
+// Windows opened this soon after startup are treated as the login placement pass
+const STARTUP_PLACEMENT: Duration = Duration::from_secs(10);
+// Best workspace score a program needs before a milestone is reported, about three recent moves
+const MILESTONE_MIN_SCORE: f64 = 3.0;
 
 #[derive(Error, Debug)]
 enum Error {
@@ -37,26 +74,201 @@ enum Error {
     HyprError(#[from] HyprError),
     #[error("io error")]
     IO(#[from] std::io::Error),
+    #[error("window file error")]
+    Windows(#[from] crate::windows::Error),
     #[error("storage error")]
     Storage(#[from] crate::storage::Error),
     #[error("config error")]
     Config(#[from] crate::config::Error),
     #[error("logger error")]
     Logger(#[from] crate::logger::Error),
+    #[error("telemetry error")]
+    Telemetry(#[from] crate::telemetry::Error),
+    #[error("recorder error")]
+    Recorder(#[from] crate::recorder::Error),
+    #[error("seed error")]
+    Seed(#[from] crate::seed::Error),
+    #[error("schedule error")]
+    Schedule(#[from] crate::schedule::Error),
+    #[error("argument error")]
+    Cli(#[from] crate::cli::Error),
+    #[error("could not reach a running nest")]
+    Ipc(#[from] crate::ipc::Error),
+    #[error("pid file error")]
+    Pid(#[from] crate::pid::Error),
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
-    let config = match Config::new(APP_NAME, CONFIG_FILE_NAME) {
+    let args = Args::parse(std::env::args().skip(1))?;
+
+    match &args.command {
+        Command::Run => (),
+        Command::PrintConfig => {
+            let config = load_config(&args)?;
+            print!("{}", toml::to_string(&config).map_err(config::Error::from)?);
+            return Ok(());
+        }
+        Command::History(class) => {
+            let config = load_config(&args)?;
+            let programs = open_storage(&args)?.read()?;
+            let class = Grouping::new(&config.group).class(class).into_owned();
+            print!("{}", commands::history(&programs, &class));
+            return Ok(());
+        }
+        Command::Match(class) => {
+            let config = load_config(&args)?;
+            let programs = open_storage(&args)?.read()?;
+            let class = Grouping::new(&config.group).class(class).into_owned();
+            print!("{}", commands::match_class(&config, &programs, &class));
+            return Ok(());
+        }
+        Command::Floats => {
+            let config = load_config(&args)?;
+            let matcher = Matcher::new(&config.floating.filter.programs);
+            let programs = open_storage(&args)?.read()?;
+            let clients = grouped_clients(&config).await?;
+            let monitors = Hyprland.monitors().await?;
+            print!(
+                "{}",
+                commands::floats(&clients, &monitors, &programs, |class| {
+                    matcher.decision(class, &config.floating.filter.mode)
+                })
+            );
+            return Ok(());
+        }
+        Command::Diff => {
+            let config = load_config(&args)?;
+            let programs = open_storage(&args)?.read()?;
+            let windows: Vec<(String, WorkspaceId)> = grouped_clients(&config)
+                .await?
+                .into_iter()
+                .map(|client| (client.class, client.workspace))
+                .collect();
+            print!(
+                "{}",
+                commands::diff(&windows, &programs, &Scoring::new(&config))
+            );
+            return Ok(());
+        }
+        Command::Apply { dry_run } => {
+            // Moving needs the windows the running nest tracks
+            let command = if *dry_run { "apply dry-run" } else { "apply" };
+            println!("{}", ipc::send(APP_NAME, command).await?);
+            return Ok(());
+        }
+        Command::Confirm(class) => {
+            let command = match class {
+                Some(val) => format!("confirm {val}"),
+                None => "confirm".to_string(),
+            };
+            println!("{}", ipc::send(APP_NAME, &command).await?);
+            return Ok(());
+        }
+        Command::ResetFloat(class) => {
+            // A running nest would overwrite the storage file, so it is asked first
+            if let Ok(response) = ipc::send(APP_NAME, &format!("reset-float {class}")).await {
+                println!("{response}");
+                return Ok(());
+            }
+            let config = load_config(&args)?;
+            let class = Grouping::new(&config.group).class(class).into_owned();
+            let mut storage = open_storage(&args)?;
+            let mut programs = storage.read()?;
+            if !commands::reset_float(&mut programs, &class) {
+                println!("No program of type {class}");
+                return Ok(());
+            }
+            storage.write(&programs)?;
+            println!("ok");
+            return Ok(());
+        }
+        Command::Bench(iterations) => {
+            let config = load_config(&args)?;
+            let programs = open_storage(&args)?.read()?;
+            print!(
+                "{}",
+                commands::bench(&programs, &Scoring::new(&config), *iterations)
+            );
+            return Ok(());
+        }
+        Command::Overrides => {
+            let response = ipc::send(APP_NAME, "overrides").await?;
+            match serde_json::from_str::<SessionOverrides>(&response) {
+                Ok(overrides) => print!("{}", commands::overrides(&overrides)),
+                Err(_) => println!("{response}"),
+            }
+            return Ok(());
+        }
+        Command::ClearOverrides => {
+            println!("{}", ipc::send(APP_NAME, "clear-overrides").await?);
+            return Ok(());
+        }
+        Command::Status { json } => {
+            // Without a running nest there is nothing to report, but status bars still want a line
+            let status = match ipc::send(APP_NAME, "status").await {
+                Ok(val) => serde_json::from_str(&val).unwrap_or_default(),
+                Err(_) => Status::default(),
+            };
+            if *json {
+                println!("{}", serde_json::to_string(&status).unwrap_or_default());
+            } else {
+                print!("{}", commands::status(&status));
+            }
+            return Ok(());
+        }
+        Command::Compact => {
+            // A running nest would overwrite the storage file, so it is asked first
+            if let Ok(response) = ipc::send(APP_NAME, "compact").await {
+                println!("{response}");
+                return Ok(());
+            }
+            let config = load_config(&args)?;
+            let compaction = Compaction::new(&config);
+            let mut storage = open_storage(&args)?;
+            let mut programs = storage.read()?;
+            let mut report = CompactReport::default();
+            let now = chrono::Utc::now().timestamp();
+            programs.retain_mut(|program| {
+                let keep = compaction.apply(program, &mut report, now);
+                if !keep {
+                    report.programs += 1;
+                }
+                keep
+            });
+            storage.write(&programs)?;
+            println!("Compacted storage, {report}");
+            return Ok(());
+        }
+        Command::DaemonStatus => {
+            let liveness = pid::liveness(APP_NAME)?;
+            let status = match liveness {
+                pid::Liveness::Running(_) => match ipc::send(APP_NAME, "status").await {
+                    Ok(val) => serde_json::from_str(&val).ok(),
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+            print!("{}", commands::daemon_status(liveness, status.as_ref()));
+            // Scripts only need the exit code to know whether nest is up
+            if !matches!(liveness, pid::Liveness::Running(_)) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Command::ReplayLastCrash => {
+            let events = recorder::read_events(APP_NAME, EVENTS_FILE_NAME)?;
+            print!("{}", commands::replay(&events));
+            return Ok(());
+        }
+    }
+
+    let config = match load_config(&args) {
         Ok(val) => val,
         Err(err) => {
-            notify::call_async(
-                Icon::Error,
-                Duration::from_secs(20),
-                Color::new(225, 0, 0, 225),
-                format!("[nest] Failed to read config: {}", err),
-            )
-            .await?;
+            Notifier::new(&NotificationConfig::default())
+                .error(format!("[nest] Failed to read config: {}", err))
+                .await?;
             return Err(Error::Config(err));
         }
     };
@@ -69,216 +281,727 @@ async fn main() -> Result<(), Error> {
         }
     };
 
-    setup_logger(APP_NAME, LOG_FILE_NAME, log_level)?;
+    let notifier = Notifier::new(&config.notifications);
+    // Read only runs log to stdout alone
+    let log_dir = (!args.read_only).then_some(config.log.dir.as_str());
+    if let Err(err) = setup_logger(
+        APP_NAME,
+        LOG_FILE_NAME,
+        log_level,
+        &config.log.timestamp_format,
+        log_dir,
+    ) {
+        notifier
+            .error(format!("[nest] Failed to set up logging: {err}"))
+            .await?;
+        return Err(Error::Logger(err));
+    }
 
-    let mut storage = Storage::new(APP_NAME, STORAGE_FILE_NAME)?;
-    let storage_value = match storage.read() {
+    for kind in config.unknown_events() {
+        warn!(
+            "Unknown event {kind} in events, expected one of {}",
+            config::EVENTS.join(", ")
+        );
+    }
+    for (old, new) in config.deprecated_keys() {
+        warn!("{old} is deprecated, use {new} instead");
+    }
+
+    let telemetry = Telemetry::new(APP_NAME, &config.telemetry)?;
+    let recorder = Recorder::new(APP_NAME, EVENTS_FILE_NAME, &config.debug)?;
+    let schedule = Schedule::new(&config.schedule)?;
+    if let Err(err) = recorder.record("start", &[]).await {
+        error!("Failed to record event: {err}");
+    }
+
+    if args.read_only {
+        info!("Running read only, nothing will be saved");
+    }
+    let mut storage = match open_storage(&args) {
         Ok(val) => val,
         Err(err) => {
-            notify::call_async(
-                Icon::Error,
-                Duration::from_secs(20),
-                Color::new(225, 0, 0, 225),
-                format!("[nest] Failed to read storage file: {}", err),
-            )
-            .await?;
+            notifier
+                .error(format!("[nest] Failed to open storage file: {err}"))
+                .await?;
             return Err(Error::Storage(err));
         }
     };
+    storage.set_fsync(config.storage.fsync);
+    storage.set_compress(config.storage.compress);
+    let mut storage_value = match storage.read() {
+        Ok(val) => val,
+        Err(err) => {
+            notifier
+                .error(format!("[nest] Failed to read storage file: {}", err))
+                .await?;
+            return Err(Error::Storage(err));
+        }
+    };
+    let seeded = match seed::read_seed(APP_NAME, SEED_FILE_NAME, &storage_value) {
+        Ok(val) => val,
+        Err(err) => {
+            notifier
+                .error(format!("[nest] Failed to read seed file: {err}"))
+                .await?;
+            return Err(Error::Seed(err));
+        }
+    };
+    if !seeded.is_empty() {
+        info!("Seeded {} programs from {SEED_FILE_NAME}", seeded.len());
+    }
+    let seeded_any = !seeded.is_empty();
+    storage_value.extend(seeded);
+    if config.strict_classes {
+        check_classes(&config, &storage_value).await;
+    }
     let state = State::load(storage_value, config.clone()).await;
+    // Seeded programs are saved like learned ones, after that storage has an entry for them
+    if seeded_any {
+        state.changed.store(true, atomic::Ordering::Relaxed);
+    }
+    if storage.outdated() {
+        info!("Storage was written by an older version, it will be upgraded on the next save");
+        state.changed.store(true, atomic::Ordering::Relaxed);
+    }
+    if storage.converting() {
+        info!("Storage compression changed, it will be converted on the next save");
+        state.changed.store(true, atomic::Ordering::Relaxed);
+    }
+    if config.storage.compact_on_startup {
+        let report = state.compact().await;
+        if !report.is_empty() {
+            info!("Compacted storage, {report}");
+        }
+    }
 
-    let mut event_listener = AsyncEventListener::new();
+    let window_file = WindowFile::new(APP_NAME, WINDOWS_FILE_NAME, config.restore.persist_windows)?;
+    match window_file.read() {
+        Ok(windows) if !windows.is_empty() => match state.restore_windows(windows).await {
+            Ok(restored) => info!("Restored {restored} windows from the last run"),
+            Err(err) => error!("Failed to restore windows from the last run: {err}"),
+        },
+        Ok(_) => (),
+        Err(err) => error!("Failed to read windows from the last run: {err}"),
+    }
+
+    let context = AppContext {
+        state,
+        config: Arc::new(config),
+        storage: Arc::new(Mutex::new(storage)),
+        notifier,
+        telemetry,
+        recorder,
+        schedule,
+        window_file,
+    };
 
-    let workspace_state = state.clone();
-    event_listener.add_workspace_changed_handler(move |event| {
-        let state = workspace_state.clone();
-        Box::pin(async move {
-            state.workspace_changed(event.id);
-        })
+    match Ipc::bind(APP_NAME, context.state.clone(), context.storage.clone()) {
+        Ok(ipc) => {
+            tokio::spawn(ipc.listen());
+        }
+        Err(err) => error!("Failed to open command socket: {err}"),
+    }
+    if let Err(err) = pid::write_pid(APP_NAME) {
+        error!("Failed to write pid file: {err}");
+    }
+
+    let ctx = context.clone();
+    tokio::spawn(async move {
+        sleep(SETTLE_DURATION).await;
+        ctx.state.settle();
     });
 
-    let add_state = state.clone();
-    event_listener.add_window_opened_handler(move |event| {
-        let state = add_state.clone();
-        Box::pin(async move {
-            state
-                .add_window(event.window_class.clone(), event.window_address.clone())
-                .await;
-            let program = match state.get_program(event.window_class).await {
-                Some(val) => val,
-                None => return,
-            };
+    // Only nest's own placements take focus away, without them there is nothing to give back
+    if context.config.workspace.enabled && context.config.workspace.restore_focus_after_startup {
+        // State::load asked hyprland for the active workspace, before anything was placed
+        let ctx = context.clone();
+        let origin = ctx.state.current_workspace();
+        tokio::spawn(async move {
+            sleep(STARTUP_PLACEMENT).await;
+            // Wait for the placements to go quiet so focus is not taken away again
+            while ctx
+                .state
+                .since_last_move()
+                .is_some_and(|val| val < SETTLE_DURATION.as_secs() as i64)
+            {
+                sleep(SETTLE_DURATION).await;
+            }
+            ctx.state.restore_focus(origin).await;
+        });
+    }
 
-            let workspace_id = match calculate_workspace(program.workspaces, config.workspace.tau) {
-                Some(val) => val,
-                None => {
-                    debug!("Could not calculate where to move program");
-                    state.current_workspace()
+    let mut event_listener = AsyncEventListener::new();
+
+    // Placing windows and restoring on close are the only users of the active workspace
+    if (context.config.workspace.enabled || context.config.restore.enabled)
+        && context.config.reacts_to("workspace")
+    {
+        let ctx = context.clone();
+        event_listener.add_workspace_changed_handler(move |event| {
+            let ctx = ctx.clone();
+            Box::pin(async move {
+                ctx.state.workspace_changed(WorkspaceId(event.id)).await;
+                if ctx.config.workspace.enabled {
+                    ctx.state.prefetch(WorkspaceId(event.id)).await;
                 }
-            };
+            })
+        });
+    }
 
-            match state.move_window(&event.window_address, workspace_id).await {
-                Ok(moved) => {
-                    if moved {
-                        info!("Moved window {} to {}", event.window_address, workspace_id)
-                    } else {
-                        info!(
-                            "Tried to move window {} to {} but a move could not be completed",
-                            event.window_address, workspace_id
-                        )
-                    }
+    if context.config.reacts_to("open") {
+        let ctx = context.clone();
+        event_listener.add_window_opened_handler(move |event| {
+            let ctx = ctx.clone();
+            Box::pin(async move {
+                if let Err(err) = ctx
+                    .recorder
+                    .record(
+                        "open",
+                        &[
+                            &fmt_address(&event.window_address),
+                            &event.window_class,
+                            &event.workspace_name,
+                        ],
+                    )
+                    .await
+                {
+                    error!("Failed to record event: {err}");
                 }
-                Err(err) => error!("Failed to dispatch window move: {err}"),
-            };
+                let outcome = ctx
+                    .state
+                    .handle_open(
+                        &event.window_class,
+                        &event.window_address,
+                        &event.workspace_name,
+                        ctx.schedule.is_quiet(),
+                    )
+                    .await;
 
-            let window = match program.floating_window {
-                Some(val) => val,
-                None => return,
-            };
+                if let Some((workspace_id, score)) = outcome.prediction
+                    && let Err(err) = ctx
+                        .telemetry
+                        .record_decision(
+                            &event.window_address,
+                            &event.window_class,
+                            workspace_id,
+                            score,
+                        )
+                        .await
+                {
+                    error!("Failed to write telemetry: {err}");
+                }
 
-            match state
-                .move_float_window(&event.window_address, window.at, window.size)
-                .await
-            {
-                Ok(moved) => {
-                    if moved {
-                        info!(
-                            "Moved floating window {} to {:?} and resized to {:?}",
-                            event.window_address, window.at, window.size
+                if matches!(outcome.placement, Placement::NoPrediction)
+                    && ctx.config.workspace.on_no_prediction == NoPrediction::Notify
+                    && let Err(err) = ctx
+                        .notifier
+                        .send(
+                            Icon::Info,
+                            Color::new(0, 150, 225, 225),
+                            format!(
+                                "[nest] No placement learned for {} yet, move it where it belongs",
+                                event.window_class
+                            ),
                         )
-                    } else {
-                        info!(
-                            "Tried to moved floating window {} to {:?} and resized to {:?}",
-                            event.window_address, window.at, window.size
+                        .await
+                {
+                    error!("Failed to send notification: {err}");
+                }
+
+                if let Placement::Asked(workspace_id) = outcome.placement
+                    && let Err(err) = ctx
+                        .notifier
+                        .send(
+                            Icon::Info,
+                            Color::new(0, 150, 225, 225),
+                            format!(
+                                "[nest] Move {} to workspace {workspace_id}? Run nest confirm within {}s",
+                                event.window_class, ctx.config.workspace.ask_timeout_secs
+                            ),
                         )
+                        .await
+                {
+                    error!("Failed to send notification: {err}");
+                }
+
+                log_outcome(&event.window_address, &outcome);
+                if let Err(err) = ctx.window_file.persist(&ctx.state).await {
+                    error!("Failed to write windows: {err}");
+                }
+            })
+        });
+    }
+
+    if context.config.reacts_to("close") {
+        let ctx = context.clone();
+        event_listener.add_window_closed_handler(move |address| {
+            let ctx = ctx.clone();
+            Box::pin(async move {
+                if let Err(err) = ctx
+                    .recorder
+                    .record("close", &[&fmt_address(&address)])
+                    .await
+                {
+                    error!("Failed to record event: {err}");
+                }
+                ctx.telemetry.forget(&address).await;
+                let name = fmt_address(&address);
+                match ctx.state.remove_window(address).await {
+                    Ok(RemoveResult::Restored) => {
+                        debug!("Restored the workspace window {name} was opened from")
                     }
+                    Ok(RemoveResult::NotRestored | RemoveResult::NotTracked) => (),
+                    Err(err) => error!(
+                        "Something went wrong trying to restore state after closing a window {err}"
+                    ),
                 }
-                Err(err) => error!("Failed to dispatch window move: {err}"),
-            };
-        })
-    });
+                if let Err(err) = ctx.window_file.persist(&ctx.state).await {
+                    error!("Failed to write windows: {err}");
+                }
+            })
+        });
+    }
+
+    if context.config.notifications.milestones {
+        context
+            .state
+            .seed_confident(
+                context.config.notifications.confidence_threshold,
+                MILESTONE_MIN_SCORE,
+            )
+            .await;
+    }
+    if context.config.workspace.enabled && context.config.reacts_to("move") {
+        let ctx = context.clone();
+        event_listener.add_window_moved_handler(move |event| {
+            let ctx = ctx.clone();
+            Box::pin(async move {
+                if let Err(err) = ctx
+                    .recorder
+                    .record(
+                        "move",
+                        &[
+                            &fmt_address(&event.window_address),
+                            &event.workspace_id.to_string(),
+                        ],
+                    )
+                    .await
+                {
+                    error!("Failed to record event: {err}");
+                }
+                match ctx
+                    .state
+                    .window_moved(
+                        event.window_address.clone(),
+                        WorkspaceId(event.workspace_id),
+                        &event.workspace_name,
+                    )
+                    .await
+                {
+                    Ok(moved) => {
+                        // A move away from the prediction counts whether it was learned or not
+                        if moved.by_user()
+                            && let Err(err) = ctx.telemetry.record_move(&event.window_address).await
+                        {
+                            error!("Failed to write telemetry: {err}");
+                        }
+                        if moved == Moved::Learned
+                            && ctx.config.notifications.milestones
+                            && let Some((class, workspace_id)) = ctx
+                                .state
+                                .newly_confident(
+                                    &event.window_address,
+                                    ctx.config.notifications.confidence_threshold,
+                                    MILESTONE_MIN_SCORE,
+                                )
+                                .await
+                            && let Err(err) = ctx
+                                .notifier
+                                .send(
+                                    Icon::Ok,
+                                    Color::new(0, 225, 0, 225),
+                                    format!("[nest] {class} belongs on workspace {workspace_id}"),
+                                )
+                                .await
+                        {
+                            error!("Failed to send notification: {err}");
+                        }
+                    }
+                    Err(err) => error!("Failed react to window move: {err}"),
+                }
+            })
+        });
+    }
+
+    if context.config.workspace.enabled
+        && (context.config.workspace.learn_from_focus
+            || context.config.workspace.learn_user_moves_only)
+    {
+        let ctx = context.clone();
+        event_listener.add_active_window_changed_handler(move |event| {
+            let ctx = ctx.clone();
+            Box::pin(async move {
+                let event = match event {
+                    Some(val) => val,
+                    None => return,
+                };
+                if ctx.state.tracks_focus() {
+                    ctx.state.focus_changed(event.address.clone()).await;
+                }
+                if ctx.config.workspace.learn_from_focus
+                    && let Err(err) = ctx.state.window_focused(event.address).await
+                {
+                    debug!("Failed to learn from focus: {err}");
+                }
+            })
+        });
+    }
 
-    let remove_state = state.clone();
-    event_listener.add_window_closed_handler(move |address| {
-        let state = remove_state.clone();
-        Box::pin(async move {
-            match state.remove_window(address).await {
-                Ok(_) => (),
-                Err(err) => error!(
-                    "Something went wrong trying to restore state after closing a window {err}"
-                ),
+    if context.config.workspace.enabled
+        && context.config.workspace.on_monitor_change != MonitorChange::Nothing
+    {
+        let debounce = Debounce::default();
+        let ctx = context.clone();
+        let added = debounce.clone();
+        event_listener.add_monitor_added_handler(move |_| {
+            tokio::spawn(monitor_changed(ctx.clone(), added.clone()));
+            Box::pin(async {})
+        });
+        let ctx = context.clone();
+        event_listener.add_monitor_removed_handler(move |_| {
+            tokio::spawn(monitor_changed(ctx.clone(), debounce.clone()));
+            Box::pin(async {})
+        });
+    }
+
+    if context.config.floating.enabled {
+        tokio::spawn(poll_floating(context.clone()));
+    }
+
+    if context.config.split.enabled {
+        let ctx = context.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(ctx.config.split.frequency)).await;
+                let clients = match Hyprland.clients().await {
+                    Ok(val) => val,
+                    Err(err) => {
+                        error!("Failed to fetch clients: {err}");
+                        continue;
+                    }
+                };
+                let monitors = match Hyprland.monitors().await {
+                    Ok(val) => val,
+                    Err(err) => {
+                        error!("Failed to fetch monitors: {err}");
+                        continue;
+                    }
+                };
+                for client in &clients {
+                    if let Some(split) = Split::observe(client, &clients, &monitors)
+                        && ctx
+                            .state
+                            .learn_split(&ctx.state.group(&client.class), split)
+                            .await
+                    {
+                        debug!("Learned split {split:?} for {}", client.class);
+                    }
+                }
             }
-        })
-    });
+        });
+    }
 
-    let move_state = state.clone();
-    event_listener.add_window_moved_handler(move |event| {
-        let state = move_state.clone();
-        Box::pin(async move {
-            match state
-                .window_moved(event.window_address, event.workspace_id)
-                .await
-            {
-                Ok(_) => (),
-                Err(err) => error!("Failed react to window move: {err}"),
+    if !args.read_only {
+        let ctx = context.clone();
+        tokio::spawn(async move {
+            loop {
+                // Changes made right after a save are coalesced into the next one
+                let waiting = ctx
+                    .state
+                    .since_last_save()
+                    .is_some_and(|val| val < ctx.config.storage.min_save_interval_secs);
+                if waiting {
+                    debug!("Saved recently, waiting before saving again");
+                } else if ctx.state.changed.load(atomic::Ordering::Relaxed) {
+                    match ctx.storage.lock().await.persist(&ctx.state).await {
+                        Ok(()) => info!("State saved to storage"),
+                        Err(err) => error!("Failed to write changes: {err}"),
+                    }
+                } else {
+                    debug!("No changes found in the state");
+                }
+
+                sleep(Duration::from_secs(ctx.config.save_frequency)).await;
             }
-        })
-    });
+        });
+    }
 
-    let window_state = state.clone();
-    tokio::spawn(async move {
-        let state = window_state.clone();
-        loop {
-            let clients = match Clients::get_async().await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("Failed to fetch clients: {err}");
-                    continue;
+    if context.config.reconcile_frequency > 0 {
+        let ctx = context.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(ctx.config.reconcile_frequency)).await;
+                match ctx.state.reconcile().await {
+                    Ok(dropped) if dropped.is_empty() => (),
+                    Ok(dropped) => {
+                        for address in &dropped {
+                            ctx.telemetry.forget(address).await;
+                        }
+                        info!("Dropped {} windows that were already closed", dropped.len())
+                    }
+                    Err(err) => error!("Failed to reconcile windows: {err}"),
                 }
-            };
-            let programs = state.get_mapped_programs().await;
-            for client in clients {
-                let program = match programs.get(&client.class) {
-                    Some(val) => val,
-                    None => continue,
-                };
+            }
+        });
+    }
+
+    let state = &context.state;
+    state.connected.store(true, atomic::Ordering::Relaxed);
+    let result = tokio::select! {
+        result = event_listener.start_listener_async() => result,
+        () = shutdown() => {
+            info!("Shutting down");
+            Ok(())
+        }
+    };
+    state.connected.store(false, atomic::Ordering::Relaxed);
+    if let Err(err) = pid::remove_pid(APP_NAME) {
+        error!("Failed to remove pid file: {err}");
+    }
+    result?;
+    Ok(())
+}
 
-                if client.floating {
+// Resolves once nest is asked to stop with SIGINT or SIGTERM
+async fn shutdown() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Failed to listen for SIGTERM: {err}");
+            return std::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+// Learns the geometry of floating windows, forever
+async fn poll_floating(ctx: AppContext) {
+    let state = &ctx.state;
+    let position_mode = ctx.config.floating.position_mode;
+    let mut sightings = Sightings::default();
+    let frequency = Duration::from_secs(ctx.config.floating.frequency);
+    loop {
+        let clients = match Hyprland.clients().await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to fetch clients: {err}");
+                sleep(frequency).await;
+                continue;
+            }
+        };
+        let monitors = match Hyprland.monitors().await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to fetch monitors: {err}");
+                Vec::new()
+            }
+        };
+        let programs = state.get_mapped_programs().await;
+        for mut client in clients {
+            client.class = state.group(&client.class);
+            if !state.is_program_enabled(&client.class).await {
+                continue;
+            }
+            let program = match programs.get(&client.class) {
+                Some(val) => val,
+                None => {
+                    if !client.floating
+                        || !state.manages_floating(&client.class)
+                        || is_maximized(&client, &monitors)
+                    {
+                        continue;
+                    }
+                    if !sightings.confirmed(&client.address) {
+                        continue;
+                    }
+                    state
+                        .add_window(client.class.clone(), client.address.clone())
+                        .await;
                     match state
                         .add_floating_window(
                             &client.class,
-                            FloatingWindow {
-                                at: client.at,
-                                size: client.size,
-                            },
+                            FloatingWindow::new(
+                                client.at,
+                                client.size,
+                                position_mode,
+                                client_monitor(&client, &monitors),
+                            ),
                         )
                         .await
                     {
-                        Ok(()) => debug!("Tracking floating window of type {}", client.class),
+                        Ok(()) => debug!(
+                            "Tracking untracked floating window of type {}",
+                            client.class
+                        ),
                         Err(err) => error!("Failed to add floating window: {err}"),
                     };
-                } else if program.floating_window.is_some() {
-                    match state.remove_floating_window(&client.class).await {
-                        Ok(()) => {
-                            debug!("Stopped tracking floating window of type {}", client.class)
-                        }
-                        Err(err) => error!("Failed to remove floating window: {err}"),
-                    }
+                    continue;
                 }
-            }
-            sleep(Duration::from_secs(config.floating.frequency)).await;
-        }
-    });
+            };
 
-    let runtime_state = state.clone();
-    tokio::spawn(async move {
-        let state = runtime_state.clone();
-        loop {
-            if state.changed.load(atomic::Ordering::Relaxed) {
-                let programs = state.get_programs().await;
-                match storage.write(&programs) {
+            if program.float_moved && state.take_float_moved(&client.class).await {
+                debug!(
+                    "Floating window of type {} was just positioned, ignoring geometry",
+                    client.class
+                );
+            } else if client.floating && is_maximized(&client, &monitors) {
+                debug!(
+                    "Floating window of type {} covers its monitor, ignoring geometry",
+                    client.class
+                );
+            } else if client.floating {
+                match state
+                    .add_floating_window(
+                        &client.class,
+                        FloatingWindow::new(
+                            client.at,
+                            client.size,
+                            position_mode,
+                            client_monitor(&client, &monitors),
+                        ),
+                    )
+                    .await
+                {
+                    Ok(()) => debug!("Tracking floating window of type {}", client.class),
+                    Err(err) => error!("Failed to add floating window: {err}"),
+                };
+            } else if program.floating_window.is_some() {
+                match state.remove_floating_window(&client.class).await {
                     Ok(()) => {
-                        info!("State saved to storage");
-                        state.changed.store(false, atomic::Ordering::Relaxed)
+                        debug!("Stopped tracking floating window of type {}", client.class)
                     }
-                    Err(err) => error!("Failed to write changes: {err}"),
+                    Err(err) => error!("Failed to remove floating window: {err}"),
                 }
-            } else {
-                debug!("No changes found in the state");
             }
+        }
+        sightings.next_poll();
+        sleep(frequency).await;
+    }
+}
 
-            sleep(Duration::from_secs(config.save_frequency)).await;
+// Warns about filter entries that match no open window and no program nest has seen,
+// those are most likely typos
+async fn check_classes(config: &Config, programs: &[Program]) {
+    let mut classes: Vec<String> = programs.iter().map(|val| val.class.clone()).collect();
+    match Hyprland.clients().await {
+        Ok(clients) => classes.extend(clients.into_iter().map(|client| client.class)),
+        Err(err) => error!("Failed to fetch clients: {err}"),
+    }
+    for (section, filter) in [
+        ("workspace", &config.workspace.filter),
+        ("floating", &config.floating.filter),
+        ("restore", &config.restore.filter),
+    ] {
+        for entry in unmatched(&filter.programs, &classes) {
+            warn!("[{section}.filter] {entry} does not match any known class");
         }
-    });
+    }
+}
 
-    event_listener.start_listener_async().await?;
-    Ok(())
+// The open clients with their classes grouped like the daemon learns them
+async fn grouped_clients(config: &Config) -> Result<Vec<ClientInfo>, Error> {
+    let grouping = Grouping::new(&config.group);
+    let mut clients = Hyprland.clients().await?;
+    for client in &mut clients {
+        client.class = grouping.class(&client.class).into_owned();
+    }
+    Ok(clients)
 }
 
-fn calculate_workspace(workspaces: Vec<Workspace>, tau: f64) -> Option<i32> {
-    let mut score_map: HashMap<i32, f64> = HashMap::new();
-    let now = Utc::now().timestamp();
-    for workspace in workspaces {
-        // Aging function score = e^(-age / τ)
-        let age = (now - workspace.timestamp) as f64;
-        let score = f64::powf(f64::consts::E, -age / tau);
-        debug!("Position got a score of {score}");
-        match score_map.get(&workspace.workspace_id) {
-            Some(val) => score_map.insert(workspace.workspace_id, *val + score),
-            None => score_map.insert(workspace.workspace_id, score),
-        };
+// Docking reports every monitor on its own, so the action only runs once the changes settle
+async fn monitor_changed(ctx: AppContext, debounce: Debounce) {
+    let wait = Duration::from_millis(ctx.config.workspace.monitor_change_debounce_ms);
+    if !debounce.settled(wait).await {
+        return;
+    }
+    match ctx
+        .state
+        .monitors_changed(
+            ctx.config.workspace.on_monitor_change,
+            ctx.schedule.is_quiet(),
+        )
+        .await
+    {
+        Ok(Some(moved)) => info!("Monitors changed, moved {moved} windows"),
+        Ok(None) => debug!("Monitors changed, nothing to do"),
+        Err(err) => error!("Failed to move windows after monitors changed: {err}"),
     }
+}
 
-    score_map
-        .iter()
-        .max_by(|a, b| {
-            if a.1 > b.1 {
-                cmp::Ordering::Greater
-            } else if a.1 < b.1 {
-                cmp::Ordering::Less
-            } else {
-                cmp::Ordering::Equal
+// Read only runs never create the config dir or the storage file
+fn open_storage(args: &Args) -> Result<Storage, storage::Error> {
+    if args.read_only {
+        Storage::read_only(APP_NAME, STORAGE_FILE_NAME)
+    } else {
+        Storage::new(APP_NAME, STORAGE_FILE_NAME)
+    }
+}
+
+// Resolves the config the same way for the daemon and every command
+fn load_config(args: &Args) -> Result<Config, config::Error> {
+    let mut config = Config::new(APP_NAME, CONFIG_FILE_NAME, args.read_only)?;
+    if let Some(level) = args.log_level {
+        config.log_level = level.as_str().to_string();
+    }
+    // Everything else nest writes to disk is turned off as well
+    if args.read_only {
+        config.telemetry.enabled = false;
+        config.debug.record_events = false;
+        config.restore.persist_windows = false;
+    }
+    Ok(config)
+}
+
+// Reports what happened to a newly opened window
+fn log_outcome(address: &Address, outcome: &OpenOutcome) {
+    let address = fmt_address(address);
+    match &outcome.placement {
+        Placement::Untracked => return,
+        Placement::Skipped => debug!("Not moving window {address}"),
+        Placement::NoPrediction => debug!("Could not calculate where to move program"),
+        Placement::AlreadyThere(workspace_id) => {
+            debug!("Window {address} already opened on {workspace_id}, not moving")
+        }
+        Placement::Asked(workspace_id) => {
+            info!("Asked before moving window {address} to {workspace_id}")
+        }
+        Placement::Moved(workspace_id) => match (outcome.prediction, outcome.runner_up) {
+            (Some((_, score)), Some((next, next_score))) => info!(
+                "Moved window {address} to {workspace_id} (score {score:.2}, next {next} at {next_score:.2})"
+            ),
+            (Some((_, score)), None) => {
+                info!("Moved window {address} to {workspace_id} (score {score:.2})")
             }
-        })
-        .map(|val| *val.0)
+            (None, _) => info!("Moved window {address} to {workspace_id}"),
+        },
+        Placement::Failed(workspace_id) => info!(
+            "Tried to move window {address} to {workspace_id} but a move could not be completed"
+        ),
+        Placement::Error(err) => error!("Failed to dispatch window move: {err}"),
+    }
+    match &outcome.floating {
+        FloatPlacement::Skipped | FloatPlacement::NoGeometry => (),
+        FloatPlacement::Moved(window) => info!(
+            "Moved floating window {address} to {:?} and resized to {:?}",
+            window.at, window.size
+        ),
+        FloatPlacement::Failed(window) => info!(
+            "Tried to moved floating window {address} to {:?} and resized to {:?}",
+            window.at, window.size
+        ),
+        FloatPlacement::Error(err) => error!("Failed to dispatch window move: {err}"),
+    }
 }
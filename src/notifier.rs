@@ -0,0 +1,164 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use hyprland::ctl::{
+    Color,
+    notify::{self, Icon},
+};
+use log::{error, warn};
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::config::NotificationConfig;
+
+const DURATION: Duration = Duration::from_secs(20);
+
+// Token bucket refilled continuously, `capacity` tokens per minute
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    last: DateTime<Utc>,
+    dropped: usize,
+}
+
+impl Bucket {
+    fn take(&mut self, now: DateTime<Utc>) -> bool {
+        let elapsed = (now - self.last).num_milliseconds().max(0) as f64 / 60_000.0;
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Sends desktop notifications, when hyprland misbehaves the excess is folded into a summary
+#[derive(Clone)]
+pub struct Notifier {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationConfig) -> Self {
+        if config.max_per_minute == 0 {
+            return Self { bucket: None };
+        }
+        let capacity = config.max_per_minute as f64;
+        Self {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                capacity,
+                tokens: capacity,
+                last: Utc::now(),
+                dropped: 0,
+            }))),
+        }
+    }
+
+    pub async fn error(&self, message: String) -> hyprland::Result<()> {
+        self.send(Icon::Error, Color::new(225, 0, 0, 225), message)
+            .await
+    }
+
+    pub async fn send(&self, icon: Icon, color: Color, message: String) -> hyprland::Result<()> {
+        let bucket = match &self.bucket {
+            Some(val) => val,
+            None => return notify::call_async(icon, DURATION, color, message).await,
+        };
+        let message = {
+            let mut bucket = bucket.lock().await;
+            if !bucket.take(Utc::now()) {
+                bucket.dropped += 1;
+                warn!("Too many notifications, not showing: {message}");
+                if bucket.dropped == 1 {
+                    self.flush_later(60.0 / bucket.capacity);
+                }
+                return Ok(());
+            }
+            match std::mem::take(&mut bucket.dropped) {
+                0 => message,
+                dropped => format!("{message} (+{dropped} more)"),
+            }
+        };
+        notify::call_async(icon, DURATION, color, message).await
+    }
+
+    // Once a token is back the suppressed notifications are summarised, unless a newer one took them along
+    fn flush_later(&self, secs: f64) {
+        let bucket = match &self.bucket {
+            Some(val) => val.clone(),
+            None => return,
+        };
+        tokio::spawn(async move {
+            let dropped = loop {
+                sleep(Duration::from_secs_f64(secs)).await;
+                let mut bucket = bucket.lock().await;
+                if bucket.dropped == 0 {
+                    return;
+                }
+                if bucket.take(Utc::now()) {
+                    break std::mem::take(&mut bucket.dropped);
+                }
+            };
+            if let Err(err) = notify::call_async(
+                Icon::Warning,
+                DURATION,
+                Color::new(225, 225, 0, 225),
+                format!("[nest] (+{dropped} more) notifications were suppressed"),
+            )
+            .await
+            {
+                error!("Failed to send notification: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(capacity: f64, now: DateTime<Utc>) -> Bucket {
+        Bucket {
+            capacity,
+            tokens: capacity,
+            last: now,
+            dropped: 0,
+        }
+    }
+
+    #[test]
+    fn takes_up_to_capacity() {
+        let now = Utc::now();
+        let mut bucket = bucket(3.0, now);
+        assert!(bucket.take(now));
+        assert!(bucket.take(now));
+        assert!(bucket.take(now));
+        assert!(!bucket.take(now));
+    }
+
+    #[test]
+    fn refills_over_a_minute() {
+        let now = Utc::now();
+        let mut bucket = bucket(2.0, now);
+        assert!(bucket.take(now));
+        assert!(bucket.take(now));
+        // Two per minute is one every thirty seconds
+        assert!(!bucket.take(now + chrono::Duration::seconds(20)));
+        assert!(bucket.take(now + chrono::Duration::seconds(31)));
+        // Never more than the capacity, however long it was idle
+        let later = now + chrono::Duration::hours(1);
+        assert!(bucket.take(later));
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+    }
+
+    #[test]
+    fn clock_going_back_adds_nothing() {
+        let now = Utc::now();
+        let mut bucket = bucket(1.0, now);
+        assert!(bucket.take(now));
+        assert!(!bucket.take(now - chrono::Duration::minutes(5)));
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs::{read_to_string, remove_file, write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find runtime directory")]
+    MissingRuntime,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("invalid pid file content {0}")]
+    InvalidPid(String),
+}
+
+// What the pid file says about the daemon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Liveness {
+    Running(u32),
+    // The file is left behind by a nest that did not shut down cleanly
+    Stale(u32),
+    NotRunning,
+}
+
+pub fn pid_path(app_name: &str) -> Result<PathBuf, Error> {
+    match dirs::runtime_dir() {
+        Some(val) => Ok(val.join(format!("{app_name}.pid"))),
+        None => Err(Error::MissingRuntime),
+    }
+}
+
+// Records the pid of this process, a file from an earlier run is overwritten
+pub fn write_pid(app_name: &str) -> Result<(), Error> {
+    write(pid_path(app_name)?, format!("{}\n", std::process::id()))?;
+    Ok(())
+}
+
+// Removes the pid file on shutdown, unless another nest has written its own since
+pub fn remove_pid(app_name: &str) -> Result<(), Error> {
+    let path = pid_path(app_name)?;
+    match read_to_string(&path) {
+        Ok(content) if content.trim() == std::process::id().to_string() => {
+            remove_file(path)?;
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn liveness(app_name: &str) -> Result<Liveness, Error> {
+    let path = pid_path(app_name)?;
+    if !path.exists() {
+        return Ok(Liveness::NotRunning);
+    }
+    let content = read_to_string(path)?;
+    let pid = match content.trim().parse() {
+        Ok(val) => val,
+        Err(_) => return Err(Error::InvalidPid(content.trim().to_string())),
+    };
+    Ok(check(Path::new("/proc"), pid, app_name))
+}
+
+// A process is alive while it has an entry in `proc`. Pids are reused, so the entry only counts
+// if it is still `name`
+fn check(proc: &Path, pid: u32, name: &str) -> Liveness {
+    match read_to_string(proc.join(pid.to_string()).join("comm")) {
+        Ok(comm) if comm.trim_end() == name => Liveness::Running(pid),
+        _ => Liveness::Stale(pid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_proc_entry() {
+        let proc = std::env::temp_dir().join(format!("nest-proc-{}", std::process::id()));
+        std::fs::create_dir_all(proc.join("42")).unwrap();
+        std::fs::write(proc.join("42").join("comm"), "nest\n").unwrap();
+        std::fs::create_dir_all(proc.join("44")).unwrap();
+        std::fs::write(proc.join("44").join("comm"), "firefox\n").unwrap();
+        assert_eq!(check(&proc, 42, "nest"), Liveness::Running(42));
+        assert_eq!(check(&proc, 43, "nest"), Liveness::Stale(43));
+        // The pid was handed to another program
+        assert_eq!(check(&proc, 44, "nest"), Liveness::Stale(44));
+        std::fs::remove_dir_all(proc).unwrap();
+    }
+
+    #[test]
+    fn this_process_is_running() {
+        let pid = std::process::id();
+        let comm = read_to_string(format!("/proc/{pid}/comm")).unwrap();
+        assert_eq!(
+            check(Path::new("/proc"), pid, comm.trim_end()),
+            Liveness::Running(pid)
+        );
+        assert_eq!(check(Path::new("/proc"), pid, "nest"), Liveness::Stale(pid));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, create_dir_all, read_to_string},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use chrono::Utc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::config::DebugConfig;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find config directory")]
+    MissingConfig,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+struct Ring {
+    path: PathBuf,
+    events: VecDeque<String>,
+    capacity: usize,
+}
+
+// Keeps the last few events in a small file so what led up to a crash can be looked at afterwards
+#[derive(Clone)]
+pub struct Recorder {
+    ring: Option<Arc<Mutex<Ring>>>,
+}
+
+pub fn events_path(app_name: &str, file_name: &str) -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(val) => Ok(val.join(app_name).join(file_name)),
+        None => Err(Error::MissingConfig),
+    }
+}
+
+// Events recorded by the last run, oldest first
+pub fn read_events(app_name: &str, file_name: &str) -> Result<Vec<String>, Error> {
+    let path = events_path(app_name, file_name)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+impl Recorder {
+    pub fn new(app_name: &str, file_name: &str, config: &DebugConfig) -> Result<Self, Error> {
+        if !config.record_events {
+            return Ok(Self { ring: None });
+        }
+        let path = events_path(app_name, file_name)?;
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        // Carry on from the previous run, the events before a crash are the interesting ones
+        let events = read_events(app_name, file_name)?.into();
+        Ok(Self {
+            ring: Some(Arc::new(Mutex::new(Ring {
+                path,
+                events,
+                capacity: config.event_buffer.max(1),
+            }))),
+        })
+    }
+
+    // Stores a single event, fields are joined by `;` like the storage file
+    pub async fn record(&self, kind: &str, fields: &[&str]) -> Result<(), Error> {
+        let ring = match &self.ring {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        let mut ring = ring.lock().await;
+        let mut line = format!("{};{kind}", Utc::now().timestamp());
+        for field in fields {
+            line.push(';');
+            line.push_str(field);
+        }
+        ring.events.push_back(line);
+        while ring.events.len() > ring.capacity {
+            ring.events.pop_front();
+        }
+
+        // The buffer is small, rewriting it keeps the file bounded
+        let mut file = File::create(&ring.path)?;
+        for event in &ring.events {
+            writeln!(file, "{event}")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
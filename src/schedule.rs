@@ -0,0 +1,95 @@
+use chrono::{Local, NaiveTime};
+use thiserror::Error;
+
+use crate::config::ScheduleConfig;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid quiet hours {0}, expected HH:MM-HH:MM")]
+    InvalidRange(String),
+}
+
+// Times of day during which nest keeps learning but does not move windows
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    quiet_hours: Vec<(NaiveTime, NaiveTime)>,
+}
+
+impl Schedule {
+    pub fn new(config: &ScheduleConfig) -> Result<Self, Error> {
+        let mut quiet_hours = Vec::new();
+        for range in &config.quiet_hours {
+            let (start, end) = match range.split_once('-') {
+                Some(val) => val,
+                None => return Err(Error::InvalidRange(range.clone())),
+            };
+            let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+            match (parse(start), parse(end)) {
+                (Ok(start), Ok(end)) => quiet_hours.push((start, end)),
+                _ => return Err(Error::InvalidRange(range.clone())),
+            }
+        }
+        Ok(Self { quiet_hours })
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.is_quiet_at(Local::now().time())
+    }
+
+    // Ranges are start inclusive and end exclusive, a start after the end wraps past midnight
+    pub fn is_quiet_at(&self, time: NaiveTime) -> bool {
+        self.quiet_hours.iter().any(|(start, end)| {
+            if start <= end {
+                *start <= time && time < *end
+            } else {
+                *start <= time || time < *end
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(ranges: &[&str]) -> Result<Schedule, Error> {
+        Schedule::new(&ScheduleConfig {
+            quiet_hours: ranges.iter().map(|val| val.to_string()).collect(),
+        })
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn range_within_a_day() {
+        let schedule = schedule(&["09:00-17:30"]).unwrap();
+        assert!(!schedule.is_quiet_at(at("08:59")));
+        assert!(schedule.is_quiet_at(at("09:00")));
+        assert!(schedule.is_quiet_at(at("17:29")));
+        assert!(!schedule.is_quiet_at(at("17:30")));
+    }
+
+    #[test]
+    fn range_past_midnight() {
+        let schedule = schedule(&["22:00 - 06:00"]).unwrap();
+        assert!(schedule.is_quiet_at(at("23:00")));
+        assert!(schedule.is_quiet_at(at("00:00")));
+        assert!(schedule.is_quiet_at(at("05:59")));
+        assert!(!schedule.is_quiet_at(at("06:00")));
+        assert!(!schedule.is_quiet_at(at("12:00")));
+    }
+
+    #[test]
+    fn no_ranges_is_never_quiet() {
+        assert!(!schedule(&[]).unwrap().is_quiet_at(at("03:00")));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert!(schedule(&["22:00"]).is_err());
+        assert!(schedule(&["25:00-06:00"]).is_err());
+        assert!(schedule(&["night-day"]).is_err());
+    }
+}
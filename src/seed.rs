@@ -0,0 +1,131 @@
+use std::{collections::BTreeMap, fs::read_to_string, path::PathBuf};
+
+use chrono::Utc;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    config::PositionMode,
+    state::{FloatingWindow, Program, Workspace, WorkspaceId},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find config directory")]
+    MissingConfig,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("could not parse seed file: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+// A starting layout for one class, e.g.
+// [firefox]
+// workspace = 2
+// floating = [100, 100, 800, 600]
+#[derive(Clone, Debug, Deserialize)]
+struct SeedEntry {
+    workspace: Option<i32>,
+    // Counts as this many moves, more makes the seed harder to unlearn
+    #[serde(default = "default_weight")]
+    weight: f64,
+    // x, y, width and height in pixels
+    floating: Option<(i16, i16, i16, i16)>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+pub fn seed_path(app_name: &str, file_name: &str) -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(val) => Ok(val.join(app_name).join(file_name)),
+        None => Err(Error::MissingConfig),
+    }
+}
+
+// Programs from the seed file for classes storage knows nothing about, the seed is history
+// like any other and is unlearned as usual
+pub fn read_seed(
+    app_name: &str,
+    file_name: &str,
+    programs: &[Program],
+) -> Result<Vec<Program>, Error> {
+    let path = seed_path(app_name, file_name)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    parse_seed(&read_to_string(path)?, programs, Utc::now().timestamp())
+}
+
+fn parse_seed(content: &str, programs: &[Program], now: i64) -> Result<Vec<Program>, Error> {
+    let entries: BTreeMap<String, SeedEntry> = toml::from_str(content)?;
+    Ok(entries
+        .into_iter()
+        .filter(|(class, _)| !programs.iter().any(|program| &program.class == class))
+        .map(|(class, entry)| Program {
+            class,
+            workspaces: entry
+                .workspace
+                .map(|id| Workspace {
+                    weight: entry.weight,
+                    ..Workspace::new(WorkspaceId(id), now)
+                })
+                .into_iter()
+                .collect(),
+            floating_window: entry.floating.map(|(x, y, width, height)| {
+                FloatingWindow::new((x, y), (width, height), PositionMode::Exact, None)
+            }),
+            properties: BTreeMap::new(),
+            split: None,
+            moved: false,
+            float_moved: false,
+        })
+        .filter(|program| !program.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const SEED: &str = "
+[firefox]
+workspace = 2
+weight = 3.0
+
+[pavucontrol]
+floating = [100, 100, 800, 600]
+
+[empty]
+";
+
+    #[test]
+    fn seeds_unknown_classes() {
+        let programs = parse_seed(SEED, &[], 100).unwrap();
+        let lines: Vec<String> = programs.iter().map(|val| val.to_string()).collect();
+        // Entries without anything to learn are left out
+        assert_eq!(
+            lines,
+            vec!["firefox:[2;100;3]&[]", "pavucontrol:[]&[100;100;800;600]"]
+        );
+    }
+
+    #[test]
+    fn stored_classes_win() {
+        let stored = vec![Program::from_str("firefox:[5;1]&[]").unwrap()];
+        let programs = parse_seed(SEED, &stored, 100).unwrap();
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].class, "pavucontrol");
+    }
+
+    #[test]
+    fn invalid_seed() {
+        assert!(matches!(
+            parse_seed("[firefox]\nworkspace = \"two\"\n", &[], 100),
+            Err(Error::Toml(_))
+        ));
+    }
+}
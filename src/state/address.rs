@@ -0,0 +1,10 @@
+use hyprland::shared::Address;
+
+// Event addresses come without the `0x` that `hyprctl clients` prints, logs always use the hyprctl form
+pub fn fmt_address(address: &Address) -> String {
+    let address = address.to_string();
+    match address.strip_prefix("0x") {
+        Some(_) => address,
+        None => format!("0x{address}"),
+    }
+}
@@ -0,0 +1,576 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64},
+    },
+};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{ClassOverride, Config, FilterMode, MonitorRestore, NoPrediction, OnZombie, TieBreak},
+    state::{
+        Clock, Compaction, Fit, Focus, Grouping, Hypr, Hyprland, Matcher, Metrics, Scoring, State,
+        SystemClock, WorkspaceId, new_program_target, safemap::SafeMap,
+    },
+};
+
+// Builds a State, anything not set keeps the same default as the config
+#[derive(Clone, Debug)]
+pub struct StateBuilder {
+    workspace_matcher: Matcher,
+    workspace_mode: FilterMode,
+    floating_matcher: Matcher,
+    floating_mode: FilterMode,
+    restore_matcher: Matcher,
+    restore_mode: FilterMode,
+    properties_matcher: Matcher,
+    properties_mode: FilterMode,
+    split_matcher: Matcher,
+    split_mode: FilterMode,
+    workspace_enabled: bool,
+    buffer: usize,
+    new_program_target: Option<WorkspaceId>,
+    ignore_transient: bool,
+    aggregate: bool,
+    learn_special: bool,
+    portable: bool,
+    focus_weight: f64,
+    verify_moves: bool,
+    tie_break: TieBreak,
+    self_move_grace_ms: i64,
+    on_no_prediction: NoPrediction,
+    default_workspace: WorkspaceId,
+    floating_enabled: bool,
+    floating_fit: Fit,
+    floating_restore: MonitorRestore,
+    restore_enabled: bool,
+    restore_timeout: i64,
+    restore_recent_activity: i64,
+    restore_on_zombie: OnZombie,
+    scoring: Scoring,
+    properties_enabled: bool,
+    fast_adapt: bool,
+    fast_adapt_secs: i64,
+    fast_adapt_weight: f64,
+    compaction: Compaction,
+    ask_matcher: Matcher,
+    ask_timeout_secs: i64,
+    split_enabled: bool,
+    move_cooldown_ms: i64,
+    learn_user_moves_only: bool,
+    capture_interval_secs: i64,
+    per_monitor: bool,
+    freeze_matcher: Matcher,
+    grouping: Grouping,
+    learn_interval_secs: i64,
+    overrides: HashMap<String, ClassOverride>,
+    clock: Arc<dyn Clock>,
+    hypr: Arc<dyn Hypr>,
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        Self {
+            workspace_matcher: Matcher::default(),
+            workspace_mode: FilterMode::Exclude,
+            floating_matcher: Matcher::default(),
+            floating_mode: FilterMode::Include,
+            restore_matcher: Matcher::default(),
+            restore_mode: FilterMode::Include,
+            properties_matcher: Matcher::default(),
+            properties_mode: FilterMode::Include,
+            split_matcher: Matcher::default(),
+            split_mode: FilterMode::Include,
+            workspace_enabled: true,
+            buffer: 30,
+            new_program_target: None,
+            ignore_transient: false,
+            aggregate: false,
+            learn_special: false,
+            portable: false,
+            focus_weight: 0.25,
+            verify_moves: false,
+            tie_break: TieBreak::ActiveMonitor,
+            self_move_grace_ms: 500,
+            on_no_prediction: NoPrediction::Current,
+            default_workspace: WorkspaceId(1),
+            floating_enabled: true,
+            floating_fit: Fit::default(),
+            floating_restore: MonitorRestore::Opened,
+            restore_enabled: true,
+            restore_timeout: 120,
+            restore_recent_activity: 0,
+            restore_on_zombie: OnZombie::Skip,
+            scoring: Scoring::default(),
+            properties_enabled: false,
+            fast_adapt: false,
+            fast_adapt_secs: 30,
+            fast_adapt_weight: 5.0,
+            compaction: Compaction::default(),
+            ask_matcher: Matcher::new(&[]),
+            ask_timeout_secs: 30,
+            split_enabled: false,
+            move_cooldown_ms: 0,
+            learn_user_moves_only: false,
+            capture_interval_secs: 0,
+            per_monitor: false,
+            freeze_matcher: Matcher::new(&[]),
+            grouping: Grouping::default(),
+            learn_interval_secs: 0,
+            overrides: HashMap::new(),
+            clock: Arc::new(SystemClock),
+            hypr: Arc::new(Hyprland),
+        }
+    }
+}
+
+impl StateBuilder {
+    // The state nest runs with, the clock and hyprland are the real ones
+    pub fn from_config(config: &Config) -> Self {
+        Self::default()
+            .workspace_enabled(config.workspace.enabled)
+            .workspace_filter(
+                Matcher::new(&config.workspace.filter.programs),
+                config.workspace.filter.mode.clone(),
+            )
+            .buffer(config.workspace.buffer)
+            .new_program_target(new_program_target(&config.workspace.new_program_target))
+            .ignore_transient(config.workspace.ignore_transient)
+            .tau(config.workspace.tau)
+            .workspace_range(config.workspace.range())
+            .compaction(Compaction::new(config))
+            .aggregate(config.workspace.aggregate)
+            .learn_special(config.workspace.learn_special)
+            .portable(config.workspace.portable)
+            .focus_weight(config.workspace.focus_weight)
+            .verify_moves(config.workspace.verify_moves)
+            .tie_break(config.workspace.monitor_tie_break.clone())
+            .self_move_grace_ms(config.workspace.self_move_grace_ms)
+            .on_no_prediction(config.workspace.on_no_prediction.clone())
+            .default_workspace(WorkspaceId(config.workspace.default_workspace))
+            .floating_enabled(config.floating.enabled)
+            .floating_filter(
+                Matcher::new(&config.floating.filter.programs),
+                config.floating.filter.mode.clone(),
+            )
+            .floating_fit(Fit::new(&config.floating.monitor))
+            .floating_restore(config.floating.monitor.restore)
+            .restore_enabled(config.restore.enabled)
+            .restore_filter(
+                Matcher::new(&config.restore.filter.programs),
+                config.restore.filter.mode.clone(),
+            )
+            .restore_timeout(config.restore.timeout)
+            .restore_recent_activity(config.restore.require_recent_activity_secs)
+            .restore_on_zombie(config.restore.on_zombie.clone())
+            .time_of_day_weight(config.workspace.time_of_day_weight)
+            .properties_enabled(config.properties.enabled)
+            .properties_filter(
+                Matcher::new(&config.properties.filter.programs),
+                config.properties.filter.mode.clone(),
+            )
+            .split_filter(
+                Matcher::new(&config.split.filter.programs),
+                config.split.filter.mode.clone(),
+            )
+            .fast_adapt(config.workspace.fast_adapt)
+            .fast_adapt_secs(config.workspace.fast_adapt_secs)
+            .fast_adapt_weight(config.workspace.fast_adapt_weight)
+            .ask_matcher(Matcher::new(&config.workspace.ask))
+            .ask_timeout_secs(config.workspace.ask_timeout_secs)
+            .split_enabled(config.split.enabled)
+            .move_cooldown_ms(config.workspace.move_cooldown_ms)
+            .learn_user_moves_only(config.workspace.learn_user_moves_only)
+            .capture_interval_secs(config.floating.capture_interval_secs)
+            .per_monitor(config.workspace.per_monitor)
+            .freeze_matcher(Matcher::new(&config.workspace.freeze_learning))
+            .grouping(Grouping::new(&config.group))
+            .learn_interval_secs(config.workspace.learn_interval_secs)
+            .overrides(config.overrides.clone().into_iter().collect())
+            .clock(Arc::new(SystemClock))
+            .hypr(Arc::new(Hyprland))
+    }
+
+    pub fn workspace_filter(mut self, matcher: Matcher, mode: FilterMode) -> Self {
+        self.workspace_matcher = matcher;
+        self.workspace_mode = mode;
+        self
+    }
+
+    pub fn floating_filter(mut self, matcher: Matcher, mode: FilterMode) -> Self {
+        self.floating_matcher = matcher;
+        self.floating_mode = mode;
+        self
+    }
+
+    pub fn restore_filter(mut self, matcher: Matcher, mode: FilterMode) -> Self {
+        self.restore_matcher = matcher;
+        self.restore_mode = mode;
+        self
+    }
+
+    pub fn workspace_enabled(mut self, workspace_enabled: bool) -> Self {
+        self.workspace_enabled = workspace_enabled;
+        self
+    }
+
+    pub fn properties_filter(mut self, matcher: Matcher, mode: FilterMode) -> Self {
+        self.properties_matcher = matcher;
+        self.properties_mode = mode;
+        self
+    }
+
+    pub fn split_filter(mut self, matcher: Matcher, mode: FilterMode) -> Self {
+        self.split_matcher = matcher;
+        self.split_mode = mode;
+        self
+    }
+
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    pub fn new_program_target(mut self, new_program_target: Option<WorkspaceId>) -> Self {
+        self.new_program_target = new_program_target;
+        self
+    }
+
+    pub fn ignore_transient(mut self, ignore_transient: bool) -> Self {
+        self.ignore_transient = ignore_transient;
+        self
+    }
+
+    pub fn tau(mut self, tau: f64) -> Self {
+        self.scoring.tau = tau;
+        self
+    }
+
+    pub fn aggregate(mut self, aggregate: bool) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn learn_special(mut self, learn_special: bool) -> Self {
+        self.learn_special = learn_special;
+        self
+    }
+
+    pub fn portable(mut self, portable: bool) -> Self {
+        self.portable = portable;
+        self
+    }
+
+    pub fn focus_weight(mut self, focus_weight: f64) -> Self {
+        self.focus_weight = focus_weight;
+        self
+    }
+
+    pub fn verify_moves(mut self, verify_moves: bool) -> Self {
+        self.verify_moves = verify_moves;
+        self
+    }
+
+    pub fn tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    pub fn self_move_grace_ms(mut self, self_move_grace_ms: i64) -> Self {
+        self.self_move_grace_ms = self_move_grace_ms;
+        self
+    }
+
+    pub fn on_no_prediction(mut self, on_no_prediction: NoPrediction) -> Self {
+        self.on_no_prediction = on_no_prediction;
+        self
+    }
+
+    pub fn default_workspace(mut self, default_workspace: WorkspaceId) -> Self {
+        self.default_workspace = default_workspace;
+        self
+    }
+
+    pub fn floating_enabled(mut self, floating_enabled: bool) -> Self {
+        self.floating_enabled = floating_enabled;
+        self
+    }
+
+    pub fn floating_fit(mut self, floating_fit: Fit) -> Self {
+        self.floating_fit = floating_fit;
+        self
+    }
+
+    pub fn floating_restore(mut self, floating_restore: MonitorRestore) -> Self {
+        self.floating_restore = floating_restore;
+        self
+    }
+
+    pub fn restore_enabled(mut self, restore_enabled: bool) -> Self {
+        self.restore_enabled = restore_enabled;
+        self
+    }
+
+    pub fn restore_timeout(mut self, restore_timeout: i64) -> Self {
+        self.restore_timeout = restore_timeout;
+        self
+    }
+
+    pub fn restore_recent_activity(mut self, restore_recent_activity: i64) -> Self {
+        self.restore_recent_activity = restore_recent_activity;
+        self
+    }
+
+    pub fn restore_on_zombie(mut self, restore_on_zombie: OnZombie) -> Self {
+        self.restore_on_zombie = restore_on_zombie;
+        self
+    }
+
+    pub fn time_of_day_weight(mut self, time_of_day_weight: f64) -> Self {
+        self.scoring.time_of_day_weight = time_of_day_weight;
+        self
+    }
+
+    pub fn workspace_range(mut self, range: RangeInclusive<i32>) -> Self {
+        self.scoring.range = range;
+        self
+    }
+
+    pub fn properties_enabled(mut self, properties_enabled: bool) -> Self {
+        self.properties_enabled = properties_enabled;
+        self
+    }
+
+    pub fn fast_adapt(mut self, fast_adapt: bool) -> Self {
+        self.fast_adapt = fast_adapt;
+        self
+    }
+
+    pub fn fast_adapt_secs(mut self, fast_adapt_secs: i64) -> Self {
+        self.fast_adapt_secs = fast_adapt_secs;
+        self
+    }
+
+    pub fn fast_adapt_weight(mut self, fast_adapt_weight: f64) -> Self {
+        self.fast_adapt_weight = fast_adapt_weight;
+        self
+    }
+
+    pub fn compaction(mut self, compaction: Compaction) -> Self {
+        self.compaction = compaction;
+        self
+    }
+
+    pub fn ask_matcher(mut self, ask_matcher: Matcher) -> Self {
+        self.ask_matcher = ask_matcher;
+        self
+    }
+
+    pub fn ask_timeout_secs(mut self, ask_timeout_secs: i64) -> Self {
+        self.ask_timeout_secs = ask_timeout_secs;
+        self
+    }
+
+    pub fn split_enabled(mut self, split_enabled: bool) -> Self {
+        self.split_enabled = split_enabled;
+        self
+    }
+
+    pub fn move_cooldown_ms(mut self, move_cooldown_ms: i64) -> Self {
+        self.move_cooldown_ms = move_cooldown_ms;
+        self
+    }
+
+    pub fn learn_user_moves_only(mut self, learn_user_moves_only: bool) -> Self {
+        self.learn_user_moves_only = learn_user_moves_only;
+        self
+    }
+
+    pub fn capture_interval_secs(mut self, capture_interval_secs: i64) -> Self {
+        self.capture_interval_secs = capture_interval_secs;
+        self
+    }
+
+    pub fn per_monitor(mut self, per_monitor: bool) -> Self {
+        self.per_monitor = per_monitor;
+        self
+    }
+
+    pub fn freeze_matcher(mut self, freeze_matcher: Matcher) -> Self {
+        self.freeze_matcher = freeze_matcher;
+        self
+    }
+
+    pub fn grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    pub fn learn_interval_secs(mut self, learn_interval_secs: i64) -> Self {
+        self.learn_interval_secs = learn_interval_secs;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn hypr(mut self, hypr: Arc<dyn Hypr>) -> Self {
+        self.hypr = hypr;
+        self
+    }
+
+    pub fn overrides(mut self, overrides: HashMap<String, ClassOverride>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    pub fn build(self) -> State {
+        State {
+            addresses: SafeMap::new(),
+            programs: SafeMap::new(),
+            live_counts: SafeMap::new(),
+            disabled: Arc::new(Mutex::new(HashSet::new())),
+            confident: Arc::new(Mutex::new(HashSet::new())),
+            current_workspace: Arc::new(AtomicI32::new(1)),
+            monitor_workspaces: SafeMap::new(),
+            predictions: SafeMap::new(),
+            history_generation: Arc::new(AtomicU64::new(0)),
+            boosts: SafeMap::new(),
+            placements: SafeMap::new(),
+            pending: SafeMap::new(),
+            metrics: Arc::new(Metrics::default()),
+            captures: SafeMap::new(),
+            learns: SafeMap::new(),
+            focus: Arc::new(Mutex::new(Focus::default())),
+            last_moves: SafeMap::new(),
+            settling: Arc::new(AtomicBool::new(true)),
+            workspace_matcher: self.workspace_matcher,
+            workspace_mode: self.workspace_mode,
+            floating_matcher: self.floating_matcher,
+            floating_mode: self.floating_mode,
+            restore_matcher: self.restore_matcher,
+            restore_mode: self.restore_mode,
+            properties_matcher: self.properties_matcher,
+            properties_mode: self.properties_mode,
+            split_matcher: self.split_matcher,
+            split_mode: self.split_mode,
+            workspace_enabled: self.workspace_enabled,
+            workspace_buffer: self.buffer,
+            new_program_target: self.new_program_target,
+            ignore_transient: self.ignore_transient,
+            aggregate: self.aggregate,
+            learn_special: self.learn_special,
+            portable: self.portable,
+            focus_weight: self.focus_weight,
+            verify_moves: self.verify_moves,
+            tie_break: self.tie_break,
+            self_move_grace_ms: self.self_move_grace_ms,
+            on_no_prediction: self.on_no_prediction,
+            default_workspace: self.default_workspace,
+            floating_enabled: self.floating_enabled,
+            floating_fit: self.floating_fit,
+            floating_restore: self.floating_restore,
+            restore_enabled: self.restore_enabled,
+            restore_timeout: self.restore_timeout,
+            restore_recent_activity: self.restore_recent_activity,
+            restore_on_zombie: self.restore_on_zombie,
+            scoring: self.scoring,
+            properties_enabled: self.properties_enabled,
+            fast_adapt: self.fast_adapt,
+            fast_adapt_secs: self.fast_adapt_secs,
+            fast_adapt_weight: self.fast_adapt_weight,
+            compaction: self.compaction,
+            ask_matcher: self.ask_matcher,
+            ask_timeout_secs: self.ask_timeout_secs,
+            split_enabled: self.split_enabled,
+            move_cooldown_ms: self.move_cooldown_ms,
+            learn_user_moves_only: self.learn_user_moves_only,
+            capture_interval_secs: self.capture_interval_secs,
+            per_monitor: self.per_monitor,
+            freeze_matcher: self.freeze_matcher,
+            grouping: self.grouping,
+            learn_interval_secs: self.learn_interval_secs,
+            started: self.clock.now(),
+            overrides: self.overrides,
+            clock: self.clock,
+            hypr: self.hypr,
+            last_save: Arc::new(AtomicI64::new(0)),
+            last_move: Arc::new(AtomicI64::new(0)),
+            connected: Arc::new(AtomicBool::new(false)),
+            changed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The settings a state was built with, in one comparable value
+    fn settings(state: &State) -> impl PartialEq + std::fmt::Debug {
+        (
+            (
+                state.workspace_enabled,
+                state.workspace_mode.clone(),
+                state.workspace_buffer,
+                state.new_program_target,
+                state.on_no_prediction.clone(),
+                state.default_workspace,
+                state.tie_break.clone(),
+                state.focus_weight,
+            ),
+            (
+                state.floating_enabled,
+                state.floating_mode.clone(),
+                state.floating_restore,
+                state.restore_enabled,
+                state.restore_timeout,
+                state.restore_on_zombie.clone(),
+            ),
+            (
+                state.scoring.tau,
+                state.scoring.time_of_day_weight,
+                state.scoring.range.clone(),
+                state.move_cooldown_ms,
+                state.learn_interval_secs,
+                state.ask_timeout_secs,
+                state.self_move_grace_ms,
+            ),
+        )
+    }
+
+    #[test]
+    fn defaults_match_the_config() {
+        let built = StateBuilder::default().build();
+        let loaded = StateBuilder::from_config(&Config::default()).build();
+        assert_eq!(settings(&built), settings(&loaded));
+    }
+
+    #[test]
+    fn setters_reach_the_state() {
+        let state = StateBuilder::default()
+            .workspace_enabled(false)
+            .buffer(5)
+            .new_program_target(Some(WorkspaceId(4)))
+            .on_no_prediction(NoPrediction::Default)
+            .default_workspace(WorkspaceId(9))
+            .workspace_range(2..=6)
+            .restore_timeout(10)
+            .build();
+        assert!(!state.workspace_enabled);
+        assert_eq!(state.workspace_buffer, 5);
+        assert_eq!(state.new_program_target, Some(WorkspaceId(4)));
+        assert_eq!(state.on_no_prediction, NoPrediction::Default);
+        assert_eq!(state.default_workspace, WorkspaceId(9));
+        assert_eq!(state.scoring.range, 2..=6);
+        assert_eq!(state.restore_timeout, 10);
+        // Untouched settings keep their default
+        assert!(state.floating_enabled);
+        assert_eq!(state.tie_break, TieBreak::ActiveMonitor);
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+
+// Where the state reads the time from, scoring and compaction included, so tests can move it
+// forward by hand
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// Starts at the system time and only moves when advanced
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock(std::sync::Mutex<DateTime<Utc>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self(std::sync::Mutex::new(Utc::now()))
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        let mut now = self.0.lock().unwrap();
+        *now += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,102 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    config::{Config, FilterMode},
+    state::{Matcher, Program},
+};
+
+// Every pruning rule, applied together when the storage is compacted
+#[derive(Clone, Debug)]
+pub struct Compaction {
+    // None keeps whole histories, aggregated ones are already bounded
+    pub buffer: Option<usize>,
+    pub class_buffers: HashMap<String, usize>,
+    pub floating_matcher: Matcher,
+    pub floating_mode: FilterMode,
+    // Entries older than this are dropped, 0 keeps them
+    pub max_age_secs: i64,
+}
+
+// What a compaction removed
+#[derive(Clone, Debug, Default)]
+pub struct CompactReport {
+    pub entries: usize,
+    pub floating: usize,
+    pub programs: usize,
+}
+
+impl Default for Compaction {
+    fn default() -> Self {
+        Self {
+            buffer: Some(30),
+            class_buffers: HashMap::new(),
+            floating_matcher: Matcher::default(),
+            floating_mode: FilterMode::Include,
+            max_age_secs: 0,
+        }
+    }
+}
+
+impl Compaction {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            buffer: (!config.workspace.aggregate).then_some(config.workspace.buffer),
+            class_buffers: config
+                .overrides
+                .iter()
+                .filter_map(|(class, val)| val.buffer.map(|buffer| (class.clone(), buffer)))
+                .collect(),
+            floating_matcher: Matcher::new(&config.floating.filter.programs),
+            floating_mode: config.floating.filter.mode.clone(),
+            max_age_secs: config.storage.max_age_days * 24 * 60 * 60,
+        }
+    }
+
+    // Prunes a single program, ages are counted up to `now`. False if nothing is left worth
+    // keeping, dropped programs are counted by the caller
+    pub fn apply(&self, program: &mut Program, report: &mut CompactReport, now: i64) -> bool {
+        let before = program.workspaces.len();
+        if self.max_age_secs > 0 {
+            let oldest = now - self.max_age_secs;
+            program
+                .workspaces
+                .retain(|workspace| workspace.timestamp >= oldest);
+        }
+        if let Some(buffer) = self.buffer {
+            let buffer = match self.class_buffers.get(&program.class) {
+                Some(val) => *val,
+                None => buffer,
+            };
+            let excess = program.workspaces.len().saturating_sub(buffer);
+            program.workspaces.drain(..excess);
+        }
+        report.entries += before - program.workspaces.len();
+
+        if program.floating_window.is_some()
+            && !self
+                .floating_matcher
+                .decision(&program.class, &self.floating_mode)
+        {
+            program.floating_window = None;
+            report.floating += 1;
+        }
+
+        !program.is_empty()
+    }
+}
+
+impl CompactReport {
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0 && self.floating == 0 && self.programs == 0
+    }
+}
+
+impl Display for CompactReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed {} history entries, {} floating geometries and {} programs",
+            self.entries, self.floating, self.programs
+        )
+    }
+}
@@ -1,11 +1,225 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashSet, fmt::Display, str::FromStr};
 
-use crate::state::ParseError;
+use hyprland::shared::Address;
+
+use crate::{
+    config::{FloatingMonitorConfig, PositionMode},
+    state::{ClientInfo, MonitorInfo, ParseError},
+};
 
 #[derive(Clone, Debug)]
 pub struct FloatingWindow {
     pub at: (i16, i16),
     pub size: (i16, i16),
+    // How `at` and `size` are measured
+    pub mode: PositionMode,
+}
+
+// Logical geometry of a monitor as (x, y, width, height)
+pub type MonitorRect = (i32, i32, i32, i32);
+
+// Compositor rounding can move a restored window by a pixel or two
+const TOLERANCE: u16 = 2;
+// Percent positions are stored in hundredths of a percent so they fit the same integers
+const PERCENT_SCALE: f32 = 10000.0;
+// Floating windows covering this much of their monitor are treated as maximized
+const MAXIMIZED_RATIO: f32 = 0.95;
+
+impl FloatingWindow {
+    // Converts pixel geometry into `mode`, without a monitor to measure against it stays exact
+    pub fn new(
+        at: (i16, i16),
+        size: (i16, i16),
+        mode: PositionMode,
+        monitor: Option<MonitorRect>,
+    ) -> Self {
+        let (x, y, width, height) = match monitor {
+            Some(val) if mode != PositionMode::Exact => val,
+            _ => {
+                return Self {
+                    at,
+                    size,
+                    mode: PositionMode::Exact,
+                };
+            }
+        };
+        let relative = (at.0 as i32 - x, at.1 as i32 - y);
+        match mode {
+            PositionMode::Exact => unreachable!(),
+            PositionMode::Relative => Self {
+                at: (relative.0 as i16, relative.1 as i16),
+                size,
+                mode,
+            },
+            PositionMode::Percent => {
+                let percent = |val: i32, total: i32| {
+                    (val as f32 / total.max(1) as f32 * PERCENT_SCALE).round() as i16
+                };
+                Self {
+                    at: (percent(relative.0, width), percent(relative.1, height)),
+                    size: (
+                        percent(size.0 as i32, width),
+                        percent(size.1 as i32, height),
+                    ),
+                    mode,
+                }
+            }
+        }
+    }
+
+    // Pixel position and size on `monitor`, exact geometry ignores it
+    pub fn pixels(&self, monitor: Option<MonitorRect>) -> ((i16, i16), (i16, i16)) {
+        let (x, y, width, height) = match monitor {
+            Some(val) if self.mode != PositionMode::Exact => val,
+            _ => return (self.at, self.size),
+        };
+        match self.mode {
+            PositionMode::Exact => (self.at, self.size),
+            PositionMode::Relative => (
+                ((self.at.0 as i32 + x) as i16, (self.at.1 as i32 + y) as i16),
+                self.size,
+            ),
+            PositionMode::Percent => {
+                let pixels = |val: i16, total: i32| {
+                    (val as f32 / PERCENT_SCALE * total as f32).round() as i32
+                };
+                (
+                    (
+                        (pixels(self.at.0, width) + x) as i16,
+                        (pixels(self.at.1, height) + y) as i16,
+                    ),
+                    (
+                        pixels(self.size.0, width) as i16,
+                        pixels(self.size.1, height) as i16,
+                    ),
+                )
+            }
+        }
+    }
+
+    pub fn approx_eq(&self, other: &FloatingWindow) -> bool {
+        self.mode == other.mode
+            && self.at.0.abs_diff(other.at.0) <= TOLERANCE
+            && self.at.1.abs_diff(other.at.1) <= TOLERANCE
+            && self.size.0.abs_diff(other.size.0) <= TOLERANCE
+            && self.size.1.abs_diff(other.size.1) <= TOLERANCE
+    }
+}
+
+// The steps of `[floating.monitor]` after the monitor is picked, in the order they run
+#[derive(Clone, Debug)]
+pub struct Fit {
+    default_size: (i16, i16),
+    clamp: bool,
+    min_size: (i16, i16),
+    max_size: (i16, i16),
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Self::new(&FloatingMonitorConfig::default())
+    }
+}
+
+impl Fit {
+    pub fn new(config: &FloatingMonitorConfig) -> Self {
+        Self {
+            default_size: config.default_size,
+            clamp: config.clamp,
+            min_size: config.min_size,
+            max_size: config.max_size,
+        }
+    }
+
+    // Without a monitor only the min and max size apply
+    pub fn apply(
+        &self,
+        at: (i16, i16),
+        size: (i16, i16),
+        monitor: Option<MonitorRect>,
+    ) -> ((i16, i16), (i16, i16)) {
+        let (mut x, mut y) = (at.0 as i32, at.1 as i32);
+        let (mut width, mut height) = (size.0 as i32, size.1 as i32);
+        if let Some((left, top, monitor_width, monitor_height)) = monitor {
+            // Nothing of the window shows, the monitor it was remembered on is likely gone
+            if x >= left + monitor_width
+                || y >= top + monitor_height
+                || x + width <= left
+                || y + height <= top
+            {
+                if self.default_size.0 > 0 && self.default_size.1 > 0 {
+                    (width, height) = (self.default_size.0 as i32, self.default_size.1 as i32);
+                }
+                x = left + (monitor_width - width) / 2;
+                y = top + (monitor_height - height) / 2;
+            }
+            if self.clamp {
+                width = width.min(monitor_width);
+                height = height.min(monitor_height);
+                x = x.clamp(left, left + monitor_width - width);
+                y = y.clamp(top, top + monitor_height - height);
+            }
+        }
+        let width = (width as i16).clamp(self.min_size.0, self.max_size.0);
+        let height = (height as i16).clamp(self.min_size.1, self.max_size.1);
+        ((x as i16, y as i16), (width, height))
+    }
+}
+
+pub fn monitor_rect(monitor: &MonitorInfo) -> MonitorRect {
+    (
+        monitor.x,
+        monitor.y,
+        (monitor.width as f32 / monitor.scale) as i32,
+        (monitor.height as f32 / monitor.scale) as i32,
+    )
+}
+
+// The monitor showing the workspace a client is on
+pub fn client_monitor(client: &ClientInfo, monitors: &[MonitorInfo]) -> Option<MonitorRect> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.active_workspace == client.workspace)
+        .map(monitor_rect)
+}
+
+// Untracked floating windows are only registered once they survive a second poll, that way
+// windows that close right away don't leave empty programs behind
+#[derive(Debug, Default)]
+pub struct Sightings {
+    previous: HashSet<Address>,
+    current: HashSet<Address>,
+}
+
+impl Sightings {
+    // True if the previous poll saw the window as well, otherwise it is looked for on the next
+    pub fn confirmed(&mut self, address: &Address) -> bool {
+        if self.previous.contains(address) {
+            return true;
+        }
+        self.current.insert(address.clone());
+        false
+    }
+
+    // Ends a poll, windows it did not see start over
+    pub fn next_poll(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+}
+
+// Whether a client covers the monitor it is on, its workspace does not have to be shown
+pub fn is_maximized(client: &ClientInfo, monitors: &[MonitorInfo]) -> bool {
+    let monitor = match monitors
+        .iter()
+        .find(|monitor| Some(monitor.id) == client.monitor)
+    {
+        Some(val) => val,
+        None => return false,
+    };
+    let width = monitor.width as f32 / monitor.scale;
+    let height = monitor.height as f32 / monitor.scale;
+    client.size.0 as f32 >= width * MAXIMIZED_RATIO
+        && client.size.1 as f32 >= height * MAXIMIZED_RATIO
 }
 
 impl Display for FloatingWindow {
@@ -14,7 +228,13 @@ impl Display for FloatingWindow {
             f,
             "{};{};{};{}",
             self.at.0, self.at.1, self.size.0, self.size.1
-        )
+        )?;
+        // Exact geometry keeps the old four field form
+        match self.mode {
+            PositionMode::Exact => Ok(()),
+            PositionMode::Relative => write!(f, ";relative"),
+            PositionMode::Percent => write!(f, ";percent"),
+        }
     }
 }
 
@@ -26,7 +246,7 @@ impl FromStr for FloatingWindow {
             return Err(ParseError::InvalidFormat);
         }
         let parts: Vec<&str> = s.split(";").collect();
-        if parts.len() != 4 {
+        if !(4..=5).contains(&parts.len()) {
             return Err(ParseError::InvalidFormat);
         }
 
@@ -34,10 +254,215 @@ impl FromStr for FloatingWindow {
         let at_y: i16 = parts[1].parse()?;
         let size_x: i16 = parts[2].parse()?;
         let size_y: i16 = parts[3].parse()?;
+        let mode = match parts.get(4) {
+            None => PositionMode::Exact,
+            Some(&"relative") => PositionMode::Relative,
+            Some(&"percent") => PositionMode::Percent,
+            Some(_) => return Err(ParseError::InvalidFormat),
+        };
 
         Ok(FloatingWindow {
             at: (at_x, at_y),
             size: (size_x, size_y),
+            mode,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MonitorId, WorkspaceId};
+
+    // A 1920x1080 monitor right of another one
+    const MONITOR: MonitorRect = (1920, 0, 1920, 1080);
+
+    #[test]
+    fn exact_ignores_the_monitor() {
+        let window =
+            FloatingWindow::new((2000, 100), (800, 600), PositionMode::Exact, Some(MONITOR));
+        assert_eq!(window.at, (2000, 100));
+        assert_eq!(window.pixels(Some(MONITOR)), ((2000, 100), (800, 600)));
+    }
+
+    #[test]
+    fn no_monitor_stays_exact() {
+        let window = FloatingWindow::new((2000, 100), (800, 600), PositionMode::Percent, None);
+        assert_eq!(window.mode, PositionMode::Exact);
+        assert_eq!(window.size, (800, 600));
+    }
+
+    #[test]
+    fn relative_moves_with_the_monitor() {
+        let window = FloatingWindow::new(
+            (2020, 40),
+            (800, 600),
+            PositionMode::Relative,
+            Some(MONITOR),
+        );
+        assert_eq!(window.at, (100, 40));
+        assert_eq!(
+            window.pixels(Some((0, 0, 1920, 1080))),
+            ((100, 40), (800, 600))
+        );
+    }
+
+    #[test]
+    fn percent_scales_with_the_monitor() {
+        let window = FloatingWindow::new(
+            (2880, 270),
+            (960, 540),
+            PositionMode::Percent,
+            Some(MONITOR),
+        );
+        assert_eq!(window.at, (5000, 2500));
+        assert_eq!(window.size, (5000, 5000));
+        assert_eq!(window.pixels(Some(MONITOR)), ((2880, 270), (960, 540)));
+        // Same share of a smaller monitor
+        assert_eq!(
+            window.pixels(Some((0, 0, 1280, 720))),
+            ((640, 180), (640, 360))
+        );
+        // Without a monitor the stored numbers are all there is
+        assert_eq!(window.pixels(None), ((5000, 2500), (5000, 5000)));
+    }
+
+    #[test]
+    fn round_trip() {
+        for line in [
+            "10;20;300;400",
+            "10;20;300;400;relative",
+            "5000;0;2500;2500;percent",
+        ] {
+            assert_eq!(FloatingWindow::from_str(line).unwrap().to_string(), line);
+        }
+        assert!(FloatingWindow::from_str("").is_err());
+        assert!(FloatingWindow::from_str("1;2;3").is_err());
+        assert!(FloatingWindow::from_str("1;2;3;4;sideways").is_err());
+    }
+
+    fn fit(default_size: (i16, i16), clamp: bool) -> Fit {
+        Fit::new(&FloatingMonitorConfig {
+            default_size,
+            clamp,
+            min_size: (100, 100),
+            max_size: (1000, 1000),
+            ..FloatingMonitorConfig::default()
         })
     }
+
+    #[test]
+    fn fit_without_monitor_only_bounds_size() {
+        let fit = fit((0, 0), true);
+        assert_eq!(
+            fit.apply((-5000, 0), (50, 4000), None),
+            ((-5000, 0), (100, 1000))
+        );
+    }
+
+    #[test]
+    fn fit_centers_windows_off_the_monitor() {
+        // Remembered on a monitor left of this one
+        assert_eq!(
+            fit((0, 0), false).apply((100, 100), (800, 600), Some(MONITOR)),
+            ((2480, 240), (800, 600))
+        );
+        // The default size replaces the remembered one
+        assert_eq!(
+            fit((400, 200), false).apply((100, 100), (800, 600), Some(MONITOR)),
+            ((2680, 440), (400, 200))
+        );
+    }
+
+    #[test]
+    fn fit_clamps_to_the_monitor() {
+        let monitor = Some((0, 0, 1920, 1080));
+        // Hanging over the right edge
+        assert_eq!(
+            fit((0, 0), true).apply((1800, 100), (400, 300), monitor),
+            ((1520, 100), (400, 300))
+        );
+        assert_eq!(
+            fit((0, 0), false).apply((1800, 100), (400, 300), monitor),
+            ((1800, 100), (400, 300))
+        );
+        // Shrunk to the monitor, then to max_size
+        assert_eq!(
+            fit((0, 0), true).apply((0, 0), (3000, 3000), monitor),
+            ((0, 0), (1000, 1000))
+        );
+    }
+
+    #[test]
+    fn approx_eq_allows_rounding() {
+        let window = FloatingWindow::from_str("10;20;300;400").unwrap();
+        assert!(window.approx_eq(&FloatingWindow::from_str("12;18;301;400").unwrap()));
+        assert!(!window.approx_eq(&FloatingWindow::from_str("13;20;300;400").unwrap()));
+        assert!(!window.approx_eq(&FloatingWindow::from_str("10;20;300;400;relative").unwrap()));
+    }
+
+    fn monitor(id: i128, workspace_id: i32, width: u16, height: u16, scale: f32) -> MonitorInfo {
+        MonitorInfo {
+            id: MonitorId(id),
+            name: format!("DP-{id}"),
+            focused: false,
+            active_workspace: WorkspaceId(workspace_id),
+            special_workspace: WorkspaceId(0),
+            x: 0,
+            y: 0,
+            width,
+            height,
+            scale,
+        }
+    }
+
+    fn client(monitor: i128, workspace_id: i32, size: (i16, i16)) -> ClientInfo {
+        ClientInfo {
+            address: Address::new("a"),
+            class: "mpv".to_string(),
+            workspace: WorkspaceId(workspace_id),
+            monitor: Some(MonitorId(monitor)),
+            floating: true,
+            at: (0, 0),
+            size,
+        }
+    }
+
+    #[test]
+    fn maximized_on_its_own_monitor() {
+        let monitors = [
+            monitor(0, 1, 1920, 1080, 1.0),
+            monitor(1, 2, 3840, 2160, 2.0),
+        ];
+        assert!(is_maximized(&client(0, 1, (1900, 1060)), &monitors));
+        assert!(!is_maximized(&client(0, 1, (960, 1060)), &monitors));
+        // Measured in logical pixels of the scaled monitor
+        assert!(is_maximized(&client(1, 2, (1900, 1060)), &monitors));
+        // On a workspace no monitor shows right now
+        assert!(is_maximized(&client(1, 7, (1900, 1060)), &monitors));
+        // Another monitor showing the same workspace id does not matter
+        assert!(!is_maximized(
+            &client(1, 1, (1900, 1060)),
+            &[
+                monitor(0, 1, 1920, 1080, 1.0),
+                monitor(1, 1, 3840, 2160, 1.0)
+            ]
+        ));
+        assert!(!is_maximized(&client(2, 1, (1900, 1060)), &monitors));
+    }
+
+    #[test]
+    fn untracked_windows_need_a_second_sighting() {
+        let (a, b) = (Address::new("a"), Address::new("b"));
+        let mut sightings = Sightings::default();
+        assert!(!sightings.confirmed(&a));
+        assert!(!sightings.confirmed(&b));
+        sightings.next_poll();
+        // b closed before this poll
+        assert!(sightings.confirmed(&a));
+        sightings.next_poll();
+        sightings.next_poll();
+        // A window that comes back later starts over
+        assert!(!sightings.confirmed(&b));
+    }
 }
@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+
+use log::error;
+use regex::Regex;
+
+use crate::config::ClassGroup;
+
+// Folds classes matching a pattern into one program, e.g. every profile of a browser PWA
+#[derive(Clone, Debug, Default)]
+pub struct Grouping {
+    groups: Vec<(Regex, Option<String>)>,
+}
+
+impl Grouping {
+    pub fn new(groups: &[ClassGroup]) -> Self {
+        let mut grouping = Grouping::default();
+        for group in groups {
+            match Regex::new(&group.pattern) {
+                Ok(val) => grouping.groups.push((val, group.name.clone())),
+                Err(err) => error!("Ignoring invalid group pattern {}: {err}", group.pattern),
+            }
+        }
+        grouping
+    }
+
+    // The class nest learns under, the first group that matches decides. Without a name the
+    // first capture is used, or the whole match when the pattern has no captures
+    pub fn class<'a>(&self, class: &'a str) -> Cow<'a, str> {
+        for (pattern, name) in &self.groups {
+            let captures = match pattern.captures(class) {
+                Some(val) => val,
+                None => continue,
+            };
+            let grouped = match name {
+                Some(name) => {
+                    let mut grouped = String::new();
+                    captures.expand(name, &mut grouped);
+                    grouped
+                }
+                None => match captures.get(1).or_else(|| captures.get(0)) {
+                    Some(val) => val.as_str().to_string(),
+                    None => continue,
+                },
+            };
+            if grouped.is_empty() {
+                continue;
+            }
+            return Cow::Owned(grouped);
+        }
+        Cow::Borrowed(class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grouping(groups: &[(&str, Option<&str>)]) -> Grouping {
+        let groups: Vec<ClassGroup> = groups
+            .iter()
+            .map(|(pattern, name)| ClassGroup {
+                pattern: pattern.to_string(),
+                name: name.map(str::to_string),
+            })
+            .collect();
+        Grouping::new(&groups)
+    }
+
+    #[test]
+    fn ungrouped_classes_are_borrowed() {
+        let grouping = grouping(&[("^chrome-.*", Some("chrome"))]);
+        assert!(matches!(grouping.class("kitty"), Cow::Borrowed("kitty")));
+    }
+
+    #[test]
+    fn name_with_captures() {
+        let grouping = grouping(&[
+            ("^chrome-(\\w+)-Default$", Some("pwa-$1")),
+            ("^chrome-.*", Some("chrome")),
+        ]);
+        assert_eq!(grouping.class("chrome-abc-Default"), "pwa-abc");
+        // The first group that matches decides
+        assert_eq!(grouping.class("chrome-abc-Profile_1"), "chrome");
+    }
+
+    #[test]
+    fn without_a_name() {
+        let grouping = grouping(&[("^(steam)_app_\\d+$", None), ("^jetbrains-\\w+", None)]);
+        assert_eq!(grouping.class("steam_app_570"), "steam");
+        assert_eq!(grouping.class("jetbrains-idea-ce"), "jetbrains-idea");
+    }
+
+    #[test]
+    fn empty_results_fall_through() {
+        let grouping = grouping(&[("^x(\\d*)", None), ("^x", Some("x-group")), ("(", None)]);
+        assert_eq!(grouping.class("xterm"), "x-group");
+    }
+}
@@ -0,0 +1,1699 @@
+// Drives a State the way the event loop does, against a fake compositor, clock and storage,
+// so opening, learning, placing and restoring are tested together
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::Ordering},
+};
+
+use chrono::Duration;
+use hyprland::shared::Address;
+
+use crate::{
+    config::{
+        ClassOverride, FilterMode, MonitorChange, NoPrediction, OnClose, PositionMode, TieBreak,
+    },
+    state::{
+        Action, ClientInfo, Compaction, Error, FloatPlacement, FloatingWindow, Matcher, MonitorId,
+        MonitorInfo, MoveResult, Moved, OpenOutcome, Placement, Program, RemoveResult, State,
+        StateBuilder, Target, Window, WorkspaceId,
+        clock::{Clock, MockClock},
+        hypr::{MockHypr, WorkspaceInfo},
+    },
+    storage::{MemStore, Storage},
+};
+
+// What hyprland would report, in order
+enum Event {
+    // Address, class and the workspace it opened on
+    Open(&'static str, &'static str, i32),
+    // A move made by the user
+    Move(&'static str, i32),
+    // A move made by the user to a named workspace
+    MoveNamed(&'static str, i32, &'static str),
+    Close(&'static str),
+    // The window got focus on the active workspace
+    Focus(&'static str),
+    Workspace(i32),
+    Wait(i64),
+}
+
+struct Harness {
+    state: State,
+    clock: Arc<MockClock>,
+    hypr: Arc<MockHypr>,
+    store: MemStore,
+    // Every dispatch so far, in order
+    dispatched: Vec<Action>,
+    // What nest decided for every opened window
+    outcomes: Vec<OpenOutcome>,
+    // What became of every user move
+    moves: Vec<Moved>,
+    removed: Vec<RemoveResult>,
+}
+
+impl Harness {
+    fn new(builder: StateBuilder) -> Self {
+        let clock = Arc::new(MockClock::new());
+        let hypr = Arc::new(MockHypr::default());
+        let state = builder.clock(clock.clone()).hypr(hypr.clone()).build();
+        Self {
+            state,
+            clock,
+            hypr,
+            store: MemStore::default(),
+            dispatched: Vec::new(),
+            outcomes: Vec::new(),
+            moves: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    async fn run(&mut self, events: &[Event]) {
+        for event in events {
+            match *event {
+                Event::Open(address, class, workspace_id) => {
+                    let outcome = self
+                        .state
+                        .handle_open(
+                            class,
+                            &Address::new(address),
+                            &workspace_name(workspace_id),
+                            false,
+                        )
+                        .await;
+                    self.outcomes.push(outcome);
+                }
+                Event::Move(address, workspace_id) => {
+                    self.user_move(address, workspace_id, &workspace_name(workspace_id))
+                        .await
+                }
+                Event::MoveNamed(address, workspace_id, name) => {
+                    self.user_move(address, workspace_id, name).await
+                }
+                Event::Close(address) => {
+                    let removed = self.state.remove_window(Address::new(address)).await;
+                    self.removed.push(removed.unwrap());
+                }
+                Event::Focus(address) => {
+                    self.state
+                        .window_focused(Address::new(address))
+                        .await
+                        .unwrap();
+                }
+                Event::Workspace(workspace_id) => {
+                    self.state
+                        .workspace_changed(WorkspaceId(workspace_id))
+                        .await
+                }
+                Event::Wait(secs) => self.clock.advance(Duration::seconds(secs)),
+            }
+            self.echo().await;
+        }
+    }
+
+    async fn user_move(&mut self, address: &str, workspace_id: i32, name: &str) {
+        let moved = self
+            .state
+            .window_moved(Address::new(address), WorkspaceId(workspace_id), name)
+            .await;
+        self.moves.push(moved.unwrap());
+    }
+
+    // Hyprland reports what nest dispatched back as events, moves have to be seen to be
+    // told apart from the user's. Refused dispatches change nothing
+    async fn echo(&mut self) {
+        for action in self.hypr.take() {
+            match &action {
+                _ if self.hypr.refuses(&action) => (),
+                Action::MoveToWorkspace(Target::Id(workspace_id), address) => {
+                    let moved = self
+                        .state
+                        .window_moved(address.clone(), *workspace_id, &workspace_id.to_string())
+                        .await;
+                    assert_eq!(moved.unwrap(), Moved::Internal);
+                }
+                Action::Workspace(Target::Id(workspace_id)) => {
+                    self.state.workspace_changed(*workspace_id).await
+                }
+                _ => (),
+            }
+            self.dispatched.push(action);
+        }
+    }
+
+    // What the next start would load
+    async fn persisted(&self) -> Vec<Program> {
+        let mut storage = Storage::memory(self.store.clone());
+        storage.persist(&self.state).await.unwrap();
+        Storage::memory(self.store.clone()).read().unwrap()
+    }
+}
+
+fn moved_to(address: &str, workspace_id: i32) -> Action {
+    Action::MoveToWorkspace(Target::Id(WorkspaceId(workspace_id)), Address::new(address))
+}
+
+// How hyprland names a workspace in events
+fn workspace_name(workspace_id: i32) -> String {
+    if WorkspaceId(workspace_id).is_special() {
+        "special:scratch".to_string()
+    } else {
+        workspace_id.to_string()
+    }
+}
+
+fn client(address: &str, class: &str, workspace_id: i32) -> ClientInfo {
+    ClientInfo {
+        address: Address::new(address),
+        class: class.to_string(),
+        workspace: WorkspaceId(workspace_id),
+        monitor: Some(MonitorId(0)),
+        floating: false,
+        at: (0, 0),
+        size: (960, 1080),
+    }
+}
+
+// A 1920x1080 monitor showing `workspace_id`, laid out left to right by id
+fn monitor(id: i128, name: &str, focused: bool, workspace_id: i32) -> MonitorInfo {
+    MonitorInfo {
+        id: MonitorId(id),
+        name: name.to_string(),
+        focused,
+        active_workspace: WorkspaceId(workspace_id),
+        special_workspace: WorkspaceId(0),
+        x: id as i32 * 1920,
+        y: 0,
+        width: 1920,
+        height: 1080,
+        scale: 1.0,
+    }
+}
+
+// Workspaces by id and the output they are on
+fn workspaces(outputs: &[(i32, &str)]) -> Vec<WorkspaceInfo> {
+    outputs
+        .iter()
+        .map(|(workspace_id, output)| WorkspaceInfo {
+            id: WorkspaceId(*workspace_id),
+            name: workspace_id.to_string(),
+            monitor: output.to_string(),
+        })
+        .collect()
+}
+
+fn history(programs: &[Program], class: &str) -> Vec<i32> {
+    programs
+        .iter()
+        .find(|program| program.class == class)
+        .map(|program| {
+            program
+                .workspaces
+                .iter()
+                .map(|val| val.workspace_id.0)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn learn_then_place() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Close("a"),
+            Event::Wait(5),
+            Event::Open("b", "kitty", 1),
+        ])
+        .await;
+
+    // The first window stays where it opened, the next one follows the move
+    assert_eq!(harness.dispatched, vec![moved_to("b", 3)]);
+    // Placing the window is not learned as a move
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1, 3]);
+}
+
+#[tokio::test]
+async fn restore_on_close() {
+    let builder = StateBuilder::default().restore_filter(
+        Matcher::new(&["pavucontrol".to_string()]),
+        FilterMode::Include,
+    );
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(2),
+            Event::Open("a", "pavucontrol", 2),
+            Event::Wait(5),
+            Event::Move("a", 5),
+            Event::Workspace(5),
+            Event::Wait(5),
+            Event::Close("a"),
+            Event::Open("b", "pavucontrol", 2),
+            Event::Workspace(5),
+            // Past the restore timeout
+            Event::Wait(200),
+            Event::Close("b"),
+        ])
+        .await;
+
+    assert_eq!(
+        harness.dispatched,
+        vec![
+            Action::Workspace(Target::Id(WorkspaceId(2))),
+            moved_to("b", 5),
+        ]
+    );
+    assert_eq!(
+        harness.removed,
+        vec![RemoveResult::Restored, RemoveResult::NotRestored]
+    );
+}
+
+#[tokio::test]
+async fn filtered_class() {
+    let builder = StateBuilder::default()
+        .workspace_filter(Matcher::new(&["steam".to_string()]), FilterMode::Exclude);
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "steam", 1),
+            Event::Open("b", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 4),
+            Event::Move("b", 4),
+            Event::Close("a"),
+            Event::Close("b"),
+            Event::Open("c", "steam", 1),
+            Event::Open("d", "kitty", 1),
+        ])
+        .await;
+
+    // Filtered classes are still learned, they are just never moved
+    assert_eq!(harness.dispatched, vec![moved_to("d", 4)]);
+    let programs = harness.persisted().await;
+    assert_eq!(history(&programs, "steam"), vec![1, 4]);
+    assert_eq!(history(&programs, "kitty"), vec![1, 4]);
+}
+
+#[tokio::test]
+async fn new_program_target() {
+    let builder = StateBuilder::default().new_program_target(Some(WorkspaceId(8)));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "kitty", 1),
+        ])
+        .await;
+
+    // Only the first window of a never seen program goes to the target
+    assert_eq!(harness.dispatched, vec![moved_to("a", 8)]);
+    // The history holds where it opened, not the target
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1]);
+}
+
+#[tokio::test]
+async fn restore_on_the_monitor_it_opened_on() {
+    let builder = StateBuilder::default().restore_filter(
+        Matcher::new(&["pavucontrol".to_string()]),
+        FilterMode::Include,
+    );
+    let mut harness = Harness::new(builder);
+    harness.hypr.set_monitors(vec![
+        monitor(0, "DP-1", false, 1),
+        monitor(1, "DP-2", true, 6),
+    ]);
+    harness
+        .run(&[
+            Event::Workspace(6),
+            Event::Open("a", "pavucontrol", 6),
+            Event::Close("a"),
+        ])
+        .await;
+
+    // The monitor comes first so the workspace is shown where it was
+    assert_eq!(
+        harness.dispatched,
+        vec![
+            Action::FocusMonitor("DP-2".to_string()),
+            Action::Workspace(Target::Id(WorkspaceId(6))),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn refused_move_is_not_taken_for_internal() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .hypr
+        .fail(|action| matches!(action, Action::MoveToWorkspace(..)));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Close("a"),
+            Event::Open("b", "kitty", 1),
+            Event::Wait(5),
+            // Had the refused move stayed marked as nest's own, this would be ignored
+            Event::Move("b", 2),
+        ])
+        .await;
+
+    assert_eq!(harness.dispatched, vec![moved_to("b", 3)]);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1, 3, 2]);
+}
+
+#[tokio::test]
+async fn older_moves_fade() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 2),
+            Event::Move("b", 2),
+            // Four weeks is four time constants
+            Event::Wait(4 * 604800),
+            Event::Move("a", 3),
+            Event::Close("a"),
+            Event::Close("b"),
+            Event::Open("c", "kitty", 1),
+        ])
+        .await;
+
+    // Two old moves weigh less than a single new one
+    assert_eq!(harness.dispatched, vec![moved_to("c", 3)]);
+}
+
+#[tokio::test]
+async fn compaction_drops_old_entries() {
+    let builder = StateBuilder::default().compaction(Compaction {
+        max_age_secs: 7 * 24 * 60 * 60,
+        ..Compaction::default()
+    });
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 2),
+            Event::Wait(8 * 24 * 60 * 60),
+            Event::Move("a", 3),
+        ])
+        .await;
+
+    assert_eq!(harness.state.compact().await.entries, 2);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![3]);
+}
+
+#[tokio::test]
+async fn class_buffers_trim_separately() {
+    let builder = StateBuilder::default().overrides(HashMap::from([(
+        "kitty".to_string(),
+        ClassOverride {
+            buffer: Some(2),
+            ..ClassOverride::default()
+        },
+    )]));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "firefox", 1),
+            Event::Wait(5),
+            Event::Move("a", 2),
+            Event::Move("a", 3),
+            Event::Move("b", 2),
+            Event::Move("b", 3),
+        ])
+        .await;
+
+    let programs = harness.persisted().await;
+    assert_eq!(history(&programs, "kitty"), vec![2, 3]);
+    assert_eq!(history(&programs, "firefox"), vec![1, 2, 3]);
+}
+
+// The focused monitor shows workspace 1 with the scratchpad on top
+fn scratchpad(harness: &Harness) {
+    let mut monitor = monitor(0, "DP-1", true, 1);
+    monitor.special_workspace = WorkspaceId(-98);
+    harness.hypr.set_monitors(vec![monitor]);
+}
+
+#[tokio::test]
+async fn open_on_special_workspace() {
+    let mut harness = Harness::new(StateBuilder::default());
+    scratchpad(&harness);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", -98),
+            Event::Wait(5),
+            Event::Move("a", 2),
+        ])
+        .await;
+
+    // Left on the scratchpad, and the scratchpad is not where it is learned to open
+    assert_eq!(harness.dispatched, vec![]);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![2]);
+}
+
+#[tokio::test]
+async fn open_on_special_workspace_learned() {
+    let mut harness = Harness::new(StateBuilder::default().learn_special(true));
+    scratchpad(&harness);
+    harness.hypr.set_clients(vec![client("a", "kitty", -98)]);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "kitty", -98)])
+        .await;
+
+    assert_eq!(harness.dispatched, vec![]);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![-98]);
+}
+
+#[tokio::test]
+async fn named_workspaces_are_not_special() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            // Named workspaces get ids from -1337 down
+            Event::Move("a", -1337),
+        ])
+        .await;
+
+    assert_eq!(harness.moves, vec![Moved::Learned]);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1, -1337]);
+}
+
+#[tokio::test]
+async fn move_to_named_workspace() {
+    for workspace_id in [5, -1337] {
+        let mut harness = Harness::new(StateBuilder::default());
+        harness
+            .run(&[
+                Event::Workspace(1),
+                Event::Open("a", "spotify", 1),
+                Event::Wait(5),
+                Event::MoveNamed("a", workspace_id, "music"),
+                Event::Close("a"),
+                Event::Open("b", "spotify", 1),
+            ])
+            .await;
+
+        // By name, so hyprland creates the workspace again if it is gone
+        assert_eq!(
+            harness.dispatched,
+            vec![Action::MoveToWorkspace(
+                Target::Name("music".to_string()),
+                Address::new("b")
+            )]
+        );
+    }
+}
+
+#[tokio::test]
+async fn transient_move_is_not_learned() {
+    let mut harness = Harness::new(StateBuilder::default().ignore_transient(true));
+    let mut dialog = client("b", "gimp", 1);
+    dialog.floating = true;
+    harness
+        .hypr
+        .set_clients(vec![client("a", "gimp", 1), dialog]);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "gimp", 1),
+            Event::Open("b", "gimp", 1),
+            Event::Wait(5),
+            Event::Move("b", 2),
+            Event::Move("a", 3),
+        ])
+        .await;
+
+    assert_eq!(harness.moves, vec![Moved::NotLearned, Moved::Learned]);
+    assert_eq!(history(&harness.persisted().await, "gimp"), vec![1, 3]);
+}
+
+// Both monitors mirror workspace 3, the second one is focused
+async fn mirrored(tie_break: TieBreak) -> Vec<Action> {
+    let mut harness = Harness::new(StateBuilder::default().tie_break(tie_break));
+    harness.hypr.set_monitors(vec![
+        monitor(0, "DP-1", false, 3),
+        monitor(1, "DP-2", true, 3),
+    ]);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Close("a"),
+            Event::Open("b", "kitty", 1),
+        ])
+        .await;
+    harness.dispatched
+}
+
+#[tokio::test]
+async fn tie_break_active_monitor() {
+    assert_eq!(
+        mirrored(TieBreak::ActiveMonitor).await,
+        vec![Action::FocusMonitor("DP-2".to_string()), moved_to("b", 3)]
+    );
+}
+
+#[tokio::test]
+async fn tie_break_lowest_monitor() {
+    assert_eq!(
+        mirrored(TieBreak::LowestMonitor).await,
+        vec![Action::FocusMonitor("DP-1".to_string()), moved_to("b", 3)]
+    );
+}
+
+#[tokio::test]
+async fn portable_follows_renumbered_workspaces() {
+    let mut harness = Harness::new(StateBuilder::default().portable(true));
+    harness.hypr.set_workspaces(workspaces(&[
+        (1, "DP-1"),
+        (2, "DP-1"),
+        (3, "HDMI-A-1"),
+        (4, "HDMI-A-1"),
+    ]));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            // The second workspace on HDMI-A-1
+            Event::Move("a", 4),
+            Event::Close("a"),
+        ])
+        .await;
+    // Plugged back in, HDMI-A-1 holds other ids
+    harness.hypr.set_workspaces(workspaces(&[
+        (1, "DP-1"),
+        (2, "DP-1"),
+        (5, "HDMI-A-1"),
+        (6, "HDMI-A-1"),
+    ]));
+    harness.run(&[Event::Open("b", "kitty", 1)]).await;
+
+    assert_eq!(harness.dispatched, vec![moved_to("b", 6)]);
+}
+
+#[tokio::test]
+async fn portable_keeps_the_id_when_the_output_is_gone() {
+    let mut harness = Harness::new(StateBuilder::default().portable(true));
+    harness
+        .hypr
+        .set_workspaces(workspaces(&[(1, "DP-1"), (4, "HDMI-A-1")]));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 4),
+            Event::Close("a"),
+        ])
+        .await;
+    harness.hypr.set_workspaces(workspaces(&[(1, "DP-1")]));
+    harness.run(&[Event::Open("b", "kitty", 1)]).await;
+
+    assert_eq!(harness.dispatched, vec![moved_to("b", 4)]);
+}
+
+#[tokio::test]
+async fn disabled_workspace_places_nothing() {
+    let builder = StateBuilder::default()
+        .workspace_enabled(false)
+        .new_program_target(Some(WorkspaceId(8)));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "kitty", 1)])
+        .await;
+
+    assert_eq!(harness.dispatched, vec![]);
+}
+
+// Opens a window from workspace 2 and closes it from workspace 5
+async fn close_away(restore_enabled: bool) -> Vec<Action> {
+    let builder = StateBuilder::default()
+        .restore_enabled(restore_enabled)
+        .restore_filter(
+            Matcher::new(&["pavucontrol".to_string()]),
+            FilterMode::Include,
+        );
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(2),
+            Event::Open("a", "pavucontrol", 2),
+            Event::Workspace(5),
+            Event::Wait(5),
+            Event::Close("a"),
+        ])
+        .await;
+    harness.dispatched
+}
+
+#[tokio::test]
+async fn disabled_restore_restores_nothing() {
+    assert_eq!(
+        close_away(true).await,
+        vec![Action::Workspace(Target::Id(WorkspaceId(2)))]
+    );
+    assert_eq!(close_away(false).await, vec![]);
+}
+
+// Opens a second window of a program that has floating geometry learned
+async fn float_restore(floating_enabled: bool) -> Vec<Action> {
+    let builder = StateBuilder::default()
+        .floating_enabled(floating_enabled)
+        .floating_filter(
+            Matcher::new(&["pavucontrol".to_string()]),
+            FilterMode::Include,
+        );
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "pavucontrol", 1)])
+        .await;
+    harness
+        .state
+        .add_floating_window(
+            "pavucontrol",
+            FloatingWindow::new((100, 100), (600, 400), PositionMode::Exact, None),
+        )
+        .await
+        .unwrap();
+    harness.run(&[Event::Open("b", "pavucontrol", 1)]).await;
+    harness.dispatched
+}
+
+#[tokio::test]
+async fn disabled_floating_moves_nothing() {
+    assert!(!float_restore(true).await.is_empty());
+    assert_eq!(float_restore(false).await, vec![]);
+}
+
+// A kitty window learned on workspace 3 that is back on 1 after monitors changed
+async fn monitors_changed(action: MonitorChange, quiet: bool) -> (Option<usize>, Vec<Action>) {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Move("a", 3),
+        ])
+        .await;
+    harness.hypr.set_clients(vec![client("a", "kitty", 1)]);
+    let moved = harness.state.monitors_changed(action, quiet).await.unwrap();
+    harness.echo().await;
+    (moved, harness.dispatched)
+}
+
+#[tokio::test]
+async fn monitor_change_reapply() {
+    assert_eq!(
+        monitors_changed(MonitorChange::Reapply, false).await,
+        (Some(1), vec![moved_to("a", 3)])
+    );
+}
+
+#[tokio::test]
+async fn monitor_change_does_nothing() {
+    assert_eq!(
+        monitors_changed(MonitorChange::Nothing, false).await,
+        (None, vec![])
+    );
+    // Quiet hours hold the reapply back
+    assert_eq!(
+        monitors_changed(MonitorChange::Reapply, true).await,
+        (None, vec![])
+    );
+}
+
+fn origin(windows: &[(Address, Window)], address: &str) -> i32 {
+    windows
+        .iter()
+        .find(|(val, _)| *val == Address::new(address))
+        .map(|(_, window)| window.origin.0)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn live_workspace_wins_while_settling() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness.hypr.set_active_workspace(WorkspaceInfo {
+        id: WorkspaceId(2),
+        name: "2".to_string(),
+        monitor: "DP-1".to_string(),
+    });
+    // An event from before startup arrives after hyprland already moved on
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "kitty", 2)])
+        .await;
+    assert_eq!(origin(&harness.state.windows().await, "a"), 2);
+
+    // Once settled events are trusted over the query
+    harness.state.settle();
+    harness
+        .run(&[Event::Workspace(4), Event::Open("b", "kitty", 4)])
+        .await;
+    assert_eq!(origin(&harness.state.windows().await, "b"), 4);
+}
+
+#[tokio::test]
+async fn live_counts_follow_opens_and_closes() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "kitty", 1),
+            Event::Open("c", "foot", 1),
+            // A repeated open event is not another window
+            Event::Open("a", "kitty", 1),
+        ])
+        .await;
+    assert_eq!(harness.state.live_count("kitty").await, 2);
+    assert_eq!(harness.state.live_count("foot").await, 1);
+
+    harness
+        .run(&[
+            Event::Close("a"),
+            Event::Close("unknown"),
+            Event::Close("a"),
+        ])
+        .await;
+    assert_eq!(harness.state.live_count("kitty").await, 1);
+    assert_eq!(harness.state.live_count("foot").await, 1);
+    assert_eq!(
+        harness.removed,
+        vec![
+            RemoveResult::NotRestored,
+            RemoveResult::NotTracked,
+            RemoveResult::NotTracked
+        ]
+    );
+
+    harness.run(&[Event::Close("b")]).await;
+    assert_eq!(harness.state.live_count("kitty").await, 0);
+}
+
+#[tokio::test]
+async fn aggregate_keeps_one_entry_per_workspace() {
+    let mut harness = Harness::new(StateBuilder::default().aggregate(true));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Move("a", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+        ])
+        .await;
+
+    let programs = harness.persisted().await;
+    assert_eq!(history(&programs, "kitty"), vec![1, 3]);
+    let weights: Vec<f64> = programs[0]
+        .workspaces
+        .iter()
+        .map(|val| val.weight)
+        .collect();
+    // Both moves to 3 are in its weight, barely decayed
+    assert!(weights[1] > 1.9 && weights[1] <= 2.0);
+}
+
+#[tokio::test]
+async fn forget_program_with_open_windows() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "kitty", 1),
+            Event::Open("c", "foot", 1),
+        ])
+        .await;
+    harness.state.changed.store(false, Ordering::Relaxed);
+
+    assert!(harness.state.forget_program("kitty").await);
+    assert!(harness.state.changed.load(Ordering::Relaxed));
+    assert_eq!(harness.state.live_count("kitty").await, 0);
+    assert_eq!(harness.state.live_count("foot").await, 1);
+    assert!(
+        harness
+            .state
+            .get_program("kitty".to_string())
+            .await
+            .is_none()
+    );
+    // The windows went with it, closing them is nothing to nest
+    harness.run(&[Event::Close("a")]).await;
+    assert_eq!(harness.removed, vec![RemoveResult::NotTracked]);
+    assert_eq!(history(&harness.persisted().await, "foot"), vec![1]);
+    assert!(history(&harness.persisted().await, "kitty").is_empty());
+}
+
+#[tokio::test]
+async fn forget_program_without_open_windows() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Close("a"),
+        ])
+        .await;
+
+    assert!(harness.state.forget_program("kitty").await);
+    assert!(
+        harness
+            .state
+            .get_program("kitty".to_string())
+            .await
+            .is_none()
+    );
+    // Nothing left to forget
+    assert!(!harness.state.forget_program("kitty").await);
+    assert!(!harness.state.forget_program("foot").await);
+}
+
+#[tokio::test]
+async fn restored_geometry_is_not_learned_back() {
+    let builder = StateBuilder::default().floating_filter(
+        Matcher::new(&["pavucontrol".to_string()]),
+        FilterMode::Include,
+    );
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "pavucontrol", 1)])
+        .await;
+    harness
+        .state
+        .add_floating_window(
+            "pavucontrol",
+            FloatingWindow::new((100, 100), (600, 400), PositionMode::Exact, None),
+        )
+        .await
+        .unwrap();
+    harness.run(&[Event::Open("b", "pavucontrol", 1)]).await;
+    harness.state.changed.store(false, Ordering::Relaxed);
+
+    // The poll right after the restore skips the class once
+    assert!(harness.state.take_float_moved("pavucontrol").await);
+    assert!(!harness.state.take_float_moved("pavucontrol").await);
+    // Later polls reading it back a pixel off change nothing, so nothing is saved
+    harness
+        .state
+        .add_floating_window(
+            "pavucontrol",
+            FloatingWindow::new((101, 99), (600, 401), PositionMode::Exact, None),
+        )
+        .await
+        .unwrap();
+    assert!(!harness.state.changed.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn focus_counts_less_than_a_move() {
+    let mut harness = Harness::new(StateBuilder::default().focus_weight(0.25));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Workspace(2),
+            Event::Focus("a"),
+            Event::Wait(5),
+            Event::Focus("a"),
+            Event::Wait(5),
+            Event::Workspace(3),
+            Event::Move("a", 3),
+            Event::Close("a"),
+            Event::Workspace(2),
+            Event::Open("b", "kitty", 2),
+        ])
+        .await;
+
+    let programs = harness.persisted().await;
+    // Focusing again on the workspace the history ends on adds nothing
+    assert_eq!(history(&programs, "kitty"), vec![1, 2, 3]);
+    let weights: Vec<f64> = programs[0]
+        .workspaces
+        .iter()
+        .map(|val| val.weight)
+        .collect();
+    assert_eq!(weights, vec![1.0, 0.25, 1.0]);
+    // The open workspace only has the focus behind it
+    assert_eq!(harness.dispatched, vec![moved_to("b", 3)]);
+}
+
+#[tokio::test]
+async fn opened_on_the_prediction() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Close("a"),
+            Event::Workspace(3),
+            Event::Open("b", "kitty", 3),
+        ])
+        .await;
+
+    assert_eq!(harness.dispatched, vec![]);
+    assert!(matches!(
+        harness.outcomes[1].placement,
+        Placement::AlreadyThere(WorkspaceId(3))
+    ));
+}
+
+#[tokio::test]
+async fn opened_on_the_prediction_by_name() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Close("a"),
+        ])
+        .await;
+    // The event only names the workspace, where the window is comes from hyprland
+    harness.hypr.set_clients(vec![client("b", "kitty", 3)]);
+    let outcome = harness
+        .state
+        .handle_open("kitty", &Address::new("b"), "work", false)
+        .await;
+    harness.echo().await;
+
+    assert_eq!(harness.dispatched, vec![]);
+    assert!(matches!(
+        outcome.placement,
+        Placement::AlreadyThere(WorkspaceId(3))
+    ));
+}
+
+#[tokio::test]
+async fn duplicate_open_keeps_the_origin() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[Event::Workspace(2), Event::Open("a", "kitty", 2)])
+        .await;
+    let opened = harness.state.windows().await[0].1.timestamp;
+    harness
+        .run(&[
+            Event::Workspace(5),
+            Event::Wait(5),
+            Event::Open("a", "kitty", 5),
+        ])
+        .await;
+
+    let windows = harness.state.windows().await;
+    assert_eq!(windows.len(), 1);
+    assert_eq!(origin(&windows, "a"), 2);
+    assert_eq!(windows[0].1.timestamp, opened);
+    assert!(matches!(
+        harness.outcomes[1].placement,
+        Placement::Untracked
+    ));
+    assert_eq!(harness.state.live_count("kitty").await, 1);
+}
+
+#[tokio::test]
+async fn prefetched_predictions_until_the_history_changes() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Workspace(1),
+        ])
+        .await;
+    let state = &harness.state;
+    state.prefetch(WorkspaceId(1)).await;
+    let (workspace_id, score) = state.predict("kitty", None).await.unwrap();
+    assert_eq!(workspace_id, WorkspaceId(3));
+
+    // A day of decay would lower the score, the cached one is used as is
+    harness.clock.advance(Duration::days(1));
+    assert_eq!(
+        harness.state.predict("kitty", None).await,
+        Some((WorkspaceId(3), score))
+    );
+    // Other workspaces were not prefetched
+    harness.run(&[Event::Workspace(2)]).await;
+    let (_, uncached) = harness.state.predict("kitty", None).await.unwrap();
+    assert!(uncached < score);
+
+    // Learning drops the cache
+    harness
+        .run(&[Event::Workspace(1), Event::Move("a", 3)])
+        .await;
+    let (_, learned) = harness.state.predict("kitty", None).await.unwrap();
+    assert_ne!(learned, score);
+}
+
+// A window opened on workspace 4 while 2 is active, the history only holds workspaces outside
+// of the range
+async fn no_prediction(on_no_prediction: NoPrediction) -> (Placement, Vec<Action>) {
+    let builder = StateBuilder::default()
+        .workspace_range(5..=10)
+        .on_no_prediction(on_no_prediction)
+        .default_workspace(WorkspaceId(7));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[Event::Workspace(2), Event::Open("a", "kitty", 4)])
+        .await;
+    (harness.outcomes.remove(0).placement, harness.dispatched)
+}
+
+#[tokio::test]
+async fn no_prediction_branches() {
+    let (placement, dispatched) = no_prediction(NoPrediction::Current).await;
+    assert!(matches!(placement, Placement::Moved(WorkspaceId(2))));
+    assert_eq!(dispatched, vec![moved_to("a", 2)]);
+
+    let (placement, dispatched) = no_prediction(NoPrediction::Default).await;
+    assert!(matches!(placement, Placement::Moved(WorkspaceId(7))));
+    assert_eq!(dispatched, vec![moved_to("a", 7)]);
+
+    for on_no_prediction in [NoPrediction::Leave, NoPrediction::Notify] {
+        let (placement, dispatched) = no_prediction(on_no_prediction).await;
+        assert!(matches!(placement, Placement::NoPrediction));
+        assert_eq!(dispatched, vec![]);
+    }
+}
+
+#[tokio::test]
+async fn boost_wins_until_it_expires() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Move("a", 3),
+        ])
+        .await;
+    let state = &harness.state;
+    let (workspace_id, score) = state.predict("kitty", None).await.unwrap();
+    assert_eq!(workspace_id, WorkspaceId(3));
+
+    // Workspaces the program was never on are not made up
+    state.boost(WorkspaceId(5), 10.0, 60).await;
+    assert_eq!(
+        state.predict("kitty", None).await,
+        Some((WorkspaceId(3), score))
+    );
+
+    state.boost(WorkspaceId(1), 10.0, 60).await;
+    let (workspace_id, boosted) = state.predict("kitty", None).await.unwrap();
+    assert_eq!(workspace_id, WorkspaceId(1));
+    assert!(boosted > 10.0);
+
+    harness.run(&[Event::Wait(61)]).await;
+    let (workspace_id, _) = harness.state.predict("kitty", None).await.unwrap();
+    assert_eq!(workspace_id, WorkspaceId(3));
+    // Nothing of the boost was kept
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1, 3, 3]);
+}
+
+// Decay takes a little off every move, the third one still counts
+const MIN_SCORE: f64 = 2.5;
+
+// Opened on 1 and moved to 3 `moves` times, asking after every move the way main does
+async fn milestones(moves: usize, seed: bool) -> Vec<Option<(String, WorkspaceId)>> {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "kitty", 1)])
+        .await;
+    if seed {
+        harness.state.seed_confident(0.7, MIN_SCORE).await;
+    }
+    let mut crossed = Vec::new();
+    for _ in 0..moves {
+        harness.run(&[Event::Wait(5), Event::Move("a", 3)]).await;
+        assert_eq!(harness.moves.last(), Some(&Moved::Learned));
+        crossed.push(
+            harness
+                .state
+                .newly_confident(&Address::new("a"), 0.7, MIN_SCORE)
+                .await,
+        );
+    }
+    crossed
+}
+
+#[tokio::test]
+async fn milestone_fires_once() {
+    assert_eq!(
+        milestones(5, false).await,
+        vec![
+            None,
+            None,
+            Some(("kitty".to_string(), WorkspaceId(3))),
+            None,
+            None
+        ]
+    );
+}
+
+#[tokio::test]
+async fn seeded_milestone_does_not_fire() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Move("a", 3),
+            Event::Wait(5),
+            Event::Move("a", 3),
+        ])
+        .await;
+    // What a restart knows, the program was confident before it
+    harness.state.seed_confident(0.7, MIN_SCORE).await;
+    harness.run(&[Event::Wait(5), Event::Move("a", 3)]).await;
+    assert_eq!(
+        harness
+            .state
+            .newly_confident(&Address::new("a"), 0.7, MIN_SCORE)
+            .await,
+        None
+    );
+    // Programs that were not confident yet are still reported
+    assert_eq!(
+        milestones(3, true).await[2],
+        Some(("kitty".to_string(), WorkspaceId(3)))
+    );
+}
+
+#[tokio::test]
+async fn outcomes_for_seeded_state() {
+    let builder = StateBuilder::default()
+        .on_no_prediction(NoPrediction::Leave)
+        .floating_filter(
+            Matcher::new(&["pavucontrol".to_string()]),
+            FilterMode::Include,
+        );
+    let mut harness = Harness::new(builder);
+    let now = harness.clock.now().timestamp();
+    let programs = [
+        format!("firefox:[2;{now};3,5;{now};1]&[]"),
+        "pavucontrol:[]&[100;100;600;400]".to_string(),
+    ];
+    let programs = programs.iter().map(|val| val.parse().unwrap()).collect();
+    harness.state.reload(programs, false).await;
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "firefox", 1),
+            Event::Open("b", "firefox", 2),
+            Event::Open("c", "pavucontrol", 1),
+        ])
+        .await;
+
+    let outcome = &harness.outcomes[0];
+    assert_eq!(outcome.prediction, Some((WorkspaceId(2), 3.0)));
+    assert_eq!(outcome.runner_up, Some((WorkspaceId(5), 1.0)));
+    assert!(matches!(
+        outcome.placement,
+        Placement::Moved(WorkspaceId(2))
+    ));
+    assert!(matches!(outcome.floating, FloatPlacement::NoGeometry));
+
+    let outcome = &harness.outcomes[1];
+    assert!(matches!(
+        outcome.placement,
+        Placement::AlreadyThere(WorkspaceId(2))
+    ));
+
+    // Only the geometry was learned
+    let outcome = &harness.outcomes[2];
+    assert_eq!(outcome.prediction, None);
+    assert!(matches!(outcome.placement, Placement::NoPrediction));
+    assert!(matches!(
+        outcome.floating,
+        FloatPlacement::Moved(FloatingWindow { at: (100, 100), .. })
+    ));
+    assert_eq!(harness.dispatched[0], moved_to("a", 2));
+}
+
+// Restores remembered geometry onto a window while hyprland refuses some of the dispatches,
+// returns what was dispatched for it
+async fn float_failure(
+    fails: fn(&Action) -> bool,
+    floating: bool,
+) -> (FloatPlacement, Vec<Action>) {
+    let builder = StateBuilder::default().floating_filter(
+        Matcher::new(&["pavucontrol".to_string()]),
+        FilterMode::Include,
+    );
+    let mut harness = Harness::new(builder);
+    let programs = vec!["pavucontrol:[]&[100;100;600;400]".parse().unwrap()];
+    harness.state.reload(programs, false).await;
+    harness.hypr.set_clients(vec![ClientInfo {
+        floating,
+        ..client("a", "pavucontrol", 1)
+    }]);
+    harness.hypr.fail(fails);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "pavucontrol", 1)])
+        .await;
+    (harness.outcomes.remove(0).floating, harness.dispatched)
+}
+
+#[tokio::test]
+async fn failed_float_move_is_tiled_again() {
+    let toggle = Action::ToggleFloating(Address::new("a"));
+    let moved = Action::MoveWindowPixel((100, 100), Address::new("a"));
+    let resized = Action::ResizeWindowPixel((600, 400), Address::new("a"));
+
+    let (floating, dispatched) = float_failure(
+        |action| matches!(action, Action::MoveWindowPixel(..)),
+        false,
+    )
+    .await;
+    assert!(matches!(floating, FloatPlacement::Failed(_)));
+    assert_eq!(
+        dispatched,
+        vec![toggle.clone(), moved.clone(), toggle.clone()]
+    );
+
+    let (floating, dispatched) = float_failure(
+        |action| matches!(action, Action::ResizeWindowPixel(..)),
+        false,
+    )
+    .await;
+    assert!(matches!(floating, FloatPlacement::Failed(_)));
+    assert_eq!(
+        dispatched,
+        vec![
+            toggle.clone(),
+            moved.clone(),
+            resized.clone(),
+            toggle.clone()
+        ]
+    );
+
+    // A window that opened floating was never toggled, so it is left floating
+    let (floating, dispatched) =
+        float_failure(|action| matches!(action, Action::MoveWindowPixel(..)), true).await;
+    assert!(matches!(floating, FloatPlacement::Failed(_)));
+    assert_eq!(dispatched, vec![moved.clone()]);
+
+    let (floating, dispatched) = float_failure(|_| false, false).await;
+    assert!(matches!(floating, FloatPlacement::Moved(_)));
+    assert_eq!(dispatched, vec![toggle, moved, resized]);
+}
+
+// Firefox asks before it is moved, its history points at workspace 2
+fn asking() -> Harness {
+    let builder = StateBuilder::default()
+        .ask_matcher(Matcher::new(&["firefox".to_string()]))
+        .ask_timeout_secs(30);
+    Harness::new(builder)
+}
+
+async fn seed_firefox(harness: &Harness) {
+    let now = harness.clock.now().timestamp();
+    let programs = vec![format!("firefox:[2;{now}]&[]").parse().unwrap()];
+    harness.state.reload(programs, false).await;
+}
+
+#[tokio::test]
+async fn confirmed_move() {
+    let mut harness = asking();
+    seed_firefox(&harness).await;
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "firefox", 1)])
+        .await;
+    assert!(matches!(
+        harness.outcomes[0].placement,
+        Placement::Asked(WorkspaceId(2))
+    ));
+    assert_eq!(harness.dispatched, vec![]);
+
+    // Only moves of the named class are confirmed
+    assert!(matches!(
+        harness.state.confirm(Some("kitty")).await,
+        Err(Error::NothingPending)
+    ));
+    harness.run(&[Event::Wait(10)]).await;
+    let (class, placement) = harness.state.confirm(Some("firefox")).await.unwrap();
+    harness.echo().await;
+    assert_eq!(class, "firefox");
+    assert!(matches!(placement, Placement::Moved(WorkspaceId(2))));
+    assert_eq!(harness.dispatched, vec![moved_to("a", 2)]);
+    // Confirmed once
+    assert!(matches!(
+        harness.state.confirm(None).await,
+        Err(Error::NothingPending)
+    ));
+}
+
+#[tokio::test]
+async fn unconfirmed_move_times_out() {
+    let mut harness = asking();
+    seed_firefox(&harness).await;
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "firefox", 1),
+            Event::Wait(31),
+        ])
+        .await;
+    assert!(matches!(
+        harness.state.confirm(None).await,
+        Err(Error::NothingPending)
+    ));
+    harness.echo().await;
+    assert_eq!(harness.dispatched, vec![]);
+    // The window was left where it opened and nothing was learned
+    assert_eq!(history(&harness.persisted().await, "firefox"), vec![2]);
+}
+
+#[tokio::test]
+async fn cooldown_suppresses_rapid_moves() {
+    let mut harness = Harness::new(StateBuilder::default().move_cooldown_ms(1000));
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", "kitty", 1)])
+        .await;
+    let address = Address::new("a");
+    let state = &harness.state;
+    assert_eq!(
+        state.move_window(&address, WorkspaceId(3)).await.unwrap(),
+        MoveResult::Moved
+    );
+    // A bounced event right after is dropped
+    assert_eq!(
+        state.move_window(&address, WorkspaceId(4)).await.unwrap(),
+        MoveResult::Filtered
+    );
+    // Other windows are not held back
+    harness.run(&[Event::Open("b", "kitty", 1)]).await;
+    assert_eq!(
+        harness
+            .state
+            .move_window(&Address::new("b"), WorkspaceId(4))
+            .await
+            .unwrap(),
+        MoveResult::Moved
+    );
+
+    harness.run(&[Event::Wait(2)]).await;
+    assert_eq!(
+        harness
+            .state
+            .move_window(&address, WorkspaceId(4))
+            .await
+            .unwrap(),
+        MoveResult::Moved
+    );
+    harness.echo().await;
+    let moves: Vec<Action> = harness
+        .dispatched
+        .into_iter()
+        .filter(|action| matches!(action, Action::MoveToWorkspace(..)))
+        .collect();
+    assert_eq!(
+        moves,
+        vec![moved_to("a", 3), moved_to("b", 4), moved_to("a", 4)]
+    );
+}
+
+// Opens pavucontrol from workspace 2 and closes it from workspace 5 with `on_close` set
+async fn close_with(on_close: Option<OnClose>) -> (Vec<Action>, RemoveResult) {
+    let builder = StateBuilder::default()
+        .restore_filter(
+            Matcher::new(&["pavucontrol".to_string()]),
+            FilterMode::Include,
+        )
+        .overrides(HashMap::from([(
+            "pavucontrol".to_string(),
+            ClassOverride {
+                on_close,
+                ..ClassOverride::default()
+            },
+        )]));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(2),
+            Event::Open("a", "pavucontrol", 2),
+            Event::Workspace(5),
+            Event::Wait(5),
+            Event::Close("a"),
+        ])
+        .await;
+    (harness.dispatched, harness.removed.remove(0))
+}
+
+#[tokio::test]
+async fn on_close_actions() {
+    let restored = (
+        vec![Action::Workspace(Target::Id(WorkspaceId(2)))],
+        RemoveResult::Restored,
+    );
+    assert_eq!(close_with(None).await, restored);
+    assert_eq!(close_with(Some(OnClose::Restore)).await, restored);
+    assert_eq!(
+        close_with(Some(OnClose::None)).await,
+        (vec![], RemoveResult::NotRestored)
+    );
+}
+
+#[tokio::test]
+async fn on_close_exec_runs_the_command() {
+    let marker = std::env::temp_dir().join(format!("nest-on-close-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let command = format!(
+        "printf '%s %s' \"$NEST_CLASS\" \"$NEST_ADDRESS\" > {}",
+        marker.display()
+    );
+    assert_eq!(
+        close_with(Some(OnClose::Exec(command))).await,
+        (vec![], RemoveResult::NotRestored)
+    );
+    // The command runs in the background
+    for _ in 0..100 {
+        if std::fs::read_to_string(&marker).is_ok_and(|val| !val.is_empty()) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(std::fs::read_to_string(&marker).unwrap(), "pavucontrol 0xa");
+    std::fs::remove_file(marker).unwrap();
+}
+
+#[tokio::test]
+async fn user_and_programmatic_moves() {
+    let mut harness = Harness::new(StateBuilder::default().learn_user_moves_only(true));
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "foot", 1),
+            Event::Wait(5),
+        ])
+        .await;
+    harness.state.focus_changed(Address::new("a")).await;
+    // Users move the focused window
+    harness
+        .run(&[Event::Move("b", 3), Event::Move("a", 3), Event::Wait(5)])
+        .await;
+    // Or the one they focused away from just before
+    harness.state.focus_changed(Address::new("b")).await;
+    harness
+        .run(&[Event::Move("a", 4), Event::Wait(5), Event::Move("a", 5)])
+        .await;
+    // A window moving itself right after it opened is never the user
+    harness.state.focus_changed(Address::new("c")).await;
+    harness
+        .run(&[Event::Open("c", "mpv", 1), Event::Move("c", 6)])
+        .await;
+    assert_eq!(
+        harness.moves,
+        vec![
+            Moved::NotUser,
+            Moved::Learned,
+            Moved::Learned,
+            Moved::NotUser,
+            Moved::NotUser
+        ]
+    );
+    let programs = harness.persisted().await;
+    assert_eq!(history(&programs, "kitty"), vec![1, 3, 4]);
+    assert_eq!(history(&programs, "foot"), vec![1]);
+}
+
+#[tokio::test]
+async fn every_move_is_the_users_by_default() {
+    let mut harness = Harness::new(StateBuilder::default());
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Wait(5),
+            Event::Move("a", 3),
+        ])
+        .await;
+    assert_eq!(harness.moves, vec![Moved::Learned]);
+}
+
+// Moves window "a" of `class` to workspace 3 while it is on `on` and hyprland refuses moves
+// when `refused` is set
+async fn move_result(class: &'static str, on: i32, refused: bool) -> MoveResult {
+    let builder = StateBuilder::default()
+        .workspace_filter(Matcher::new(&["steam".to_string()]), FilterMode::Exclude);
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[Event::Workspace(1), Event::Open("a", class, 1)])
+        .await;
+    harness.hypr.set_clients(vec![client("a", class, on)]);
+    if refused {
+        harness
+            .hypr
+            .fail(|action| matches!(action, Action::MoveToWorkspace(..)));
+    }
+    harness
+        .state
+        .move_window(&Address::new("a"), WorkspaceId(3))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn move_results() {
+    assert_eq!(move_result("kitty", 1, false).await, MoveResult::Moved);
+    assert_eq!(move_result("steam", 1, false).await, MoveResult::Filtered);
+    assert_eq!(move_result("kitty", 1, true).await, MoveResult::Failed);
+    // Hyprland refuses to move a window onto the workspace it is on
+    assert_eq!(
+        move_result("kitty", 3, true).await,
+        MoveResult::AlreadyThere
+    );
+
+    let harness = Harness::new(StateBuilder::default());
+    assert!(matches!(
+        harness
+            .state
+            .move_window(&Address::new("a"), WorkspaceId(3))
+            .await,
+        Err(Error::BlankAddress)
+    ));
+}
+
+#[tokio::test]
+async fn remove_results() {
+    let builder = StateBuilder::default().restore_filter(
+        Matcher::new(&["pavucontrol".to_string()]),
+        FilterMode::Include,
+    );
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(2),
+            Event::Open("a", "pavucontrol", 2),
+            Event::Open("b", "kitty", 2),
+            Event::Workspace(5),
+            Event::Wait(5),
+            // Restoring only applies to pavucontrol
+            Event::Close("b"),
+            Event::Close("a"),
+            // Closed already
+            Event::Close("a"),
+            Event::Close("c"),
+        ])
+        .await;
+    assert_eq!(
+        harness.removed,
+        vec![
+            RemoveResult::NotRestored,
+            RemoveResult::Restored,
+            RemoveResult::NotTracked,
+            RemoveResult::NotTracked
+        ]
+    );
+}
+
+// Moves `address` to `workspace_id` and tells whether the state needs saving afterwards
+async fn move_flags_changed(
+    harness: &mut Harness,
+    address: &'static str,
+    workspace_id: i32,
+) -> bool {
+    harness.state.changed.store(false, Ordering::Relaxed);
+    harness.run(&[Event::Move(address, workspace_id)]).await;
+    harness.state.changed.load(Ordering::Relaxed)
+}
+
+#[tokio::test]
+async fn moves_within_the_learn_interval_stay_clean() {
+    let builder = StateBuilder::default()
+        .learn_interval_secs(60)
+        .overrides(HashMap::from([(
+            "foot".to_string(),
+            ClassOverride {
+                learn_interval_secs: Some(0),
+                ..ClassOverride::default()
+            },
+        )]));
+    let mut harness = Harness::new(builder);
+    harness
+        .run(&[
+            Event::Workspace(1),
+            Event::Open("a", "kitty", 1),
+            Event::Open("b", "kitty", 1),
+            Event::Open("c", "foot", 1),
+            Event::Wait(5),
+        ])
+        .await;
+    assert!(move_flags_changed(&mut harness, "a", 3).await);
+    harness.run(&[Event::Wait(10)]).await;
+    // The interval is per class, not per window
+    assert!(!move_flags_changed(&mut harness, "a", 4).await);
+    assert!(!move_flags_changed(&mut harness, "b", 4).await);
+    // Foot learns every move
+    assert!(move_flags_changed(&mut harness, "c", 3).await);
+    assert!(move_flags_changed(&mut harness, "c", 4).await);
+
+    harness.run(&[Event::Wait(60)]).await;
+    assert!(move_flags_changed(&mut harness, "a", 5).await);
+    assert_eq!(history(&harness.persisted().await, "kitty"), vec![1, 3, 5]);
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+// Answer to the ping command, kept small so watchdogs can parse it cheaply
+#[derive(Clone, Debug, Serialize)]
+pub struct Health {
+    pub uptime: i64,
+    pub programs: usize,
+    pub last_save: Option<i64>,
+    pub connected: bool,
+}
+
+// Answer to the status command, waybar modules read these fields so they should not change
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub workspace: Option<i32>,
+    pub programs: usize,
+    // Seconds since the state was last written, none before the first save
+    pub last_save_age: Option<i64>,
+    pub healthy: bool,
+}
+
+// Changes made through the command socket that only last until nest restarts
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionOverrides {
+    pub disabled: Vec<String>,
+    pub boosts: Vec<Boost>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Boost {
+    pub workspace: i32,
+    pub weight: f64,
+    // Seconds until the boost runs out
+    pub remaining: i64,
+}
@@ -0,0 +1,299 @@
+use std::{fmt::Debug, future::Future, pin::Pin};
+
+use hyprland::{
+    data::{Client, Clients, Monitor, Monitors, Workspace, Workspaces},
+    dispatch::{
+        Dispatch, DispatchType, MonitorIdentifier, Position, WindowIdentifier,
+        WorkspaceIdentifierWithSpecial,
+    },
+    error::HyprError,
+    shared::{Address, HyprData, HyprDataActive, HyprDataActiveOptional},
+};
+
+use crate::state::{MonitorId, WorkspaceId};
+
+pub type HyprFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, HyprError>> + Send + 'a>>;
+
+// Everything the state asks of or tells hyprland goes through here, so tests can stand in
+// for the compositor
+pub trait Hypr: Debug + Send + Sync {
+    fn dispatch(&self, action: Action) -> HyprFuture<'_, ()>;
+    fn clients(&self) -> HyprFuture<'_, Vec<ClientInfo>>;
+    fn active_client(&self) -> HyprFuture<'_, Option<ClientInfo>>;
+    fn monitors(&self) -> HyprFuture<'_, Vec<MonitorInfo>>;
+    fn workspaces(&self) -> HyprFuture<'_, Vec<WorkspaceInfo>>;
+    fn active_workspace(&self) -> HyprFuture<'_, WorkspaceInfo>;
+}
+
+// The dispatches nest makes, owned so they can be recorded
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    FocusMonitor(String),
+    Workspace(Target),
+    MoveToWorkspace(Target, Address),
+    ToggleFloating(Address),
+    MoveWindowPixel((i16, i16), Address),
+    ResizeWindowPixel((i16, i16), Address),
+    Custom(String, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Id(WorkspaceId),
+    Name(String),
+    // Special workspaces can only be targeted by name
+    Special(String),
+}
+
+// The parts of a window nest reads, owned so tests can make them up
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientInfo {
+    pub address: Address,
+    pub class: String,
+    pub workspace: WorkspaceId,
+    pub monitor: Option<MonitorId>,
+    pub floating: bool,
+    pub at: (i16, i16),
+    pub size: (i16, i16),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorInfo {
+    pub id: MonitorId,
+    pub name: String,
+    pub focused: bool,
+    pub active_workspace: WorkspaceId,
+    // 0 when no special workspace is shown
+    pub special_workspace: WorkspaceId,
+    pub x: i32,
+    pub y: i32,
+    pub width: u16,
+    pub height: u16,
+    pub scale: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkspaceInfo {
+    pub id: WorkspaceId,
+    pub name: String,
+    // Name of the output it is on
+    pub monitor: String,
+}
+
+impl From<Client> for ClientInfo {
+    fn from(client: Client) -> Self {
+        Self {
+            address: client.address,
+            class: client.class,
+            workspace: WorkspaceId(client.workspace.id),
+            monitor: client.monitor.map(MonitorId),
+            floating: client.floating,
+            at: client.at,
+            size: client.size,
+        }
+    }
+}
+
+impl From<Monitor> for MonitorInfo {
+    fn from(monitor: Monitor) -> Self {
+        Self {
+            id: MonitorId(monitor.id),
+            name: monitor.name,
+            focused: monitor.focused,
+            active_workspace: WorkspaceId(monitor.active_workspace.id),
+            special_workspace: WorkspaceId(monitor.special_workspace.id),
+            x: monitor.x,
+            y: monitor.y,
+            width: monitor.width,
+            height: monitor.height,
+            scale: monitor.scale,
+        }
+    }
+}
+
+impl From<Workspace> for WorkspaceInfo {
+    fn from(workspace: Workspace) -> Self {
+        Self {
+            id: WorkspaceId(workspace.id),
+            name: workspace.name,
+            monitor: workspace.monitor,
+        }
+    }
+}
+
+impl Target {
+    fn identifier(&self) -> WorkspaceIdentifierWithSpecial<'_> {
+        match self {
+            Target::Id(workspace_id) => WorkspaceIdentifierWithSpecial::Id(workspace_id.0),
+            Target::Name(name) => WorkspaceIdentifierWithSpecial::Name(name),
+            Target::Special(name) => WorkspaceIdentifierWithSpecial::Special(Some(name)),
+        }
+    }
+}
+
+impl Action {
+    fn dispatch_type(&self) -> DispatchType<'_> {
+        match self {
+            Action::FocusMonitor(name) => DispatchType::FocusMonitor(MonitorIdentifier::Name(name)),
+            Action::Workspace(target) => DispatchType::Workspace(target.identifier()),
+            Action::MoveToWorkspace(target, address) => DispatchType::MoveToWorkspace(
+                target.identifier(),
+                Some(WindowIdentifier::Address(address.clone())),
+            ),
+            Action::ToggleFloating(address) => {
+                DispatchType::ToggleFloating(Some(WindowIdentifier::Address(address.clone())))
+            }
+            Action::MoveWindowPixel(at, address) => DispatchType::MoveWindowPixel(
+                Position::Exact(at.0, at.1),
+                WindowIdentifier::Address(address.clone()),
+            ),
+            Action::ResizeWindowPixel(size, address) => DispatchType::ResizeWindowPixel(
+                Position::Exact(size.0, size.1),
+                WindowIdentifier::Address(address.clone()),
+            ),
+            Action::Custom(name, args) => DispatchType::Custom(name, args),
+        }
+    }
+}
+
+// The running compositor
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hyprland;
+
+impl Hypr for Hyprland {
+    fn dispatch(&self, action: Action) -> HyprFuture<'_, ()> {
+        Box::pin(async move { Dispatch::call_async(action.dispatch_type()).await })
+    }
+
+    fn clients(&self) -> HyprFuture<'_, Vec<ClientInfo>> {
+        Box::pin(async {
+            Ok(Clients::get_async()
+                .await?
+                .into_iter()
+                .map(ClientInfo::from)
+                .collect())
+        })
+    }
+
+    fn active_client(&self) -> HyprFuture<'_, Option<ClientInfo>> {
+        Box::pin(async { Ok(Client::get_active_async().await?.map(ClientInfo::from)) })
+    }
+
+    fn monitors(&self) -> HyprFuture<'_, Vec<MonitorInfo>> {
+        Box::pin(async {
+            Ok(Monitors::get_async()
+                .await?
+                .into_iter()
+                .map(MonitorInfo::from)
+                .collect())
+        })
+    }
+
+    fn workspaces(&self) -> HyprFuture<'_, Vec<WorkspaceInfo>> {
+        Box::pin(async {
+            Ok(Workspaces::get_async()
+                .await?
+                .into_iter()
+                .map(WorkspaceInfo::from)
+                .collect())
+        })
+    }
+
+    fn active_workspace(&self) -> HyprFuture<'_, WorkspaceInfo> {
+        Box::pin(async { Ok(Workspace::get_active_async().await?.into()) })
+    }
+}
+
+// Picks the dispatches the mock refuses
+#[cfg(test)]
+type Refuse = fn(&Action) -> bool;
+
+// Accepts every dispatch that is not set to fail and records it. Queries answer with what the
+// test set, by default no windows, monitors or workspaces, so the state falls back to what it
+// tracks itself
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockHypr {
+    dispatched: std::sync::Mutex<Vec<Action>>,
+    fail: std::sync::Mutex<Option<Refuse>>,
+    clients: std::sync::Mutex<Vec<ClientInfo>>,
+    monitors: std::sync::Mutex<Vec<MonitorInfo>>,
+    workspaces: std::sync::Mutex<Vec<WorkspaceInfo>>,
+    active_workspace: std::sync::Mutex<Option<WorkspaceInfo>>,
+}
+
+#[cfg(test)]
+impl MockHypr {
+    // Every dispatch since the last call, failed ones included
+    pub fn take(&self) -> Vec<Action> {
+        std::mem::take(&mut *self.dispatched.lock().unwrap())
+    }
+
+    // Dispatches `fails` returns true for are recorded and then refused
+    pub fn fail(&self, fails: Refuse) {
+        *self.fail.lock().unwrap() = Some(fails);
+    }
+
+    pub fn refuses(&self, action: &Action) -> bool {
+        self.fail.lock().unwrap().is_some_and(|fails| fails(action))
+    }
+
+    pub fn set_clients(&self, clients: Vec<ClientInfo>) {
+        *self.clients.lock().unwrap() = clients;
+    }
+
+    pub fn set_workspaces(&self, workspaces: Vec<WorkspaceInfo>) {
+        *self.workspaces.lock().unwrap() = workspaces;
+    }
+
+    pub fn set_active_workspace(&self, workspace: WorkspaceInfo) {
+        *self.active_workspace.lock().unwrap() = Some(workspace);
+    }
+
+    pub fn set_monitors(&self, monitors: Vec<MonitorInfo>) {
+        *self.monitors.lock().unwrap() = monitors;
+    }
+}
+
+#[cfg(test)]
+impl Hypr for MockHypr {
+    fn dispatch(&self, action: Action) -> HyprFuture<'_, ()> {
+        let fails = self.refuses(&action);
+        self.dispatched.lock().unwrap().push(action);
+        Box::pin(async move {
+            match fails {
+                true => Err(HyprError::IoError(std::io::Error::other(
+                    "dispatch refused",
+                ))),
+                false => Ok(()),
+            }
+        })
+    }
+
+    fn clients(&self) -> HyprFuture<'_, Vec<ClientInfo>> {
+        let clients = self.clients.lock().unwrap().clone();
+        Box::pin(async { Ok(clients) })
+    }
+
+    fn active_client(&self) -> HyprFuture<'_, Option<ClientInfo>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn monitors(&self) -> HyprFuture<'_, Vec<MonitorInfo>> {
+        let monitors = self.monitors.lock().unwrap().clone();
+        Box::pin(async { Ok(monitors) })
+    }
+
+    fn workspaces(&self) -> HyprFuture<'_, Vec<WorkspaceInfo>> {
+        let workspaces = self.workspaces.lock().unwrap().clone();
+        Box::pin(async { Ok(workspaces) })
+    }
+
+    fn active_workspace(&self) -> HyprFuture<'_, WorkspaceInfo> {
+        let workspace = self.active_workspace.lock().unwrap().clone();
+        Box::pin(async {
+            workspace
+                .ok_or_else(|| HyprError::IoError(std::io::Error::other("no active workspace")))
+        })
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::state::ParseError;
+
+// Hyprland hands out workspace and monitor ids as bare integers, wrapping them keeps one
+// from being passed where the other is expected. Both print as the bare number
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WorkspaceId(pub i32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MonitorId(pub i128);
+
+impl WorkspaceId {
+    // Hyprland gives special (scratchpad) workspaces ids from -99 to -2. Named workspaces are
+    // negative as well, from -1337 down, so a negative id alone says nothing
+    pub fn is_special(self) -> bool {
+        (-99..=-2).contains(&self.0)
+    }
+}
+
+impl From<i32> for WorkspaceId {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<WorkspaceId> for i32 {
+    fn from(value: WorkspaceId) -> Self {
+        value.0
+    }
+}
+
+impl Display for WorkspaceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for WorkspaceId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.parse()?))
+    }
+}
+
+impl From<i128> for MonitorId {
+    fn from(value: i128) -> Self {
+        Self(value)
+    }
+}
+
+impl From<MonitorId> for i128 {
+    fn from(value: MonitorId) -> Self {
+        value.0
+    }
+}
+
+impl Display for MonitorId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for MonitorId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.parse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspace_ids_round_trip() {
+        for id in [1, 0, -98, -1337] {
+            let workspace_id = WorkspaceId::from(id);
+            assert_eq!(i32::from(workspace_id), id);
+            assert_eq!(workspace_id.to_string(), id.to_string());
+            assert_eq!(
+                workspace_id.to_string().parse::<WorkspaceId>().unwrap(),
+                workspace_id
+            );
+        }
+        assert!("x".parse::<WorkspaceId>().is_err());
+    }
+
+    #[test]
+    fn monitor_ids_round_trip() {
+        for id in [0, 1, -1] {
+            let monitor_id = MonitorId::from(id);
+            assert_eq!(i128::from(monitor_id), id);
+            assert_eq!(monitor_id.to_string(), id.to_string());
+            assert_eq!(
+                monitor_id.to_string().parse::<MonitorId>().unwrap(),
+                monitor_id
+            );
+        }
+        assert!("".parse::<MonitorId>().is_err());
+    }
+
+    #[test]
+    fn specials_are_the_scratchpad_range() {
+        assert!(WorkspaceId(-98).is_special());
+        assert!(!WorkspaceId(-1).is_special());
+        assert!(!WorkspaceId(-1337).is_special());
+        assert!(!WorkspaceId(3).is_special());
+    }
+}
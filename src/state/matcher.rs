@@ -0,0 +1,146 @@
+use log::error;
+use regex::Regex;
+
+use crate::config::FilterMode;
+
+// Compiled form of a program list, entries are matched exactly unless they are
+// prefixed with `re:` (regex) or contain `*`/`?` (glob). Hyprland reports the app_id of
+// native wayland windows as their class, so an `app_id:` prefix matches the class too
+#[derive(Clone, Debug, Default)]
+pub struct Matcher {
+    exact: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Matcher {
+    pub fn new(programs: &[String]) -> Self {
+        let mut matcher = Matcher::default();
+        for program in programs {
+            let program = program.strip_prefix("app_id:").unwrap_or(program);
+            let pattern = if let Some(pattern) = program.strip_prefix("re:") {
+                format!("^(?:{pattern})$")
+            } else if program.contains(['*', '?']) {
+                glob_to_regex(program)
+            } else {
+                matcher.exact.push(program.to_string());
+                continue;
+            };
+            match Regex::new(&pattern) {
+                Ok(val) => matcher.patterns.push(val),
+                Err(err) => error!("Ignoring invalid pattern {program}: {err}"),
+            }
+        }
+        matcher
+    }
+
+    pub fn matches(&self, class: &str) -> bool {
+        self.exact.iter().any(|val| val == class)
+            || self.patterns.iter().any(|val| val.is_match(class))
+    }
+
+    // True if the filter lets nest act on the class
+    pub fn decision(&self, class: &str, mode: &FilterMode) -> bool {
+        let is_in_list = self.matches(class);
+        match mode {
+            FilterMode::Include => is_in_list,
+            FilterMode::Exclude => !is_in_list,
+        }
+    }
+}
+
+// Entries of a program list that don't match any of the known classes
+pub fn unmatched<'a>(programs: &'a [String], classes: &[String]) -> Vec<&'a String> {
+    programs
+        .iter()
+        .filter(|program| {
+            let matcher = Matcher::new(std::slice::from_ref(program));
+            !classes.iter().any(|class| matcher.matches(class))
+        })
+        .collect()
+}
+
+// The first entry of a program list that matches the class, to explain a decision
+pub fn matching_entry<'a>(programs: &'a [String], class: &str) -> Option<&'a String> {
+    programs
+        .iter()
+        .find(|program| Matcher::new(std::slice::from_ref(program)).matches(class))
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(programs: &[&str]) -> Matcher {
+        let programs: Vec<String> = programs.iter().map(|val| val.to_string()).collect();
+        Matcher::new(&programs)
+    }
+
+    #[test]
+    fn matches_exact_glob_and_regex() {
+        let matcher = matcher(&["kitty", "steam_app_*", "re:fire(fox|dragon)"]);
+        assert!(matcher.matches("kitty"));
+        assert!(!matcher.matches("kitty2"));
+        assert!(matcher.matches("steam_app_123"));
+        assert!(matcher.matches("firefox"));
+        assert!(matcher.matches("firedragon"));
+        // Regexes are anchored like exact entries
+        assert!(!matcher.matches("firefox-beta"));
+    }
+
+    #[test]
+    fn app_id_prefix_matches_the_class() {
+        let matcher = matcher(&["app_id:org.gnome.Nautilus", "app_id:re:org\\.kde\\..*"]);
+        assert!(matcher.matches("org.gnome.Nautilus"));
+        assert!(matcher.matches("org.kde.dolphin"));
+    }
+
+    #[test]
+    fn invalid_regex_is_skipped() {
+        let matcher = matcher(&["re:(", "kitty"]);
+        assert!(matcher.matches("kitty"));
+        assert!(!matcher.matches("("));
+    }
+
+    #[test]
+    fn decision_follows_mode() {
+        let matcher = matcher(&["kitty"]);
+        assert!(matcher.decision("kitty", &FilterMode::Include));
+        assert!(!matcher.decision("foot", &FilterMode::Include));
+        assert!(!matcher.decision("kitty", &FilterMode::Exclude));
+        assert!(matcher.decision("foot", &FilterMode::Exclude));
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        assert_eq!(glob_to_regex("a.b*"), "^a\\.b.*$");
+        assert_eq!(glob_to_regex("?x"), "^.x$");
+        let matcher = matcher(&["a.b*"]);
+        assert!(matcher.matches("a.bc"));
+        assert!(!matcher.matches("axbc"));
+    }
+
+    #[test]
+    fn unmatched_and_matching_entry() {
+        let programs: Vec<String> = ["kitty", "stea*", "typo"]
+            .iter()
+            .map(|val| val.to_string())
+            .collect();
+        let classes = vec!["kitty".to_string(), "steam".to_string()];
+        assert_eq!(unmatched(&programs, &classes), vec![&programs[2]]);
+        assert_eq!(matching_entry(&programs, "steam"), Some(&programs[1]));
+        assert_eq!(matching_entry(&programs, "foot"), None);
+    }
+}
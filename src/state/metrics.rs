@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::state::Health;
+
+// Things nest counts while running, the names are part of the metrics output and should not change
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    WindowsSeen,
+    Moves,
+    MoveFailures,
+    FloatingMoves,
+    FloatingMoveFailures,
+    Saves,
+    SaveFailures,
+}
+
+const COUNTERS: [Counter; 7] = [
+    Counter::WindowsSeen,
+    Counter::Moves,
+    Counter::MoveFailures,
+    Counter::FloatingMoves,
+    Counter::FloatingMoveFailures,
+    Counter::Saves,
+    Counter::SaveFailures,
+];
+
+impl Counter {
+    fn name(&self) -> &'static str {
+        match self {
+            Counter::WindowsSeen => "nest_windows_seen_total",
+            Counter::Moves => "nest_moves_total",
+            Counter::MoveFailures => "nest_move_failures_total",
+            Counter::FloatingMoves => "nest_floating_moves_total",
+            Counter::FloatingMoveFailures => "nest_floating_move_failures_total",
+            Counter::Saves => "nest_saves_total",
+            Counter::SaveFailures => "nest_save_failures_total",
+        }
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Counter::WindowsSeen => "Windows nest started tracking",
+            Counter::Moves => "Windows moved to a workspace",
+            Counter::MoveFailures => "Workspace moves hyprland did not complete",
+            Counter::FloatingMoves => "Floating windows restored to their geometry",
+            Counter::FloatingMoveFailures => "Floating windows that could not be restored",
+            Counter::Saves => "Times the state was written to storage",
+            Counter::SaveFailures => "Times writing the state failed",
+        }
+    }
+}
+
+// Counters since nest started, they reset on restart like any prometheus counter
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counts: [AtomicU64; COUNTERS.len()],
+}
+
+impl Metrics {
+    pub fn inc(&self, counter: Counter) {
+        self.counts[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prometheus text exposition format, the health fields are added as gauges
+    pub fn exposition(&self, health: &Health) -> String {
+        let mut out = String::new();
+        for counter in COUNTERS {
+            let value = self.counts[counter as usize].load(Ordering::Relaxed);
+            out.push_str(&format!(
+                "# HELP {name} {}\n# TYPE {name} counter\n{name} {value}\n",
+                counter.help(),
+                name = counter.name()
+            ));
+        }
+        let gauges = [
+            (
+                "nest_programs",
+                "Programs nest has learned about",
+                health.programs as i64,
+            ),
+            (
+                "nest_uptime_seconds",
+                "Seconds since nest started",
+                health.uptime,
+            ),
+            (
+                "nest_connected",
+                "1 if hyprland answered the last request",
+                health.connected as i64,
+            ),
+            (
+                "nest_last_save_timestamp_seconds",
+                "Unix time of the last save, 0 before the first",
+                health.last_save.unwrap_or(0),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+            ));
+        }
+        out
+    }
+}
@@ -1,21 +1,23 @@
-use crate::config::{Config, FilterMode};
-use chrono::Utc;
-use hyprland::{
-    dispatch::{Dispatch, DispatchType, WindowIdentifier, WorkspaceIdentifierWithSpecial},
-    error::HyprError,
-    shared::Address,
+use crate::config::{
+    ClassOverride, Config, FilterMode, MonitorChange, MonitorRestore, NoPrediction, OnClose,
+    OnZombie, TieBreak,
 };
-use log::{debug, info};
+use chrono::{DateTime, Local, Utc};
+use hyprland::{error::HyprError, shared::Address};
+use log::{debug, info, warn};
 use std::{
-    collections::HashMap,
-    num::ParseIntError,
+    borrow::Cow,
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet},
+    num::{ParseFloatError, ParseIntError},
     str::ParseBoolError,
     sync::{
         Arc,
-        atomic::{AtomicBool, AtomicI32, Ordering},
+        atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, Ordering},
     },
 };
 use thiserror::Error;
+use tokio::sync::Mutex;
 
 mod safemap;
 pub use safemap::SafeMap;
@@ -30,7 +32,56 @@ mod workspace;
 pub use workspace::Workspace;
 
 mod floatingwindow;
-pub use floatingwindow::FloatingWindow;
+pub use floatingwindow::{
+    Fit, FloatingWindow, MonitorRect, Sightings, client_monitor, is_maximized, monitor_rect,
+};
+
+mod output;
+pub use output::OutputWorkspace;
+
+mod scoring;
+pub use scoring::Scoring;
+
+mod compact;
+pub use compact::{CompactReport, Compaction};
+
+mod builder;
+pub use builder::StateBuilder;
+
+mod health;
+pub use health::{Boost, Health, SessionOverrides, Status};
+
+mod group;
+pub use group::Grouping;
+
+mod matcher;
+pub use matcher::{Matcher, matching_entry, unmatched};
+
+mod address;
+pub use address::fmt_address;
+
+mod ids;
+pub use ids::{MonitorId, WorkspaceId};
+
+mod split;
+pub use split::Split;
+
+mod metrics;
+pub use metrics::{Counter, Metrics};
+
+mod clock;
+pub use clock::{Clock, SystemClock};
+
+mod hypr;
+pub use hypr::{Action, ClientInfo, Hypr, Hyprland, MonitorInfo, Target};
+
+#[cfg(test)]
+mod harness;
+
+mod outcome;
+pub use outcome::{
+    Applied, FloatPlacement, MoveResult, Moved, OpenOutcome, Placement, RemoveResult,
+};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -40,6 +91,8 @@ pub enum Error {
     BlankAddress,
     #[error("class not mapped to a program")]
     BlankClass,
+    #[error("no move waiting for confirmation")]
+    NothingPending,
 }
 
 #[derive(Error, Debug)]
@@ -50,89 +103,299 @@ pub enum ParseError {
     Int(#[from] ParseIntError),
     #[error("could parse bool: {0}")]
     Bool(#[from] ParseBoolError),
+    #[error("could parse float: {0}")]
+    Float(#[from] ParseFloatError),
 }
 
 #[derive(Clone)]
 pub struct State {
     addresses: SafeMap<Address, Window>,
     programs: SafeMap<String, Program>,
+    live_counts: SafeMap<String, usize>,
+    disabled: Arc<Mutex<HashSet<String>>>,
+    // Programs whose prediction was confident at some point this session
+    confident: Arc<Mutex<HashSet<String>>>,
     current_workspace: Arc<AtomicI32>,
-    workspace_list: Arc<[String]>,
+    // Active workspace of every monitor, keyed by monitor id
+    monitor_workspaces: SafeMap<MonitorId, WorkspaceId>,
+    // Predictions for programs seen on a workspace, worked out when it is switched to
+    predictions: SafeMap<WorkspaceId, HashMap<String, (WorkspaceId, f64)>>,
+    // Bumped whenever a history changes so a prefetch running at the same time is thrown away
+    history_generation: Arc<AtomicU64>,
+    // Session only score added to a workspace, (weight, expiry timestamp)
+    boosts: SafeMap<WorkspaceId, (f64, i64)>,
+    // Where nest last moved a window and when, so a correction right after can be spotted
+    placements: SafeMap<Address, (WorkspaceId, i64)>,
+    // When nest last moved each window, in milliseconds
+    last_moves: SafeMap<Address, i64>,
+    metrics: Arc<Metrics>,
+    // When the floating geometry of each class was last recorded
+    captures: SafeMap<String, i64>,
+    // When each class last learned a workspace
+    learns: SafeMap<String, i64>,
+    // The focused window and the one before it, with when focus moved to the current one
+    focus: Arc<Mutex<Focus>>,
+    // Moves waiting for `confirm`, the predicted workspace and when it was asked
+    pending: SafeMap<Address, (WorkspaceId, i64)>,
+    settling: Arc<AtomicBool>,
+    workspace_matcher: Matcher,
     workspace_mode: FilterMode,
     workspace_buffer: usize,
-    floating_list: Arc<[String]>,
+    new_program_target: Option<WorkspaceId>,
+    ignore_transient: bool,
+    aggregate: bool,
+    learn_special: bool,
+    portable: bool,
+    focus_weight: f64,
+    verify_moves: bool,
+    tie_break: TieBreak,
+    self_move_grace_ms: i64,
+    workspace_enabled: bool,
+    on_no_prediction: NoPrediction,
+    default_workspace: WorkspaceId,
+    floating_enabled: bool,
+    floating_matcher: Matcher,
     floating_mode: FilterMode,
-    restore_list: Arc<[String]>,
+    floating_fit: Fit,
+    floating_restore: MonitorRestore,
+    restore_matcher: Matcher,
     restore_mode: FilterMode,
+    properties_matcher: Matcher,
+    properties_mode: FilterMode,
+    split_matcher: Matcher,
+    split_mode: FilterMode,
     restore_timeout: i64,
+    restore_enabled: bool,
+    restore_recent_activity: i64,
+    restore_on_zombie: OnZombie,
+    scoring: Scoring,
+    properties_enabled: bool,
+    fast_adapt: bool,
+    fast_adapt_secs: i64,
+    fast_adapt_weight: f64,
+    compaction: Compaction,
+    // Classes that wait for `confirm` before being moved
+    ask_matcher: Matcher,
+    ask_timeout_secs: i64,
+    split_enabled: bool,
+    move_cooldown_ms: i64,
+    learn_user_moves_only: bool,
+    capture_interval_secs: i64,
+    // Histories are kept apart per monitor, see monitor_history
+    per_monitor: bool,
+    // Classes still placed from their history, but nothing new is learned for them
+    freeze_matcher: Matcher,
+    // Classes that are learned as one program
+    grouping: Grouping,
+    learn_interval_secs: i64,
+    // Per class settings that replace the global ones, see class_override
+    overrides: HashMap<String, ClassOverride>,
+    started: DateTime<Utc>,
+    clock: Arc<dyn Clock>,
+    hypr: Arc<dyn Hypr>,
+    last_save: Arc<AtomicI64>,
+    last_move: Arc<AtomicI64>,
+    pub connected: Arc<AtomicBool>,
     pub changed: Arc<AtomicBool>,
 }
 
-pub type WorkspaceConfig = (Arc<[String]>, FilterMode, usize);
-pub type FloatingConfig = (Arc<[String]>, FilterMode);
-pub type RestoreConfig = (Arc<[String]>, FilterMode, i64);
+#[derive(Clone, Debug, Default)]
+struct Focus {
+    current: Option<Address>,
+    previous: Option<Address>,
+    since: i64,
+}
+
+// Windows younger than this are never dropped by reconcile, hyprland may not list them yet
+const RECONCILE_GRACE_SECS: i64 = 10;
+
+// Highest score a workspace can reach, see saturate
+const MAX_SCORE: f64 = 1e12;
+
+// A silent move hands focus to another window, the moved one still counts as focused this long
+const USER_MOVE_FOCUS_MS: i64 = 1000;
+
+// What add_window did with a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Added {
+    // The program is disabled or the window was already tracked
+    Ignored,
+    Window,
+    // The window is the first nest has seen of its program
+    Program,
+}
 
 impl State {
-    pub fn new(
-        workspace_config: WorkspaceConfig,
-        floating_config: FloatingConfig,
-        restore_config: RestoreConfig,
-    ) -> Self {
-        Self {
-            addresses: SafeMap::new(),
-            programs: SafeMap::new(),
-            workspace_list: workspace_config.0,
-            workspace_mode: workspace_config.1,
-            workspace_buffer: workspace_config.2,
-            floating_list: floating_config.0,
-            floating_mode: floating_config.1,
-            restore_list: restore_config.0,
-            restore_mode: restore_config.1,
-            restore_timeout: restore_config.2,
-            current_workspace: Arc::new(AtomicI32::new(1)),
-            changed: Arc::new(AtomicBool::new(false)),
-        }
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
     }
 
     pub async fn load(programs: Vec<Program>, config: Config) -> Self {
-        let state = Self::new(
-            (
-                config.workspace.filter.programs.into(),
-                config.workspace.filter.mode,
-                config.workspace.buffer,
-            ),
-            (
-                config.floating.filter.programs.into(),
-                config.floating.filter.mode,
-            ),
-            (
-                config.restore.filter.programs.into(),
-                config.restore.filter.mode,
-                config.restore.timeout,
-            ),
-        );
-        let mut programs_map = state.programs.0.lock().await;
-        for program in programs {
-            programs_map.insert(program.class.clone(), program);
+        let state = StateBuilder::from_config(&config).build();
+        check_nth_rules(&config.overrides);
+        {
+            // Aggregated histories are folded below instead of trimmed
+            let buffer = |class: &str| {
+                if state.aggregate {
+                    usize::MAX
+                } else {
+                    state.buffer_for(class)
+                }
+            };
+            let mut programs_map = state.programs.0.lock().await;
+            for program in programs {
+                // Dropped here so the next save leaves them out of storage
+                if program.is_empty() {
+                    debug!(
+                        "Dropping {} from storage, nothing was learned",
+                        program.class
+                    );
+                    state.changed.store(true, Ordering::Relaxed);
+                    continue;
+                }
+                if let Some(existing) = programs_map.get_mut(&program.class) {
+                    warn!(
+                        "Storage has more than one entry for {}, merging their histories",
+                        program.class
+                    );
+                    existing.merge(program, buffer(&existing.class));
+                    state.changed.store(true, Ordering::Relaxed);
+                    continue;
+                }
+                programs_map.insert(program.class.clone(), program);
+            }
+            for rename in &config.rename {
+                let mut program = match programs_map.remove(&rename.from) {
+                    Some(val) => val,
+                    None => continue,
+                };
+                info!(
+                    "Moving the history of {} over to {}",
+                    rename.from, rename.to
+                );
+                program.class = rename.to.clone();
+                match programs_map.get_mut(&rename.to) {
+                    Some(existing) => existing.merge(program, buffer(&rename.to)),
+                    None => {
+                        programs_map.insert(rename.to.clone(), program);
+                    }
+                }
+                state.changed.store(true, Ordering::Relaxed);
+            }
+            // Histories learned before a class was grouped are folded into the group
+            let classes: Vec<String> = programs_map.keys().cloned().collect();
+            for class in classes {
+                let grouped = state.grouping.class(&class).into_owned();
+                if grouped == class {
+                    continue;
+                }
+                let mut program = match programs_map.remove(&class) {
+                    Some(val) => val,
+                    None => continue,
+                };
+                info!("Grouping the history of {class} into {grouped}");
+                program.class = grouped.clone();
+                match programs_map.get_mut(&grouped) {
+                    Some(existing) => existing.merge(program, buffer(&grouped)),
+                    None => {
+                        programs_map.insert(grouped, program);
+                    }
+                }
+                state.changed.store(true, Ordering::Relaxed);
+            }
+            // A buffer may have been lowered since the history was written
+            for program in programs_map.values_mut() {
+                let excess = program
+                    .workspaces
+                    .len()
+                    .saturating_sub(buffer(&program.class));
+                if excess > 0 {
+                    program.workspaces.drain(..excess);
+                    state.changed.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        // Histories stored in the raw format are migrated the first time they load
+        if state.aggregate {
+            for program in state.programs.0.lock().await.values_mut() {
+                program.aggregate(state.scoring.tau);
+            }
         }
-        state.clone()
+        state.origin_workspace().await;
+        state.seed_monitor_workspaces().await;
+        state
     }
 
-    pub async fn add_window(&self, class: String, address: Address) {
+    // A repeated open event is ignored, keeping the original timestamp and origin so
+    // restoring still works. The class is expected to be grouped already
+    pub async fn add_window(&self, class: String, address: Address) -> Added {
+        if !self.is_program_enabled(&class).await {
+            debug!(
+                "Window {} of type {class} is disabled, not tracking",
+                fmt_address(&address)
+            );
+            return Added::Ignored;
+        }
+        {
+            let mut addresses = self.addresses.0.lock().await;
+            if let Some(window) = addresses.get_mut(&address) {
+                if window.class != class {
+                    debug!(
+                        "Window {} changed type from {} to {class}",
+                        fmt_address(&address),
+                        window.class
+                    );
+                    let mut live_counts = self.live_counts.0.lock().await;
+                    decrement(&mut live_counts, &window.class);
+                    *live_counts.entry(class.clone()).or_insert(0) += 1;
+                    window.class = class;
+                } else {
+                    debug!(
+                        "Window {} of type {class} is already tracked",
+                        fmt_address(&address)
+                    );
+                }
+                return Added::Ignored;
+            }
+        }
+        let mut origin = self.origin_workspace().await;
+        let monitor = focused_monitor(self.hypr.as_ref()).await;
+        // The tracked workspace can't see special workspaces, they are read from the monitor
+        let special = monitor
+            .as_ref()
+            .map(|monitor| monitor.special_workspace)
+            .filter(|id| id.is_special());
+        if let Some(id) = special
+            && self.learn_special
+        {
+            origin = id;
+        }
+        let mut added = Added::Window;
         {
             // Creates new program if none exists
             let mut programs = self.programs.0.lock().await;
             if !programs.contains_key(&class) {
-                let positions = vec![Workspace {
-                    workspace_id: self.current_workspace.load(Ordering::Relaxed),
-                    timestamp: Utc::now().timestamp(),
-                }];
+                added = Added::Program;
+                let positions = match special {
+                    Some(_) if !self.learn_special => {
+                        debug!(
+                            "Window {} opened on a special workspace, not learning",
+                            fmt_address(&address)
+                        );
+                        Vec::new()
+                    }
+                    // new_program_target is applied by handle_open, the history only holds
+                    // where the window really opened
+                    _ => vec![Workspace::new(origin, self.now().timestamp())],
+                };
                 let _ = programs.insert(
                     class.clone(),
                     Program {
                         class: class.clone(),
                         workspaces: positions,
                         floating_window: None,
+                        properties: BTreeMap::new(),
+                        split: None,
                         moved: false,
                         float_moved: false,
                     },
@@ -143,250 +406,2061 @@ impl State {
             // Maps the address to the program
             let window = Window {
                 class: class.clone(),
-                timestamp: Utc::now(),
-                origin: self.current_workspace(),
+                timestamp: self.now(),
+                origin,
+                monitor: monitor.map(|monitor| monitor.name),
             };
             let mut addresses = self.addresses.0.lock().await;
+            // Another open event for the same window may have won the race while we looked things up
+            if addresses.contains_key(&address) {
+                return Added::Ignored;
+            }
+            let mut live_counts = self.live_counts.0.lock().await;
             addresses.insert(address.clone(), window);
+            *live_counts.entry(class.clone()).or_insert(0) += 1;
+            self.metrics.inc(Counter::WindowsSeen);
         }
-        debug!("Window {address} of type {class} added");
+        debug!(
+            "Window {} of type {class} added, {} open",
+            fmt_address(&address),
+            self.live_count(&class).await
+        );
+        added
     }
 
-    // Removes mapping between window and program, it will never remove a programs state
-    pub async fn remove_window(&self, address: Address) -> Result<(), Error> {
-        let mut addresses = self.addresses.0.lock().await;
-        if let Some(window) = addresses.remove(&address) {
-            let diff = Utc::now() - window.timestamp;
-            let is_in_list = self.restore_list.contains(&window.class);
-            if self.restore_timeout >= diff.num_seconds()
-                && ((is_in_list && self.restore_mode == FilterMode::Include)
-                    || (!is_in_list && self.restore_mode == FilterMode::Exclude))
-            {
-                Dispatch::call_async(DispatchType::Workspace(WorkspaceIdentifierWithSpecial::Id(
-                    window.origin,
-                )))
-                .await?;
+    // Everything nest does when a window opens, the caller only reports the outcome
+    pub async fn handle_open(
+        &self,
+        class: &str,
+        address: &Address,
+        workspace_name: &str,
+        quiet: bool,
+    ) -> OpenOutcome {
+        let mut outcome = OpenOutcome {
+            prediction: None,
+            runner_up: None,
+            placement: Placement::Untracked,
+            floating: FloatPlacement::Skipped,
+        };
+        // Everything below works on the program the window is learned under
+        let class = &self.group(class);
+        let added = self.add_window(class.to_string(), address.clone()).await;
+        if added == Added::Ignored {
+            return outcome;
+        }
+        let program = match self.get_program(class.to_string()).await {
+            Some(val) => val,
+            None => return outcome,
+        };
+        outcome.placement = Placement::Skipped;
+        // The window is still tracked, it is just left where it opened
+        if quiet {
+            return outcome;
+        }
+
+        // A window opened on a scratchpad is left there unless special workspaces are learned
+        let special = workspace_name.starts_with("special:");
+        if self.workspace_enabled && (self.learn_special || !special) {
+            // Rules come before the history
+            let target = match self.nth_target(class).await {
+                Some(val) => Some(val),
+                None if added == Added::Program => self.new_program_target,
+                None => None,
+            };
+            let monitor = self
+                .addresses
+                .0
+                .lock()
+                .await
+                .get(address)
+                .and_then(|window| window.monitor.clone());
+            outcome.prediction = match target {
+                Some(workspace_id) => Some((workspace_id, 0.0)),
+                None => match self.predict(class, monitor.as_deref()).await {
+                    Some(val) => {
+                        outcome.runner_up = self.runner_up(class, monitor.as_deref(), val.0).await;
+                        Some(val)
+                    }
+                    None => match self.on_no_prediction {
+                        NoPrediction::Current => Some((self.current_workspace(), 0.0)),
+                        NoPrediction::Default => Some((self.default_workspace, 0.0)),
+                        NoPrediction::Leave | NoPrediction::Notify => None,
+                    },
+                },
+            };
+            outcome.placement = match outcome.prediction {
+                Some((workspace_id, _)) if self.ask_matcher.matches(class) => {
+                    self.ask(address, workspace_name, workspace_id).await
+                }
+                Some((workspace_id, _)) => self.place(address, workspace_name, workspace_id).await,
+                None => Placement::NoPrediction,
+            };
+        }
+
+        if self.floating_enabled {
+            outcome.floating = match program.floating_window {
+                Some(window) => match self.move_float_window(address, &window).await {
+                    Ok(true) => {
+                        self.metrics.inc(Counter::FloatingMoves);
+                        FloatPlacement::Moved(window)
+                    }
+                    Ok(false) => {
+                        self.metrics.inc(Counter::FloatingMoveFailures);
+                        FloatPlacement::Failed(window)
+                    }
+                    Err(err) => {
+                        self.metrics.inc(Counter::FloatingMoveFailures);
+                        FloatPlacement::Error(err)
+                    }
+                },
+                None => FloatPlacement::NoGeometry,
+            };
+        }
+
+        if self.properties_enabled
+            && self
+                .properties_matcher
+                .decision(class, &self.properties_mode)
+        {
+            for (property, value) in &program.properties {
+                if let Err(err) = set_property(self.hypr.as_ref(), address, property, value).await {
+                    warn!(
+                        "Failed to set {property} of window {}: {err}",
+                        fmt_address(address)
+                    );
+                }
             }
-            debug!(
-                "Window {address} of type {} removed after {}s",
-                window.class,
-                diff.num_seconds()
+        }
+        if let Some(split) = program.split
+            && self.manages_split(class)
+            && let Err(err) = restore_split(self.hypr.as_ref(), address, split).await
+        {
+            warn!(
+                "Failed to restore the split of window {}: {err}",
+                fmt_address(address)
             );
         }
-        Ok(())
+        outcome
     }
 
-    pub async fn window_moved(&self, address: Address, workspace_id: i32) -> Result<(), Error> {
-        let addresses = self.addresses.0.lock().await;
-        let window = match addresses.get(&address) {
-            Some(val) => val,
-            None => {
-                return Err(Error::BlankAddress);
-            }
-        };
+    pub fn manages_split(&self, class: &str) -> bool {
+        self.split_enabled && self.split_matcher.decision(class, &self.split_mode)
+    }
 
+    // Remembers how a tiled window of a program is split, true if it changed
+    pub async fn learn_split(&self, class: &str, split: Split) -> bool {
+        if !self.manages_split(class) {
+            return false;
+        }
         let mut programs = self.programs.0.lock().await;
-        let program = match programs.get_mut(&window.class) {
+        let program = match programs.get_mut(class) {
             Some(val) => val,
-            None => {
-                return Err(Error::BlankClass);
-            }
-        };
-
-        // This is true if the program moved a window
-        if program.moved {
-            debug!("Internal move, ignoring results");
-            program.moved = false;
-            return Ok(());
-        }
-
-        let position = Workspace {
-            workspace_id,
-            timestamp: Utc::now().timestamp(),
+            None => return false,
         };
-        program.workspaces.push(position);
-
-        while program.workspaces.len() > self.workspace_buffer {
-            program.workspaces.remove(0);
+        if program.split == Some(split) {
+            return false;
         }
-
+        program.split = Some(split);
         self.changed.store(true, Ordering::Relaxed);
-        info!(
-            "Program of type {} got moved to workspace {}",
-            window.class, workspace_id
-        );
-
-        Ok(())
+        true
     }
 
-    pub async fn move_window(&self, address: &Address, workspace_id: i32) -> Result<bool, Error> {
-        let addresses = self.addresses.0.lock().await;
-        let mut programs = self.programs.0.lock().await;
+    // The class a window is learned under, see Grouping
+    pub fn group(&self, class: &str) -> String {
+        self.grouping.class(class).into_owned()
+    }
 
-        let window = match addresses.get(address) {
+    // Sets a property of the focused window and remembers it for its class if properties are
+    // on for it, returns the class
+    pub async fn set_active_property(&self, property: &str, value: &str) -> Result<String, Error> {
+        let client = match self.hypr.active_client().await? {
             Some(val) => val,
             None => return Err(Error::BlankAddress),
         };
-
-        let is_in_list = self.workspace_list.contains(&window.class);
-        if (!is_in_list && self.workspace_mode == FilterMode::Include)
-            || (is_in_list && self.workspace_mode == FilterMode::Exclude)
+        set_property(self.hypr.as_ref(), &client.address, property, value).await?;
+        let class = self.group(&client.class);
+        if !self.properties_enabled
+            || !self
+                .properties_matcher
+                .decision(&class, &self.properties_mode)
         {
-            return Ok(false);
+            return Ok(class);
         }
-
-        let program = match programs.get_mut(&window.class) {
+        let mut programs = self.programs.0.lock().await;
+        let program = match programs.get_mut(&class) {
             Some(val) => val,
             None => return Err(Error::BlankClass),
         };
+        program
+            .properties
+            .insert(property.to_string(), value.to_string());
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(class)
+    }
 
-        program.moved = true;
+    // The rule for the window that was just added, counting the open windows of its class.
+    // These win over the history
+    async fn nth_target(&self, class: &str) -> Option<WorkspaceId> {
+        let count = self.live_count(class).await;
+        self.class_override(class)?
+            .nth
+            .iter()
+            .find(|(nth, _)| nth.parse() == Ok(count))
+            .map(|(_, workspace_id)| WorkspaceId(*workspace_id))
+    }
 
-        match Dispatch::call_async(DispatchType::MoveToWorkspace(
-            WorkspaceIdentifierWithSpecial::Id(workspace_id),
-            Some(WindowIdentifier::Address(address.clone())),
-        ))
-        .await
-        {
-            Ok(_) => Ok(true),
-            Err(_) => {
-                // We failed to move the window (this does not mean an error the window could be in the right position already)
-                program.moved = false;
-                Ok(false)
-            }
-        }
+    // The overrides of a class, anything they leave out uses the global value
+    fn class_override(&self, class: &str) -> Option<&ClassOverride> {
+        self.overrides.get(class)
     }
 
-    pub async fn add_floating_window(
+    async fn place(
         &self,
-        class: &str,
-        window: FloatingWindow,
-    ) -> Result<(), Error> {
-        let mut programs = self.programs.0.lock().await;
-
-        let program = match programs.get_mut(class) {
-            Some(val) => val,
-            None => return Err(Error::BlankClass),
-        };
-
-        let change = match &program.floating_window {
-            Some(last) => last.at != window.at || last.size != window.size,
-            None => true,
-        };
-
-        if change {
-            program.floating_window = Some(window);
-            self.changed.store(true, Ordering::Relaxed);
+        address: &Address,
+        workspace_name: &str,
+        workspace_id: WorkspaceId,
+    ) -> Placement {
+        if open_workspace(self.hypr.as_ref(), address, workspace_name).await == Some(workspace_id) {
+            return Placement::AlreadyThere(workspace_id);
+        }
+        match self.move_window(address, workspace_id).await {
+            Ok(result) => Placement::from_move(result, workspace_id),
+            Err(err) => Placement::Error(err),
         }
-
-        Ok(())
     }
 
-    pub async fn remove_floating_window(&self, class: &str) -> Result<(), Error> {
-        let mut programs = self.programs.0.lock().await;
-
-        let program = match programs.get_mut(class) {
-            Some(val) => val,
-            None => return Err(Error::BlankClass),
-        };
+    // Moves every tracked window that is away from its prediction, the focused window is left
+    // alone so nothing is pulled away from under the user
+    pub async fn apply(&self, dry_run: bool) -> Result<Vec<Applied>, Error> {
+        let mut applied = Vec::new();
+        if !self.workspace_enabled {
+            return Ok(applied);
+        }
+        let clients = self.hypr.clients().await?;
+        let active = self
+            .hypr
+            .active_client()
+            .await?
+            .map(|client| client.address);
+        let windows = self.windows().await;
+        for client in clients {
+            if active.as_ref() == Some(&client.address) {
+                continue;
+            }
+            let (class, monitor) = match windows
+                .iter()
+                .find(|(address, _)| *address == client.address)
+            {
+                Some((_, window)) => (&window.class, window.monitor.as_deref()),
+                None => continue,
+            };
+            if !self.workspace_matcher.decision(class, &self.workspace_mode)
+                || !self.is_program_enabled(class).await
+            {
+                continue;
+            }
+            let to = match self.predict(class, monitor).await {
+                Some((workspace_id, _)) => workspace_id,
+                None => continue,
+            };
+            let from = client.workspace;
+            if from == to {
+                continue;
+            }
+            let moved = !dry_run
+                && match self.move_window(&client.address, to).await {
+                    Ok(result) => result == MoveResult::Moved,
+                    Err(err) => {
+                        warn!(
+                            "Failed to move window {}: {err}",
+                            fmt_address(&client.address)
+                        );
+                        false
+                    }
+                };
+            applied.push(Applied {
+                class: class.clone(),
+                from,
+                to,
+                moved,
+            });
+        }
+        Ok(applied)
+    }
 
-        program.floating_window = None;
-        self.changed.store(true, Ordering::Relaxed);
-        Ok(())
+    // Runs `action` once monitors were added or removed, the number of windows moved or None
+    // when nothing ran. Quiet hours leave windows where they are
+    pub async fn monitors_changed(
+        &self,
+        action: MonitorChange,
+        quiet: bool,
+    ) -> Result<Option<usize>, Error> {
+        match action {
+            MonitorChange::Nothing => Ok(None),
+            MonitorChange::Reapply if quiet => {
+                info!("Monitors changed during quiet hours, not moving windows");
+                Ok(None)
+            }
+            MonitorChange::Reapply => {
+                let applied = self.apply(false).await?;
+                Ok(Some(applied.iter().filter(|val| val.moved).count()))
+            }
+        }
     }
 
-    pub async fn move_float_window(
+    // Holds the move back until it is confirmed
+    async fn ask(
         &self,
         address: &Address,
-        at: (i16, i16),
-        size: (i16, i16),
-    ) -> Result<bool, Error> {
-        let addresses = self.addresses.0.lock().await;
-        let mut programs = self.programs.0.lock().await;
+        workspace_name: &str,
+        workspace_id: WorkspaceId,
+    ) -> Placement {
+        if open_workspace(self.hypr.as_ref(), address, workspace_name).await == Some(workspace_id) {
+            return Placement::AlreadyThere(workspace_id);
+        }
+        self.pending
+            .0
+            .lock()
+            .await
+            .insert(address.clone(), (workspace_id, self.now().timestamp()));
+        Placement::Asked(workspace_id)
+    }
 
-        let window = match addresses.get(address) {
+    // Does the newest move still waiting for confirmation, of `class` if given. Expired moves
+    // are dropped, returns the class and where the window went
+    pub async fn confirm(&self, class: Option<&str>) -> Result<(String, Placement), Error> {
+        let class = class.map(|val| self.group(val));
+        let class = class.as_deref();
+        let now = self.now().timestamp();
+        let address = {
+            // Same order as remove_window, addresses before pending
+            let addresses = self.addresses.0.lock().await;
+            let mut pending = self.pending.0.lock().await;
+            pending.retain(|_, (_, asked)| now - *asked <= self.ask_timeout_secs);
+            pending
+                .iter()
+                .filter(|(address, _)| match (class, addresses.get(*address)) {
+                    (Some(class), Some(window)) => window.class == class,
+                    (None, Some(_)) => true,
+                    (_, None) => false,
+                })
+                .max_by_key(|(_, (_, asked))| *asked)
+                .map(|(address, _)| address.clone())
+        };
+        let address = match address {
             Some(val) => val,
+            None => return Err(Error::NothingPending),
+        };
+        let (workspace_id, _) = match self.pending.0.lock().await.remove(&address) {
+            Some(val) => val,
+            None => return Err(Error::NothingPending),
+        };
+        let class = match self.addresses.0.lock().await.get(&address) {
+            Some(val) => val.class.clone(),
             None => return Err(Error::BlankAddress),
         };
+        let placement = match self.move_window(&address, workspace_id).await {
+            Ok(result) => Placement::from_move(result, workspace_id),
+            Err(err) => Placement::Error(err),
+        };
+        Ok((class, placement))
+    }
 
-        let is_in_list = self.floating_list.contains(&window.class);
-        if (!is_in_list && self.floating_mode == FilterMode::Include)
-            || (is_in_list && self.floating_mode == FilterMode::Exclude)
-        {
-            return Ok(false);
-        }
-
-        let program = match programs.get_mut(&window.class) {
+    // Removes mapping between window and program, it will never remove a programs state
+    pub async fn remove_window(&self, address: Address) -> Result<RemoveResult, Error> {
+        let mut addresses = self.addresses.0.lock().await;
+        let window = match addresses.remove(&address) {
             Some(val) => val,
-            None => return Err(Error::BlankClass),
+            None => return Ok(RemoveResult::NotTracked),
         };
+        decrement(&mut *self.live_counts.0.lock().await, &window.class);
+        self.forget_window(&address).await;
+        let restored = self.closed(&address, &window).await?;
+        debug!(
+            "Window {} of type {} removed after {}s",
+            fmt_address(&address),
+            window.class,
+            (self.now() - window.timestamp).num_seconds()
+        );
+        if restored {
+            Ok(RemoveResult::Restored)
+        } else {
+            Ok(RemoveResult::NotRestored)
+        }
+    }
 
-        program.float_moved = true;
+    // Drops what is kept per window next to its mapping, however the window went away
+    async fn forget_window(&self, address: &Address) {
+        self.placements.0.lock().await.remove(address);
+        self.pending.0.lock().await.remove(address);
+        self.last_moves.0.lock().await.remove(address);
+    }
 
-        match Dispatch::call_async(DispatchType::ToggleFloating(Some(
-            WindowIdentifier::Address(address.clone()),
-        )))
-        .await
+    // Runs the on_close action of the class, restoring when there is none. True if the
+    // workspace was restored
+    async fn closed(&self, address: &Address, window: &Window) -> Result<bool, Error> {
+        match self
+            .class_override(&window.class)
+            .and_then(|val| val.on_close.as_ref())
         {
-            Ok(_) => (),
-            Err(_) => {
-                program.float_moved = false;
-                return Ok(false);
+            Some(OnClose::None) => Ok(false),
+            Some(OnClose::Exec(command)) => {
+                run_close_hook(command, &window.class, address);
+                Ok(false)
             }
+            Some(OnClose::Restore) | None => self.restore(window).await,
         }
+    }
 
-        match Dispatch::call_async(DispatchType::MoveWindowPixel(
-            hyprland::dispatch::Position::Exact(at.0, at.1),
-            WindowIdentifier::Address(address.clone()),
-        ))
-        .await
+    // Goes back to the workspace a closed window was opened from, if restoring applies to it
+    async fn restore(&self, window: &Window) -> Result<bool, Error> {
+        let diff = self.now() - window.timestamp;
+        let class_override = self.class_override(&window.class);
+        let enabled = class_override
+            .and_then(|val| val.restore)
+            .unwrap_or(self.restore_enabled);
+        let timeout = class_override
+            .and_then(|val| val.restore_timeout)
+            .unwrap_or(self.restore_timeout);
+        if !enabled
+            || timeout < diff.num_seconds()
+            || !self
+                .restore_matcher
+                .decision(&window.class, &self.restore_mode)
+            || !self.recently_active(&window.class).await
         {
-            Ok(_) => (),
-            Err(_) => {
-                program.float_moved = false;
-                return Ok(false);
-            }
+            return Ok(false);
         }
-
-        match Dispatch::call_async(DispatchType::ResizeWindowPixel(
-            hyprland::dispatch::Position::Exact(size.0, size.1),
-            WindowIdentifier::Address(address.clone()),
-        ))
-        .await
+        // Focus the monitor first so the workspace comes back where it was
+        if let Some(monitor) = &window.monitor
+            && let Err(err) = self
+                .hypr
+                .dispatch(Action::FocusMonitor(monitor.clone()))
+                .await
         {
-            Ok(_) => Ok(true),
-            Err(_) => {
-                program.float_moved = false;
-                Ok(false)
-            }
+            debug!("Failed to focus monitor {monitor}: {err}");
         }
+        let target = match special_name(self.hypr.as_ref(), window.origin).await {
+            Some(name) => Target::Special(name),
+            None => Target::Id(window.origin),
+        };
+        self.hypr.dispatch(Action::Workspace(target)).await?;
+        Ok(true)
     }
 
-    pub async fn get_program(&self, class: String) -> Option<Program> {
-        let programs = self.programs.0.lock().await;
-        programs.get(&class).cloned()
+    pub async fn windows(&self) -> Vec<(Address, Window)> {
+        let addresses = self.addresses.0.lock().await;
+        addresses
+            .iter()
+            .map(|(address, window)| (address.clone(), window.clone()))
+            .collect()
     }
 
-    pub async fn get_programs(&self) -> Vec<Program> {
-        let programs = self.programs.0.lock().await;
-        let val: Vec<Program> = programs
-            .clone()
+    // Takes back windows persisted by a previous run, only those hyprland still has with the same class
+    pub async fn restore_windows(&self, windows: Vec<(Address, Window)>) -> Result<usize, Error> {
+        let live: HashMap<Address, String> = self
+            .hypr
+            .clients()
+            .await?
             .into_iter()
-            .map(|val| val.1.clone())
+            .map(|client| (client.address, self.group(&client.class)))
             .collect();
-        val
+        let mut addresses = self.addresses.0.lock().await;
+        let mut live_counts = self.live_counts.0.lock().await;
+        let mut restored = 0;
+        for (address, window) in windows {
+            if live.get(&address) != Some(&window.class) || addresses.contains_key(&address) {
+                continue;
+            }
+            *live_counts.entry(window.class.clone()).or_insert(0) += 1;
+            addresses.insert(address, window);
+            restored += 1;
+        }
+        Ok(restored)
     }
 
-    pub async fn get_mapped_programs(&self) -> HashMap<String, Program> {
+    // Drops windows hyprland no longer knows about, for when a close event was missed.
+    // With `on_zombie = "restore"` the newest of them is restored as if it had closed,
+    // otherwise nothing is since the windows closed a while ago. Returns how many were dropped
+    pub async fn reconcile(&self) -> Result<Vec<Address>, Error> {
+        let live: HashSet<Address> = self
+            .hypr
+            .clients()
+            .await?
+            .into_iter()
+            .map(|client| client.address)
+            .collect();
+        let mut newest: Option<(Address, Window)> = None;
+        let stale = {
+            let mut addresses = self.addresses.0.lock().await;
+            let mut live_counts = self.live_counts.0.lock().await;
+            let now = self.now();
+            // Windows opened after the clients were fetched are not in `live` yet
+            let stale: Vec<Address> = addresses
+                .iter()
+                .filter(|(address, window)| {
+                    !live.contains(address)
+                        && (now - window.timestamp).num_seconds() > RECONCILE_GRACE_SECS
+                })
+                .map(|(address, _)| address.clone())
+                .collect();
+            for address in &stale {
+                if let Some(window) = addresses.remove(address) {
+                    decrement(&mut live_counts, &window.class);
+                    debug!(
+                        "Window {} of type {} no longer exists, dropping it",
+                        fmt_address(address),
+                        window.class
+                    );
+                    if newest
+                        .as_ref()
+                        .is_none_or(|(_, val)| val.timestamp < window.timestamp)
+                    {
+                        newest = Some((address.clone(), window));
+                    }
+                }
+            }
+            stale
+        };
+        for address in &stale {
+            self.forget_window(address).await;
+        }
+        // Restoring every vanished window would just flick through workspaces
+        if self.restore_on_zombie == OnZombie::Restore
+            && let Some((address, window)) = newest
+        {
+            self.closed(&address, &window).await?;
+        }
+        Ok(stale)
+    }
+
+    // True if the program was moved within the configured activity window, always true when disabled
+    async fn recently_active(&self, class: &str) -> bool {
+        if self.restore_recent_activity <= 0 {
+            return true;
+        }
         let programs = self.programs.0.lock().await;
-        programs.clone()
+        let latest = programs
+            .get(class)
+            .and_then(|program| program.workspaces.iter().map(|val| val.timestamp).max());
+        match latest {
+            Some(timestamp) => self.now().timestamp() - timestamp <= self.restore_recent_activity,
+            None => false,
+        }
     }
 
-    pub fn workspace_changed(&self, id: i32) {
-        self.current_workspace.store(id, Ordering::Relaxed);
+    // Returns true if the move was made by the user and got learned
+    pub async fn window_moved(
+        &self,
+        address: Address,
+        workspace_id: WorkspaceId,
+        workspace_name: &str,
+    ) -> Result<Moved, Error> {
+        // Both ask hyprland, so they are done before any lock is taken
+        let position = self
+            .position(workspace_id, storable_name(workspace_id, workspace_name))
+            .await;
+        let floating = self.ignore_transient && is_floating(self.hypr.as_ref(), &address).await;
+        let addresses = self.addresses.0.lock().await;
+        let window = match addresses.get(&address) {
+            Some(val) => val,
+            None => {
+                return Err(Error::BlankAddress);
+            }
+        };
+
+        let mut programs = self.programs.0.lock().await;
+        let program = match programs.get_mut(&window.class) {
+            Some(val) => val,
+            None => {
+                return Err(Error::BlankClass);
+            }
+        };
+
+        // This is true if the program moved a window
+        if program.moved {
+            debug!("Internal move, ignoring results");
+            program.moved = false;
+            return Ok(Moved::Internal);
+        }
+
+        // Some programs move themselves right after opening, that is not the user's choice
+        if (self.now() - window.timestamp).num_milliseconds() < self.self_move_grace_ms {
+            debug!(
+                "Window of type {} moved itself right after opening, ignoring results",
+                window.class
+            );
+            return Ok(Moved::NotUser);
+        }
+
+        if self.learn_user_moves_only && !self.is_user_move(&address).await {
+            debug!(
+                "Window of type {} moved without focus, likely by a program, ignoring results",
+                window.class
+            );
+            return Ok(Moved::NotUser);
+        }
+
+        if workspace_id.is_special() && !self.learn_special {
+            debug!("Window moved to a special workspace, ignoring results");
+            return Ok(Moved::NotLearned);
+        }
+
+        if self.is_transient(floating, &window.class).await {
+            debug!(
+                "Transient window of type {} moved, ignoring results",
+                window.class
+            );
+            return Ok(Moved::NotLearned);
+        }
+
+        if self.freeze_matcher.matches(&window.class) {
+            debug!(
+                "Learning is frozen for type {}, ignoring results",
+                window.class
+            );
+            return Ok(Moved::NotLearned);
+        }
+
+        if self.learned_recently(&window.class).await {
+            return Ok(Moved::NotLearned);
+        }
+
+        let weight = if self.is_correction(&address, workspace_id).await {
+            info!(
+                "Window of type {} was moved away right after nest placed it, learning it {}x",
+                window.class, self.fast_adapt_weight
+            );
+            self.fast_adapt_weight
+        } else {
+            1.0
+        };
+        self.learn(program, position, window.monitor.clone(), weight)
+            .await;
+        info!(
+            "Program of type {} got moved to workspace {}",
+            window.class, workspace_id
+        );
+
+        Ok(Moved::Learned)
+    }
+
+    // True if nest placed the window elsewhere within `fast_adapt_secs`, the placement is used up
+    async fn is_correction(&self, address: &Address, workspace_id: WorkspaceId) -> bool {
+        if !self.fast_adapt {
+            return false;
+        }
+        let (placed, timestamp) = match self.placements.0.lock().await.remove(address) {
+            Some(val) => val,
+            None => return false,
+        };
+        placed != workspace_id && self.now().timestamp() - timestamp <= self.fast_adapt_secs
+    }
+
+    // Hyprland does not say who moved a window, users move the focused one, programs and
+    // scripts usually move whatever they like
+    async fn is_user_move(&self, address: &Address) -> bool {
+        let focus = self.focus.lock().await;
+        focus.current.as_ref() == Some(address)
+            || (focus.previous.as_ref() == Some(address)
+                && self.now().timestamp_millis() - focus.since <= USER_MOVE_FOCUS_MS)
+    }
+
+    pub async fn focus_changed(&self, address: Address) {
+        let mut focus = self.focus.lock().await;
+        if focus.current.as_ref() == Some(&address) {
+            return;
+        }
+        focus.previous = focus.current.replace(address);
+        focus.since = self.now().timestamp_millis();
+    }
+
+    pub fn tracks_focus(&self) -> bool {
+        self.learn_user_moves_only
+    }
+
+    // Focusing a window counts as a weaker move to the workspace it is viewed on
+    pub async fn window_focused(&self, address: Address) -> Result<bool, Error> {
+        let workspace_id = self.current_workspace();
+        let position = self.position(workspace_id, None).await;
+        let addresses = self.addresses.0.lock().await;
+        let window = match addresses.get(&address) {
+            Some(val) => val,
+            None => return Err(Error::BlankAddress),
+        };
+
+        let mut programs = self.programs.0.lock().await;
+        let program = match programs.get_mut(&window.class) {
+            Some(val) => val,
+            None => return Err(Error::BlankClass),
+        };
+
+        // Refocusing on the workspace the history already ends on adds nothing, and would
+        // push real moves out of the buffer
+        if workspace_id.is_special()
+            || self.freeze_matcher.matches(&window.class)
+            || program
+                .workspaces
+                .last()
+                .is_some_and(|val| val.workspace_id == workspace_id)
+            || self.learned_recently(&window.class).await
+        {
+            return Ok(false);
+        }
+
+        self.learn(program, position, window.monitor.clone(), self.focus_weight)
+            .await;
+        debug!(
+            "Program of type {} got focused on workspace {}",
+            window.class, workspace_id
+        );
+        Ok(true)
+    }
+
+    fn buffer_for(&self, class: &str) -> usize {
+        self.class_override(class)
+            .and_then(|val| val.buffer)
+            .unwrap_or(self.workspace_buffer)
+    }
+
+    // Classes that are moved around a lot only learn once per interval, so every move does not
+    // mark the state for saving. The time is taken when learning is let through
+    async fn learned_recently(&self, class: &str) -> bool {
+        let interval = self
+            .class_override(class)
+            .and_then(|val| val.learn_interval_secs)
+            .unwrap_or(self.learn_interval_secs);
+        if interval <= 0 {
+            return false;
+        }
+        let now = self.now().timestamp();
+        let mut learns = self.learns.0.lock().await;
+        if let Some(last) = learns.get(class)
+            && now - last < interval
+        {
+            debug!(
+                "Type {class} learned a workspace {}s ago, ignoring results",
+                now - last
+            );
+            return true;
+        }
+        learns.insert(class.to_string(), now);
+        false
+    }
+
+    // A history entry for a move to `workspace_id`, without a name from the event one is only
+    // looked up for portable histories. This asks hyprland, so no lock may be held
+    async fn position(&self, workspace_id: WorkspaceId, name: Option<String>) -> Workspace {
+        let mut position = Workspace::new(workspace_id, self.now().timestamp());
+        if self.portable {
+            position.output =
+                OutputWorkspace::locate(workspace_id, &workspace_outputs(self.hypr.as_ref()).await);
+        }
+        position.name = match name {
+            Some(val) => Some(val),
+            None if self.portable => workspace_name(self.hypr.as_ref(), workspace_id).await,
+            None => None,
+        };
+        position
+    }
+
+    async fn learn(
+        &self,
+        program: &mut Program,
+        mut position: Workspace,
+        monitor: Option<String>,
+        weight: f64,
+    ) {
+        position.weight = weight;
+        if self.per_monitor {
+            position.monitor = monitor;
+        }
+        if self.aggregate {
+            program.add_weighted(position, self.scoring.tau);
+        } else {
+            program.workspaces.push(position);
+            while program.workspaces.len() > self.buffer_for(&program.class) {
+                program.workspaces.remove(0);
+            }
+        }
+        self.changed.store(true, Ordering::Relaxed);
+        self.invalidate_predictions().await;
+    }
+
+    async fn invalidate_predictions(&self) {
+        let mut predictions = self.predictions.0.lock().await;
+        self.history_generation.fetch_add(1, Ordering::Relaxed);
+        predictions.clear();
+    }
+
+    // Scores the programs that have lived on a workspace ahead of time, so opening one there
+    // does not have to go through the history
+    pub async fn prefetch(&self, workspace_id: WorkspaceId) {
+        // Boosted scores are not cached, they change when the boost runs out. Neither are
+        // time of day scores, they change with the clock, nor per monitor scores
+        if self.scoring.time_of_day_weight > 0.0
+            || self.per_monitor
+            || !self.active_boosts().await.is_empty()
+        {
+            return;
+        }
+        let generation = self.history_generation.load(Ordering::Relaxed);
+        let cached: HashMap<String, (WorkspaceId, f64)> = {
+            let programs = self.programs.0.lock().await;
+            programs
+                .values()
+                .filter(|program| {
+                    program
+                        .workspaces
+                        .iter()
+                        .any(|val| val.workspace_id == workspace_id)
+                })
+                .filter_map(|program| {
+                    calculate_workspace(
+                        &program.workspaces,
+                        &self.scoring,
+                        &HashMap::new(),
+                        self.now(),
+                    )
+                    .map(|val| (program.class.clone(), val))
+                })
+                .collect()
+        };
+        let mut predictions = self.predictions.0.lock().await;
+        if self.history_generation.load(Ordering::Relaxed) == generation {
+            predictions.insert(workspace_id, cached);
+        }
+    }
+
+    // Where a program should go, decay scales every score the same way so a cached
+    // winner stays the winner until the history changes
+    pub async fn predict(&self, class: &str, monitor: Option<&str>) -> Option<(WorkspaceId, f64)> {
+        let boosts = self.active_boosts().await;
+        let cached =
+            if boosts.is_empty() && self.scoring.time_of_day_weight <= 0.0 && !self.per_monitor {
+                self.predictions
+                    .0
+                    .lock()
+                    .await
+                    .get(&self.current_workspace())
+                    .and_then(|val| val.get(class))
+                    .copied()
+            } else {
+                None
+            };
+        if let Some(val) = cached {
+            debug!("Using prefetched prediction for {class}");
+            return Some(val);
+        }
+        let programs = self.programs.0.lock().await;
+        let program = programs.get(class)?;
+        let workspaces = self.monitor_history(&program.workspaces, monitor);
+        calculate_workspace(&workspaces, &self.scoring, &boosts, self.now())
+    }
+
+    // With per_monitor only what was learned on `monitor` is used, or the whole history while
+    // nothing was learned there yet
+    fn monitor_history<'a>(
+        &self,
+        workspaces: &'a [Workspace],
+        monitor: Option<&str>,
+    ) -> Cow<'a, [Workspace]> {
+        let monitor = match monitor {
+            Some(val) if self.per_monitor => val,
+            _ => return Cow::Borrowed(workspaces),
+        };
+        let history: Vec<Workspace> = workspaces
+            .iter()
+            .filter(|val| val.monitor.as_deref() == Some(monitor))
+            .cloned()
+            .collect();
+        if history.is_empty() {
+            Cow::Borrowed(workspaces)
+        } else {
+            Cow::Owned(history)
+        }
+    }
+
+    // The best scoring workspace other than `winner`, only used to explain a prediction
+    async fn runner_up(
+        &self,
+        class: &str,
+        monitor: Option<&str>,
+        winner: WorkspaceId,
+    ) -> Option<(WorkspaceId, f64)> {
+        let boosts = self.active_boosts().await;
+        let programs = self.programs.0.lock().await;
+        let program = programs.get(class)?;
+        let workspaces = self.monitor_history(&program.workspaces, monitor);
+        let scores = score_workspaces(&workspaces, &self.scoring, &boosts, self.now());
+        scores
+            .into_iter()
+            .filter(|(workspace_id, _)| *workspace_id != winner)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    // Marks the programs that are already confident so only new crossings are reported
+    pub async fn seed_confident(&self, threshold: f64, min_score: f64) {
+        let programs = self.programs.0.lock().await;
+        let mut confident = self.confident.lock().await;
+        for program in programs.values() {
+            if is_confident(
+                &program.workspaces,
+                &self.scoring,
+                threshold,
+                min_score,
+                self.now(),
+            )
+            .is_some()
+            {
+                confident.insert(program.class.clone());
+            }
+        }
+    }
+
+    // Returns the class and its workspace the first time the window's program becomes confident
+    pub async fn newly_confident(
+        &self,
+        address: &Address,
+        threshold: f64,
+        min_score: f64,
+    ) -> Option<(String, WorkspaceId)> {
+        let class = self.addresses.0.lock().await.get(address)?.class.clone();
+        let workspace_id = {
+            let programs = self.programs.0.lock().await;
+            let program = programs.get(&class)?;
+            is_confident(
+                &program.workspaces,
+                &self.scoring,
+                threshold,
+                min_score,
+                self.now(),
+            )?
+        };
+        self.confident
+            .lock()
+            .await
+            .insert(class.clone())
+            .then_some((class, workspace_id))
+    }
+
+    // Adds `weight` to the score of a workspace for every program that has been on it,
+    // for the next `secs` seconds. Nothing is stored
+    pub async fn boost(&self, workspace_id: WorkspaceId, weight: f64, secs: i64) {
+        let expiry = self.now().timestamp() + secs;
+        self.boosts
+            .0
+            .lock()
+            .await
+            .insert(workspace_id, (weight, expiry));
+        info!("Boosting workspace {workspace_id} by {weight} for {secs}s");
+    }
+
+    async fn active_boosts(&self) -> HashMap<WorkspaceId, f64> {
+        let now = self.now().timestamp();
+        let mut boosts = self.boosts.0.lock().await;
+        boosts.retain(|_, (_, expiry)| *expiry > now);
+        boosts
+            .iter()
+            .map(|(workspace_id, (weight, _))| (*workspace_id, *weight))
+            .collect()
+    }
+
+    pub async fn move_window(
+        &self,
+        address: &Address,
+        workspace_id: WorkspaceId,
+    ) -> Result<MoveResult, Error> {
+        let now = self.now().timestamp_millis();
+        // Only what the move needs is copied out, hyprland is asked after the locks are let go
+        let (class, output, name) = {
+            let addresses = self.addresses.0.lock().await;
+            let mut programs = self.programs.0.lock().await;
+
+            let window = match addresses.get(address) {
+                Some(val) => val,
+                None => return Err(Error::BlankAddress),
+            };
+
+            if !self
+                .workspace_matcher
+                .decision(&window.class, &self.workspace_mode)
+                || !self.is_program_enabled(&window.class).await
+            {
+                return Ok(MoveResult::Filtered);
+            }
+
+            // Bouncing events would otherwise move the same window back and forth
+            if self.move_cooldown_ms > 0
+                && let Some(last) = self.last_moves.0.lock().await.get(address)
+                && now - last < self.move_cooldown_ms
+            {
+                debug!(
+                    "Window {} was moved {}ms ago, not moving it again",
+                    fmt_address(address),
+                    now - last
+                );
+                return Ok(MoveResult::Filtered);
+            }
+
+            let program = match programs.get_mut(&window.class) {
+                Some(val) => val,
+                None => return Err(Error::BlankClass),
+            };
+
+            // Set before dispatching so the move event that follows is known to be nest's
+            program.moved = true;
+
+            let learned = program
+                .workspaces
+                .iter()
+                .rev()
+                .find(|val| val.workspace_id == workspace_id);
+            (
+                window.class.clone(),
+                learned.and_then(|val| val.output.clone()),
+                learned.and_then(|val| val.name.clone()),
+            )
+        };
+
+        // Learned ids may have shifted since, the output relative form knows where it is now
+        let workspace_id = match output {
+            Some(output) if self.portable => output
+                .resolve(&workspace_outputs(self.hypr.as_ref()).await)
+                .unwrap_or(workspace_id),
+            _ => workspace_id,
+        };
+
+        if let Some(monitor) = self.tie_break_monitor(workspace_id).await
+            && let Err(err) = self
+                .hypr
+                .dispatch(Action::FocusMonitor(monitor.clone()))
+                .await
+        {
+            debug!("Failed to focus monitor {monitor}: {err}");
+        }
+
+        // A named workspace is moved to by name, hyprland creates it again if it is gone
+        let special = special_name(self.hypr.as_ref(), workspace_id).await;
+        let target = match (special, &name) {
+            (Some(special), _) => Target::Special(special),
+            (None, Some(name)) => Target::Name(name.clone()),
+            (None, None) => Target::Id(workspace_id),
+        };
+        if self
+            .hypr
+            .dispatch(Action::MoveToWorkspace(target, address.clone()))
+            .await
+            .is_err()
+        {
+            if let Some(program) = self.programs.0.lock().await.get_mut(&class) {
+                program.moved = false;
+            }
+            // Hyprland also refuses to move a window to the workspace it is on
+            if client_workspace(self.hypr.as_ref(), address).await == Some(workspace_id) {
+                return Ok(MoveResult::AlreadyThere);
+            }
+            self.metrics.inc(Counter::MoveFailures);
+            return Ok(MoveResult::Failed);
+        }
+
+        self.metrics.inc(Counter::Moves);
+        self.last_move
+            .store(self.now().timestamp(), Ordering::Relaxed);
+        if self.move_cooldown_ms > 0 {
+            self.last_moves.0.lock().await.insert(address.clone(), now);
+        }
+        if self.fast_adapt {
+            self.placements
+                .0
+                .lock()
+                .await
+                .insert(address.clone(), (workspace_id, self.now().timestamp()));
+        }
+        // The id of a recreated named workspace is not known up front
+        if self.verify_moves && name.is_none() {
+            verify_move(self.hypr.as_ref(), address, workspace_id).await;
+        }
+        Ok(MoveResult::Moved)
+    }
+
+    // Geometry of the monitor floating windows are restored on, the one a window opened on
+    // falls back to the focused one
+    async fn window_monitor(&self, address: &Address) -> Option<MonitorRect> {
+        let name = match self.floating_restore {
+            MonitorRestore::Opened => self
+                .addresses
+                .0
+                .lock()
+                .await
+                .get(address)
+                .and_then(|window| window.monitor.clone()),
+            MonitorRestore::Focused => None,
+        };
+        let monitors = match self.hypr.monitors().await {
+            Ok(val) => val,
+            Err(err) => {
+                debug!("Failed to fetch monitors: {err}");
+                return None;
+            }
+        };
+        monitors
+            .iter()
+            .find(|monitor| Some(&monitor.name) == name.as_ref())
+            .or_else(|| monitors.iter().find(|monitor| monitor.focused))
+            .map(monitor_rect)
+    }
+
+    // Picks the monitor to act on when the workspace is shown on several, None if there is no tie
+    async fn tie_break_monitor(&self, workspace_id: WorkspaceId) -> Option<String> {
+        let monitors = match self.hypr.monitors().await {
+            Ok(val) => val,
+            Err(err) => {
+                debug!("Failed to fetch monitors: {err}");
+                return None;
+            }
+        };
+        let mut showing: Vec<MonitorInfo> = monitors
+            .into_iter()
+            .filter(|monitor| monitor.active_workspace == workspace_id)
+            .collect();
+        if showing.len() < 2 {
+            return None;
+        }
+        showing.sort_by_key(|monitor| monitor.id);
+        let monitor = match self.tie_break {
+            TieBreak::ActiveMonitor => showing
+                .iter()
+                .find(|monitor| monitor.focused)
+                .unwrap_or(&showing[0]),
+            TieBreak::LowestMonitor => &showing[0],
+        };
+        Some(monitor.name.clone())
+    }
+
+    pub async fn add_floating_window(
+        &self,
+        class: &str,
+        window: FloatingWindow,
+    ) -> Result<(), Error> {
+        let mut programs = self.programs.0.lock().await;
+
+        let program = match programs.get_mut(class) {
+            Some(val) => val,
+            None => return Err(Error::BlankClass),
+        };
+
+        let change = match &program.floating_window {
+            Some(last) => !last.approx_eq(&window),
+            None => true,
+        };
+
+        if !change {
+            return Ok(());
+        }
+
+        // Windows that keep resizing themselves are only recorded once per interval
+        let interval = self
+            .class_override(class)
+            .and_then(|val| val.capture_interval_secs)
+            .unwrap_or(self.capture_interval_secs);
+        let now = self.now().timestamp();
+        let mut captures = self.captures.0.lock().await;
+        if program.floating_window.is_some()
+            && interval > 0
+            && let Some(last) = captures.get(class)
+            && now - last < interval
+        {
+            debug!(
+                "Geometry of {class} was recorded {}s ago, skipping",
+                now - last
+            );
+            return Ok(());
+        }
+        captures.insert(class.to_string(), now);
+        program.floating_window = Some(window);
+        self.changed.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    // Returns true if nest positioned a floating window of the class since the last call,
+    // the geometry read right after that is compositor noise and should not be learned
+    pub async fn take_float_moved(&self, class: &str) -> bool {
+        let mut programs = self.programs.0.lock().await;
+        match programs.get_mut(class) {
+            Some(program) => std::mem::take(&mut program.float_moved),
+            None => false,
+        }
+    }
+
+    pub async fn remove_floating_window(&self, class: &str) -> Result<(), Error> {
+        let mut programs = self.programs.0.lock().await;
+
+        let program = match programs.get_mut(class) {
+            Some(val) => val,
+            None => return Err(Error::BlankClass),
+        };
+
+        program.floating_window = None;
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn move_float_window(
+        &self,
+        address: &Address,
+        floating_window: &FloatingWindow,
+    ) -> Result<bool, Error> {
+        let monitor = self.window_monitor(address).await;
+        let (at, size) = floating_window.pixels(monitor);
+        let (at, size) = self.floating_fit.apply(at, size, monitor);
+        // Some programs open floating already, toggling those would tile them
+        let toggle = !is_floating(self.hypr.as_ref(), address).await;
+        let addresses = self.addresses.0.lock().await;
+        let mut programs = self.programs.0.lock().await;
+
+        let window = match addresses.get(address) {
+            Some(val) => val,
+            None => return Err(Error::BlankAddress),
+        };
+
+        if !self.manages_floating(&window.class) {
+            return Ok(false);
+        }
+
+        let program = match programs.get_mut(&window.class) {
+            Some(val) => val,
+            None => return Err(Error::BlankClass),
+        };
+
+        program.float_moved = true;
+
+        if toggle
+            && self
+                .hypr
+                .dispatch(Action::ToggleFloating(address.clone()))
+                .await
+                .is_err()
+        {
+            program.float_moved = false;
+            return Ok(false);
+        }
+
+        match self
+            .hypr
+            .dispatch(Action::MoveWindowPixel(at, address.clone()))
+            .await
+        {
+            Ok(_) => (),
+            Err(err) => {
+                program.float_moved = false;
+                rollback_floating(self.hypr.as_ref(), address, toggle, err).await;
+                return Ok(false);
+            }
+        }
+
+        match self
+            .hypr
+            .dispatch(Action::ResizeWindowPixel(size, address.clone()))
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                program.float_moved = false;
+                rollback_floating(self.hypr.as_ref(), address, toggle, err).await;
+                Ok(false)
+            }
+        }
+    }
+
+    // Applies every pruning rule at once, the next save writes the smaller file
+    pub async fn compact(&self) -> CompactReport {
+        let mut report = CompactReport::default();
+        let now = self.now().timestamp();
+        {
+            let mut programs = self.programs.0.lock().await;
+            let live_counts = self.live_counts.0.lock().await;
+            // Programs with open windows are kept, their windows still point at them
+            programs.retain(|class, program| {
+                let keep = self.compaction.apply(program, &mut report, now)
+                    || live_counts.contains_key(class);
+                if !keep {
+                    report.programs += 1;
+                }
+                keep
+            });
+        }
+        if !report.is_empty() {
+            self.changed.store(true, Ordering::Relaxed);
+            self.invalidate_predictions().await;
+        }
+        report
+    }
+
+    // Replaces the learned state with what was read from storage. With `merge` programs only
+    // known in memory are kept, otherwise only those that still have open windows. Returns how
+    // many programs nest knows about afterwards
+    pub async fn reload(&self, programs: Vec<Program>, merge: bool) -> usize {
+        let mut kept = 0;
+        let count = {
+            let mut programs_map = self.programs.0.lock().await;
+            let live_counts = self.live_counts.0.lock().await;
+            let mut reloaded: HashMap<String, Program> = HashMap::with_capacity(programs.len());
+            for mut program in programs.into_iter().filter(|program| !program.is_empty()) {
+                // Moves nest is still waiting to see stay marked as its own
+                if let Some(current) = programs_map.get(&program.class) {
+                    program.moved = current.moved;
+                    program.float_moved = current.float_moved;
+                }
+                match reloaded.get_mut(&program.class) {
+                    Some(existing) => existing.merge(program, self.buffer_for(&existing.class)),
+                    None => {
+                        reloaded.insert(program.class.clone(), program);
+                    }
+                }
+            }
+            // Open windows point at their program, so those are never dropped
+            for (class, program) in programs_map.drain() {
+                if !reloaded.contains_key(&class) && (merge || live_counts.contains_key(&class)) {
+                    reloaded.insert(class, program);
+                    kept += 1;
+                }
+            }
+            *programs_map = reloaded;
+            programs_map.len()
+        };
+        // Anything only known in memory still has to be written
+        self.changed.store(kept > 0, Ordering::Relaxed);
+        self.invalidate_predictions().await;
+        info!("Reloaded storage, {count} programs, {kept} kept from memory");
+        count
+    }
+
+    // Removes a program and every window mapped to it, returns true if anything was removed
+    pub async fn forget_program(&self, class: &str) -> bool {
+        let mut addresses = self.addresses.0.lock().await;
+        let mut programs = self.programs.0.lock().await;
+        let mut live_counts = self.live_counts.0.lock().await;
+
+        let program = programs.remove(class);
+        let removed: Vec<Address> = addresses
+            .iter()
+            .filter(|(_, window)| window.class == class)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in &removed {
+            addresses.remove(address);
+            self.forget_window(address).await;
+        }
+        let removed_windows = removed.len();
+        live_counts.remove(class);
+        self.captures.0.lock().await.remove(class);
+        self.learns.0.lock().await.remove(class);
+
+        if program.is_none() && removed_windows == 0 {
+            return false;
+        }
+        if program.is_some() {
+            self.changed.store(true, Ordering::Relaxed);
+            self.invalidate_predictions().await;
+        }
+        info!("Forgot program of type {class} and {removed_windows} open windows");
+        true
+    }
+
+    // Disabled programs are left alone until enabled again, this is never saved
+    pub async fn set_program_enabled(&self, class: &str, enabled: bool) {
+        let mut disabled = self.disabled.lock().await;
+        if enabled {
+            disabled.remove(class);
+        } else {
+            disabled.insert(class.to_string());
+        }
+    }
+
+    pub async fn session_overrides(&self) -> SessionOverrides {
+        let mut disabled: Vec<String> = self.disabled.lock().await.iter().cloned().collect();
+        disabled.sort();
+        let now = self.now().timestamp();
+        let mut boosts: Vec<Boost> = self
+            .boosts
+            .0
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry > now)
+            .map(|(workspace_id, (weight, expiry))| Boost {
+                workspace: workspace_id.0,
+                weight: *weight,
+                remaining: expiry - now,
+            })
+            .collect();
+        boosts.sort_by_key(|val| val.workspace);
+        SessionOverrides { disabled, boosts }
+    }
+
+    // Drops every session override, leaving only what the config says
+    pub async fn clear_session_overrides(&self) {
+        self.disabled.lock().await.clear();
+        self.boosts.0.lock().await.clear();
+        info!("Cleared session overrides");
+    }
+
+    pub async fn is_program_enabled(&self, class: &str) -> bool {
+        !self.disabled.lock().await.contains(class)
+    }
+
+    // True if the floating filter lets nest manage windows of the class
+    pub fn manages_floating(&self, class: &str) -> bool {
+        self.floating_matcher.decision(class, &self.floating_mode)
+    }
+
+    pub async fn get_program(&self, class: String) -> Option<Program> {
+        let programs = self.programs.0.lock().await;
+        programs.get(&class).cloned()
+    }
+
+    // Sorted by class so saving the same state always writes the same file
+    pub async fn get_programs(&self) -> Vec<Program> {
+        let programs = self.programs.0.lock().await;
+        let mut val: Vec<Program> = programs.values().cloned().collect();
+        val.sort_by(|a, b| a.class.cmp(&b.class));
+        val
+    }
+
+    pub async fn get_mapped_programs(&self) -> HashMap<String, Program> {
+        let programs = self.programs.0.lock().await;
+        programs.clone()
+    }
+
+    // Hyprland does not expose a parent relationship, so a floating window opened next to
+    // another window of the same class is treated as a dialog of that window
+    async fn is_transient(&self, floating: bool, class: &str) -> bool {
+        floating && self.live_count(class).await >= 2
+    }
+
+    // Number of windows of the class that are currently open
+    pub async fn live_count(&self, class: &str) -> usize {
+        let live_counts = self.live_counts.0.lock().await;
+        live_counts.get(class).copied().unwrap_or(0)
+    }
+
+    pub fn count(&self, counter: Counter) {
+        self.metrics.inc(counter);
+    }
+
+    pub fn mark_saved(&self) {
+        self.metrics.inc(Counter::Saves);
+        self.last_save
+            .store(self.now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub async fn health(&self) -> Health {
+        let programs = self.programs.0.lock().await.len();
+        let last_save = self.last_save.load(Ordering::Relaxed);
+        Health {
+            uptime: (self.now() - self.started).num_seconds(),
+            programs,
+            last_save: (last_save != 0).then_some(last_save),
+            connected: self.connected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub async fn metrics(&self) -> String {
+        self.metrics.exposition(&self.health().await)
+    }
+
+    pub async fn status(&self) -> Status {
+        let health = self.health().await;
+        Status {
+            workspace: Some(self.current_workspace().0),
+            programs: health.programs,
+            last_save_age: health
+                .last_save
+                .map(|last_save| self.now().timestamp() - last_save),
+            healthy: health.connected,
+        }
+    }
+
+    pub async fn workspace_changed(&self, id: WorkspaceId) {
+        // Until things settle origin_workspace asks hyprland instead of trusting this
+        self.current_workspace.store(id.0, Ordering::Relaxed);
+        {
+            // With a single monitor the event can only be about that one
+            let mut monitor_workspaces = self.monitor_workspaces.0.lock().await;
+            if monitor_workspaces.len() == 1 {
+                monitor_workspaces.values_mut().for_each(|val| *val = id);
+                return;
+            }
+        }
+        // The event does not say which monitor changed, so ask for all of them
+        self.seed_monitor_workspaces().await;
+    }
+
+    // Reads the active workspace of every monitor from hyprland
+    pub async fn seed_monitor_workspaces(&self) {
+        let monitors = match self.hypr.monitors().await {
+            Ok(val) => val,
+            Err(err) => {
+                debug!("Failed to fetch monitors: {err}");
+                return;
+            }
+        };
+        let mut monitor_workspaces = self.monitor_workspaces.0.lock().await;
+        monitor_workspaces.clear();
+        for monitor in monitors {
+            monitor_workspaces.insert(monitor.id, monitor.active_workspace);
+        }
+    }
+
+    pub async fn current_workspace_for_monitor(&self, id: MonitorId) -> Option<WorkspaceId> {
+        self.monitor_workspaces.0.lock().await.get(&id).copied()
+    }
+
+    // Ends the startup period, after this workspace events are trusted
+    pub fn settle(&self) {
+        self.settling.store(false, Ordering::Relaxed);
+    }
+
+    // Right after startup the first workspace event can disagree with the tracked workspace,
+    // so until things settle the active workspace is asked for directly
+    pub async fn origin_workspace(&self) -> WorkspaceId {
+        if self.settling.load(Ordering::Relaxed) {
+            match self.hypr.active_workspace().await {
+                Ok(workspace) => self
+                    .current_workspace
+                    .store(workspace.id.0, Ordering::Relaxed),
+                Err(err) => debug!("Failed to fetch active workspace: {err}"),
+            }
+        }
+        self.current_workspace()
+    }
+
+    pub fn current_workspace(&self) -> WorkspaceId {
+        WorkspaceId(self.current_workspace.load(Ordering::Relaxed))
+    }
+
+    // Seconds since the state was last saved, None if it has not been saved yet
+    pub fn since_last_save(&self) -> Option<i64> {
+        match self.last_save.load(Ordering::Relaxed) {
+            0 => None,
+            val => Some(self.now().timestamp() - val),
+        }
+    }
+
+    // Seconds since the last window nest moved, None if it has not moved any
+    pub fn since_last_move(&self) -> Option<i64> {
+        match self.last_move.load(Ordering::Relaxed) {
+            0 => None,
+            val => Some(self.now().timestamp() - val),
+        }
+    }
+
+    // Moving windows drags focus along, this takes it back to the workspace it was on
+    pub async fn restore_focus(&self, workspace_id: WorkspaceId) {
+        if self.since_last_move().is_none() || self.current_workspace() == workspace_id {
+            return;
+        }
+        info!("Restoring focus to workspace {workspace_id}");
+        if let Err(err) = self
+            .hypr
+            .dispatch(Action::Workspace(Target::Id(workspace_id)))
+            .await
+        {
+            warn!("Failed to restore focus to workspace {workspace_id}: {err}");
+        }
+    }
+}
+
+// A dispatch being accepted does not mean it was applied, this checks where the window ended
+// up. False when it is somewhere else or gone, a failed query is given the benefit of the doubt
+async fn verify_move(hypr: &dyn Hypr, address: &Address, workspace_id: WorkspaceId) -> bool {
+    let clients = match hypr.clients().await {
+        Ok(val) => val,
+        Err(err) => {
+            debug!("Failed to fetch clients: {err}");
+            return true;
+        }
+    };
+    match clients
+        .into_iter()
+        .find(|client| &client.address == address)
+    {
+        Some(client) if client.workspace != workspace_id => {
+            warn!(
+                "Window {} was moved to {workspace_id} but is on {}",
+                fmt_address(address),
+                client.workspace
+            );
+            false
+        }
+        Some(_) => {
+            debug!(
+                "Verified window {} is on {workspace_id}",
+                fmt_address(address)
+            );
+            true
+        }
+        None => {
+            warn!(
+                "Window {} was moved to {workspace_id} but could not be found",
+                fmt_address(address)
+            );
+            false
+        }
+    }
+}
+
+// Every workspace id paired with the name of the output it is on
+async fn workspace_outputs(hypr: &dyn Hypr) -> Vec<(WorkspaceId, String)> {
+    match hypr.workspaces().await {
+        Ok(workspaces) => workspaces
+            .into_iter()
+            .map(|workspace| (workspace.id, workspace.monitor))
+            .collect(),
+        Err(err) => {
+            debug!("Failed to fetch workspaces: {err}");
+            Vec::new()
+        }
+    }
+}
+
+// The name of a named workspace, None for numbered and special ones or names the storage
+// format can not hold
+async fn workspace_name(hypr: &dyn Hypr, workspace_id: WorkspaceId) -> Option<String> {
+    if workspace_id.is_special() {
+        return None;
+    }
+    let workspaces = match hypr.workspaces().await {
+        Ok(val) => val,
+        Err(err) => {
+            debug!("Failed to fetch workspaces: {err}");
+            return None;
+        }
+    };
+    let name = workspaces
+        .into_iter()
+        .find(|workspace| workspace.id == workspace_id)?
+        .name;
+    storable_name(workspace_id, &name)
+}
+
+// The name of a workspace if it is not just its id and the storage format can hold it
+fn storable_name(workspace_id: WorkspaceId, name: &str) -> Option<String> {
+    (!workspace_id.is_special()
+        && name != workspace_id.to_string()
+        && !name.contains([':', ';', ',', '&', '[', ']']))
+    .then(|| name.to_string())
+}
+
+// Special workspaces have negative ids and can only be targeted by name
+async fn special_name(hypr: &dyn Hypr, workspace_id: WorkspaceId) -> Option<String> {
+    if !workspace_id.is_special() {
+        return None;
+    }
+    match hypr.workspaces().await {
+        Ok(workspaces) => workspaces
+            .into_iter()
+            .find(|workspace| workspace.id == workspace_id)
+            .map(|workspace| workspace.name.trim_start_matches("special:").to_string()),
+        Err(err) => {
+            debug!("Failed to fetch workspaces: {err}");
+            None
+        }
+    }
+}
+
+// Picks the highest scoring workspace of a history
+pub fn calculate_workspace(
+    workspaces: &[Workspace],
+    scoring: &Scoring,
+    boosts: &HashMap<WorkspaceId, f64>,
+    now: DateTime<Utc>,
+) -> Option<(WorkspaceId, f64)> {
+    score_workspaces(workspaces, scoring, boosts, now)
+        .into_iter()
+        .max_by(|a, b| {
+            if a.1 > b.1 {
+                cmp::Ordering::Greater
+            } else if a.1 < b.1 {
+                cmp::Ordering::Less
+            } else {
+                cmp::Ordering::Equal
+            }
+        })
+}
+
+// Sums the weight of every workspace in a history decayed up to `now`, boosts only count for
+// workspaces that are in the history
+pub fn score_workspaces(
+    workspaces: &[Workspace],
+    scoring: &Scoring,
+    boosts: &HashMap<WorkspaceId, f64>,
+    now: DateTime<Utc>,
+) -> HashMap<WorkspaceId, f64> {
+    let mut score_map: HashMap<WorkspaceId, f64> = HashMap::new();
+    let local = now.with_timezone(&Local);
+    let now = now.timestamp();
+    // Workspaces outside the range are left out so the best one inside it can win
+    for workspace in workspaces
+        .iter()
+        .filter(|val| scoring.allows(val.workspace_id))
+    {
+        let mut score = workspace.weight * workspace.decay(now, scoring.tau);
+        if scoring.time_of_day_weight > 0.0 {
+            score *= workspace.time_of_day(&local, scoring.time_of_day_weight);
+        }
+        // A corrupt weight would otherwise poison the whole sum
+        if score.is_nan() {
+            debug!("Ignoring a position with an invalid weight");
+            continue;
+        }
+        debug!("Position got a score of {score}");
+        match score_map.get(&workspace.workspace_id) {
+            Some(val) => score_map.insert(workspace.workspace_id, saturate(*val + score)),
+            None => score_map.insert(workspace.workspace_id, saturate(score)),
+        };
+    }
+    for (workspace_id, boost) in boosts {
+        if let Some(val) = score_map.get_mut(workspace_id) {
+            *val = saturate(*val + boost);
+        }
+    }
+    score_map
+}
+
+// Keeps scores finite and well apart from the limits of f64 so sums and ratios stay meaningful,
+// real histories are many orders of magnitude below the cap
+fn saturate(score: f64) -> f64 {
+    score.clamp(-MAX_SCORE, MAX_SCORE)
+}
+
+// The best workspace if it holds at least `threshold` of the total score and has a score of
+// at least `min_score`, so a single move does not count as confident
+fn is_confident(
+    workspaces: &[Workspace],
+    scoring: &Scoring,
+    threshold: f64,
+    min_score: f64,
+    now: DateTime<Utc>,
+) -> Option<WorkspaceId> {
+    let scores = score_workspaces(workspaces, scoring, &HashMap::new(), now);
+    let (workspace_id, score) = scores
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(workspace_id, score)| (*workspace_id, *score))?;
+    let total: f64 = scores.values().sum();
+    (score >= min_score && score / total >= threshold).then_some(workspace_id)
+}
+
+// A window that was made floating but could not be placed is tiled again, a floating window
+// stuck at a random size is worse than one nest did not touch
+async fn rollback_floating(hypr: &dyn Hypr, address: &Address, toggled: bool, err: HyprError) {
+    if !toggled {
+        warn!(
+            "Failed to place floating window {}: {err}",
+            fmt_address(address)
+        );
+        return;
+    }
+    warn!(
+        "Failed to place floating window {}: {err}, making it tiled again",
+        fmt_address(address)
+    );
+    if let Err(err) = hypr.dispatch(Action::ToggleFloating(address.clone())).await {
+        warn!(
+            "Failed to undo floating for window {}, it is left floating: {err}",
+            fmt_address(address)
+        );
+    }
+}
+
+async fn set_property(
+    hypr: &dyn Hypr,
+    address: &Address,
+    property: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let args = format!("address:{} {property} {value}", fmt_address(address));
+    hypr.dispatch(Action::Custom("setprop".to_string(), args))
+        .await?;
+    Ok(())
+}
+
+// Warns about keys of the `nth` tables that are not a positive number, those never match
+fn check_nth_rules(overrides: &BTreeMap<String, ClassOverride>) {
+    for (class, val) in overrides {
+        for nth in val.nth.keys() {
+            if !nth.parse::<usize>().is_ok_and(|n| n > 0) {
+                warn!("Ignoring nth rule {nth} of {class}, expected a positive number");
+            }
+        }
+    }
+}
+
+// `current` leaves new programs to the normal placement, anything else should be a workspace id
+fn new_program_target(target: &str) -> Option<WorkspaceId> {
+    if target == "current" {
+        return None;
+    }
+    match target.parse() {
+        Ok(val) => Some(val),
+        Err(err) => {
+            warn!("Invalid new_program_target {target}: {err}, using the current workspace");
+            None
+        }
+    }
+}
+
+// Unknown windows count as tiled, they get toggled like before the state was checked
+async fn is_floating(hypr: &dyn Hypr, address: &Address) -> bool {
+    match hypr.clients().await {
+        Ok(clients) => clients
+            .into_iter()
+            .any(|client| &client.address == address && client.floating),
+        Err(err) => {
+            debug!("Failed to fetch clients: {err}");
+            false
+        }
+    }
+}
+
+// The open event only names the workspace, which is its id unless the workspace was renamed
+async fn open_workspace(
+    hypr: &dyn Hypr,
+    address: &Address,
+    workspace_name: &str,
+) -> Option<WorkspaceId> {
+    if let Ok(id) = workspace_name.parse() {
+        return Some(id);
+    }
+    client_workspace(hypr, address).await
+}
+
+async fn client_workspace(hypr: &dyn Hypr, address: &Address) -> Option<WorkspaceId> {
+    match hypr.clients().await {
+        Ok(clients) => clients
+            .into_iter()
+            .find(|client| &client.address == address)
+            .map(|client| client.workspace),
+        Err(err) => {
+            debug!("Failed to fetch clients: {err}");
+            None
+        }
+    }
+}
+
+// Hyprland splits new windows by the cursor, when it picked the other direction the split is
+// toggled. togglesplit works on the focused window, so a window that opened without focus is left
+async fn restore_split(hypr: &dyn Hypr, address: &Address, split: Split) -> Result<(), Error> {
+    let active = match hypr.active_client().await? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+    if &active.address != address {
+        return Ok(());
+    }
+    let clients = hypr.clients().await?;
+    let monitors = hypr.monitors().await?;
+    match Split::observe(&active, &clients, &monitors) {
+        Some(val) if val != split => {
+            hypr.dispatch(Action::Custom(
+                "layoutmsg".to_string(),
+                "togglesplit".to_string(),
+            ))
+            .await?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Runs an on_close command through the shell, nest only waits for it to reap the process
+fn run_close_hook(command: &str, class: &str, address: &Address) {
+    let mut child = match tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("NEST_CLASS", class)
+        .env("NEST_ADDRESS", fmt_address(address))
+        .spawn()
+    {
+        Ok(val) => val,
+        Err(err) => {
+            warn!("Failed to run on_close for {class}: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => debug!("on_close command exited with {status}"),
+            Ok(_) => (),
+            Err(err) => warn!("Failed to wait for on_close command: {err}"),
+        }
+    });
+}
+
+async fn focused_monitor(hypr: &dyn Hypr) -> Option<MonitorInfo> {
+    match hypr.monitors().await {
+        Ok(monitors) => monitors.into_iter().find(|monitor| monitor.focused),
+        Err(err) => {
+            debug!("Failed to fetch monitors: {err}");
+            None
+        }
+    }
+}
+
+fn decrement(live_counts: &mut HashMap<String, usize>, class: &str) {
+    if let Some(count) = live_counts.get_mut(class) {
+        *count -= 1;
+        if *count == 0 {
+            live_counts.remove(class);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Entries made just now, so decay leaves their weight as it is
+    fn history(entries: &[(i32, f64)]) -> Vec<Workspace> {
+        let now = Utc::now().timestamp();
+        entries
+            .iter()
+            .map(|(workspace_id, weight)| {
+                let mut workspace = Workspace::new(WorkspaceId(*workspace_id), now);
+                workspace.weight = *weight;
+                workspace
+            })
+            .collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn scores_sum_per_workspace() {
+        let scores = score_workspaces(
+            &history(&[(1, 1.0), (2, 1.0), (1, 1.0)]),
+            &Scoring::default(),
+            &HashMap::new(),
+            Utc::now(),
+        );
+        assert!(close(scores[&WorkspaceId(1)], 2.0));
+        assert!(close(scores[&WorkspaceId(2)], 1.0));
+        let (workspace_id, _) = calculate_workspace(
+            &history(&[(1, 1.0), (2, 1.0), (1, 1.0)]),
+            &Scoring::default(),
+            &HashMap::new(),
+            Utc::now(),
+        )
+        .unwrap();
+        assert_eq!(workspace_id, WorkspaceId(1));
+    }
+
+    #[test]
+    fn older_entries_count_less() {
+        let mut workspaces = history(&[(1, 1.0), (2, 1.0)]);
+        workspaces[0].timestamp -= 604800;
+        let scores = score_workspaces(
+            &workspaces,
+            &Scoring::default(),
+            &HashMap::new(),
+            Utc::now(),
+        );
+        assert!(close(scores[&WorkspaceId(1)], (-1.0f64).exp()));
+        assert!(close(scores[&WorkspaceId(2)], 1.0));
+    }
+
+    #[test]
+    fn range_and_boosts() {
+        let scoring = Scoring {
+            range: 1..=4,
+            ..Scoring::default()
+        };
+        let boosts = HashMap::from([(WorkspaceId(2), 5.0), (WorkspaceId(3), 5.0)]);
+        let scores = score_workspaces(
+            &history(&[(1, 1.0), (2, 1.0), (9, 1.0), (-98, 1.0)]),
+            &scoring,
+            &boosts,
+            Utc::now(),
+        );
+        assert!(!scores.contains_key(&WorkspaceId(9)));
+        // Special workspaces are never out of range
+        assert!(scores.contains_key(&WorkspaceId(-98)));
+        // Boosts only add to workspaces in the history
+        assert!(close(scores[&WorkspaceId(2)], 6.0));
+        assert!(!scores.contains_key(&WorkspaceId(3)));
+    }
+
+    #[test]
+    fn huge_and_invalid_weights() {
+        let scores = score_workspaces(
+            &history(&[(1, f64::MAX), (1, f64::MAX), (2, f64::NAN), (2, 1.0)]),
+            &Scoring::default(),
+            &HashMap::new(),
+            Utc::now(),
+        );
+        assert_eq!(scores[&WorkspaceId(1)], MAX_SCORE);
+        assert!(close(scores[&WorkspaceId(2)], 1.0));
+        assert_eq!(saturate(f64::INFINITY), MAX_SCORE);
+        assert_eq!(saturate(f64::NEG_INFINITY), -MAX_SCORE);
+        assert_eq!(saturate(3.0), 3.0);
+    }
+
+    #[test]
+    fn confidence_needs_share_and_score() {
+        let scoring = Scoring::default();
+        let workspaces = history(&[(1, 1.0), (1, 1.0), (1, 1.0), (2, 1.0)]);
+        assert_eq!(
+            is_confident(&workspaces, &scoring, 0.7, 2.0, Utc::now()),
+            Some(WorkspaceId(1))
+        );
+        assert_eq!(
+            is_confident(&workspaces, &scoring, 0.8, 2.0, Utc::now()),
+            None
+        );
+        assert_eq!(
+            is_confident(&history(&[(1, 1.0)]), &scoring, 0.5, 2.0, Utc::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn storable_names() {
+        assert_eq!(
+            storable_name(WorkspaceId(-1337), "music"),
+            Some("music".to_string())
+        );
+        assert_eq!(
+            storable_name(WorkspaceId(5), "music"),
+            Some("music".to_string())
+        );
+        assert_eq!(storable_name(WorkspaceId(5), "5"), None);
+        assert_eq!(storable_name(WorkspaceId(-98), "special:scratch"), None);
+        // The storage format splits on these
+        assert_eq!(storable_name(WorkspaceId(5), "a;b"), None);
     }
 
-    pub fn current_workspace(&self) -> i32 {
-        self.current_workspace.load(Ordering::Relaxed)
+    #[tokio::test]
+    async fn verify_reports_mismatch() {
+        let hypr = hypr::MockHypr::default();
+        let address = Address::new("a");
+        hypr.set_clients(vec![ClientInfo {
+            address: address.clone(),
+            class: "kitty".to_string(),
+            workspace: WorkspaceId(2),
+            monitor: Some(MonitorId(0)),
+            floating: false,
+            at: (0, 0),
+            size: (960, 1080),
+        }]);
+        assert!(verify_move(&hypr, &address, WorkspaceId(2)).await);
+        // Accepted but never applied
+        assert!(!verify_move(&hypr, &address, WorkspaceId(3)).await);
+        // Closed before it could be checked
+        assert!(!verify_move(&hypr, &Address::new("b"), WorkspaceId(2)).await);
     }
 }
@@ -0,0 +1,114 @@
+use std::fmt::Display;
+
+use crate::state::{Error, FloatingWindow, WorkspaceId};
+
+// What nest decided when a window opened
+#[derive(Debug)]
+pub struct OpenOutcome {
+    // Where the window should live and its score, None when there was nothing to go on
+    pub prediction: Option<(WorkspaceId, f64)>,
+    // Second best workspace from the history and its score
+    pub runner_up: Option<(WorkspaceId, f64)>,
+    pub placement: Placement,
+    pub floating: FloatPlacement,
+}
+
+#[derive(Debug)]
+pub enum Placement {
+    // The program is disabled or the window was already tracked
+    Untracked,
+    // Workspace placement is off, it is quiet hours or the filter left the window alone
+    Skipped,
+    // Nothing to predict from and `on_no_prediction` leaves the window where it opened
+    NoPrediction,
+    // The window opened on the predicted workspace
+    AlreadyThere(WorkspaceId),
+    // The class asks first, the move waits for `confirm`
+    Asked(WorkspaceId),
+    Moved(WorkspaceId),
+    // The move was not completed, the window may already be in the right place
+    Failed(WorkspaceId),
+    Error(Error),
+}
+
+impl Placement {
+    pub fn from_move(result: MoveResult, workspace_id: WorkspaceId) -> Self {
+        match result {
+            MoveResult::Filtered => Placement::Skipped,
+            MoveResult::Moved => Placement::Moved(workspace_id),
+            MoveResult::Failed => Placement::Failed(workspace_id),
+            MoveResult::AlreadyThere => Placement::AlreadyThere(workspace_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FloatPlacement {
+    // Floating memory is off, or the window was not placed at all
+    Skipped,
+    // Nothing remembered for the program
+    NoGeometry,
+    Moved(FloatingWindow),
+    Failed(FloatingWindow),
+    Error(Error),
+}
+
+// What move_window did with a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoveResult {
+    // The filter, a disabled class or the move cooldown kept it where it is
+    Filtered,
+    Moved,
+    // Hyprland refused the move and the window is somewhere else
+    Failed,
+    // Hyprland refused the move because the window is on the workspace already
+    AlreadyThere,
+}
+
+// What window_moved made of a move event
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Moved {
+    // Nest moved the window itself
+    Internal,
+    // The window moved itself, or the move does not look like it came from the user
+    NotUser,
+    // The user moved the window but the move was not learned, e.g. a frozen class
+    NotLearned,
+    Learned,
+}
+
+impl Moved {
+    pub fn by_user(self) -> bool {
+        matches!(self, Moved::NotLearned | Moved::Learned)
+    }
+}
+
+// What remove_window did after a window closed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemoveResult {
+    // The workspace the window was opened from was shown again
+    Restored,
+    // Restoring does not apply to the window, or its on_close action replaced it
+    NotRestored,
+    // Nest was not tracking the window
+    NotTracked,
+}
+
+// A window `apply` moved, or would move on a dry run
+#[derive(Debug)]
+pub struct Applied {
+    pub class: String,
+    pub from: WorkspaceId,
+    pub to: WorkspaceId,
+    pub moved: bool,
+}
+
+impl Display for Applied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", self.class, self.from, self.to)?;
+        if !self.moved {
+            write!(f, " (not moved)")?;
+        }
+        Ok(())
+    }
+}
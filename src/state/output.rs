@@ -0,0 +1,110 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::state::{ParseError, WorkspaceId};
+
+// A workspace described by the output it lives on and its position among that output's
+// workspaces, unlike ids this survives monitors being renamed or reordered
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputWorkspace {
+    pub output: String,
+    pub index: usize,
+}
+
+impl OutputWorkspace {
+    // Finds the output relative form of a workspace id, workspaces are (id, output) pairs
+    pub fn locate(workspace_id: WorkspaceId, workspaces: &[(WorkspaceId, String)]) -> Option<Self> {
+        let output = workspaces
+            .iter()
+            .find(|(id, _)| *id == workspace_id)
+            .map(|(_, output)| output)?;
+        let index = ids_on(output, workspaces)
+            .iter()
+            .position(|id| *id == workspace_id)?;
+        Some(Self {
+            output: output.clone(),
+            index,
+        })
+    }
+
+    // Translates back to the id the workspace currently has
+    pub fn resolve(&self, workspaces: &[(WorkspaceId, String)]) -> Option<WorkspaceId> {
+        ids_on(&self.output, workspaces).get(self.index).copied()
+    }
+}
+
+fn ids_on(output: &str, workspaces: &[(WorkspaceId, String)]) -> Vec<WorkspaceId> {
+    let mut ids: Vec<WorkspaceId> = workspaces
+        .iter()
+        .filter(|(id, val)| !id.is_special() && val == output)
+        .map(|(id, _)| *id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+impl Display for OutputWorkspace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.output, self.index)
+    }
+}
+
+impl FromStr for OutputWorkspace {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (output, index) = match s.rsplit_once('@') {
+            Some(val) => val,
+            None => return Err(ParseError::InvalidFormat),
+        };
+        if output.is_empty() {
+            return Err(ParseError::InvalidFormat);
+        }
+        Ok(Self {
+            output: output.to_string(),
+            index: index.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspaces() -> Vec<(WorkspaceId, String)> {
+        vec![
+            (WorkspaceId(4), "DP-1".to_string()),
+            (WorkspaceId(1), "DP-1".to_string()),
+            (WorkspaceId(2), "HDMI-A-1".to_string()),
+            (WorkspaceId(-98), "DP-1".to_string()),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let output = OutputWorkspace::from_str("DP-1@2").unwrap();
+        assert_eq!(output.to_string(), "DP-1@2");
+        // Only the last @ separates the index
+        let output = OutputWorkspace::from_str("a@b@0").unwrap();
+        assert_eq!(output.output, "a@b");
+        assert!(OutputWorkspace::from_str("@1").is_err());
+        assert!(OutputWorkspace::from_str("DP-1").is_err());
+    }
+
+    #[test]
+    fn locate_and_resolve() {
+        let workspaces = workspaces();
+        let output = OutputWorkspace::locate(WorkspaceId(4), &workspaces).unwrap();
+        // Ids are ordered per output and special workspaces are skipped
+        assert_eq!(output.to_string(), "DP-1@1");
+        assert_eq!(output.resolve(&workspaces), Some(WorkspaceId(4)));
+        assert_eq!(OutputWorkspace::locate(WorkspaceId(9), &workspaces), None);
+    }
+
+    #[test]
+    fn resolve_follows_renumbered_workspaces() {
+        let output = OutputWorkspace::locate(WorkspaceId(2), &workspaces()).unwrap();
+        let renumbered = vec![(WorkspaceId(6), "HDMI-A-1".to_string())];
+        assert_eq!(output.resolve(&renumbered), Some(WorkspaceId(6)));
+        assert_eq!(output.resolve(&[]), None);
+    }
+}
@@ -1,29 +1,105 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
-use crate::state::{FloatingWindow, ParseError, Workspace};
+use crate::state::{FloatingWindow, ParseError, Split, Workspace};
 
 #[derive(Clone, Debug)]
 pub struct Program {
     pub class: String,
     pub workspaces: Vec<Workspace>,
     pub floating_window: Option<FloatingWindow>,
+    // Hyprland window properties, e.g. alpha, reapplied when the program opens
+    pub properties: BTreeMap<String, String>,
+    // How its tiled windows were last split, reapplied when the program opens
+    pub split: Option<Split>,
     pub moved: bool,
     pub float_moved: bool,
 }
 
+impl Program {
+    // Nothing learned at all, a line like `class:[]&[]`. Such a program predicts nothing and is
+    // not worth storing
+    pub fn is_empty(&self) -> bool {
+        self.workspaces.is_empty()
+            && self.floating_window.is_none()
+            && self.properties.is_empty()
+            && self.split.is_none()
+    }
+
+    // Folds the history into a single decaying weight per workspace, this keeps the
+    // score of every workspace while bounding storage to the number of workspaces
+    pub fn aggregate(&mut self, tau: f64) {
+        let mut workspaces = std::mem::take(&mut self.workspaces);
+        workspaces.sort_by_key(|workspace| workspace.timestamp);
+        for workspace in workspaces {
+            self.add_weighted(workspace, tau);
+        }
+    }
+
+    // Combines the history of two programs of the same class, keeping the newest entries
+    pub fn merge(&mut self, other: Program, buffer: usize) {
+        self.workspaces.extend(other.workspaces);
+        self.workspaces.sort_by_key(|workspace| workspace.timestamp);
+        let excess = self.workspaces.len().saturating_sub(buffer);
+        self.workspaces.drain(..excess);
+        if self.floating_window.is_none() {
+            self.floating_window = other.floating_window;
+        }
+        for (property, value) in other.properties {
+            self.properties.entry(property).or_insert(value);
+        }
+        if self.split.is_none() {
+            self.split = other.split;
+        }
+    }
+
+    // weight = weight * decay + w, where decay is the aging since the last update
+    pub fn add_weighted(&mut self, workspace: Workspace, tau: f64) {
+        match self.workspaces.iter_mut().find(|val| {
+            val.workspace_id == workspace.workspace_id && val.monitor == workspace.monitor
+        }) {
+            Some(val) => {
+                val.weight = val.weight * val.decay(workspace.timestamp, tau) + workspace.weight;
+                val.timestamp = workspace.timestamp;
+                if workspace.output.is_some() {
+                    val.output = workspace.output;
+                }
+                if workspace.name.is_some() {
+                    val.name = workspace.name;
+                }
+            }
+            None => self.workspaces.push(workspace),
+        }
+    }
+}
+
 impl Display for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:[", self.class)?;
         for (i, workspace) in self.workspaces.iter().enumerate() {
-            write!(f, "{}", workspace)?;
-            if i != self.workspaces.len() - 1 {
+            if i != 0 {
                 write!(f, ",")?;
             }
+            write!(f, "{}", workspace)?;
         }
         match &self.floating_window {
-            Some(floating_window) => write!(f, "]&[{}]", floating_window),
-            None => write!(f, "]&[]"),
+            Some(floating_window) => write!(f, "]&[{}]", floating_window)?,
+            None => write!(f, "]&[]")?,
+        }
+        // Only written when there are properties or a split, so older versions still read the line
+        if !self.properties.is_empty() || self.split.is_some() {
+            write!(f, "&[")?;
+            for (i, (property, value)) in self.properties.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, "{property}={value}")?;
+            }
+            write!(f, "]")?;
         }
+        if let Some(split) = self.split {
+            write!(f, "&[{split}]")?;
+        }
+        Ok(())
     }
 }
 
@@ -41,6 +117,7 @@ impl FromStr for Program {
             .trim()
             .trim_matches(['[', ']'])
             .split(',')
+            .filter(|val| !val.is_empty())
             .collect();
 
         let mut workspaces: Vec<Workspace> = Vec::with_capacity(workspaces_str.len());
@@ -49,16 +126,115 @@ impl FromStr for Program {
             workspaces.push(workspace);
         }
 
-        let window_str = data.last().unwrap_or(&"0").trim().trim_matches(['[', ']']);
+        let window_str = data.get(1).unwrap_or(&"0").trim().trim_matches(['[', ']']);
 
         let floating_window = FloatingWindow::from_str(window_str).ok();
 
+        let mut properties = BTreeMap::new();
+        if let Some(properties_str) = data.get(2) {
+            for property in properties_str.trim().trim_matches(['[', ']']).split(';') {
+                if property.is_empty() {
+                    continue;
+                }
+                match property.split_once('=') {
+                    Some((property, value)) => {
+                        properties.insert(property.to_string(), value.to_string());
+                    }
+                    None => return Err(ParseError::InvalidFormat),
+                }
+            }
+        }
+
+        let split = match data.get(3) {
+            Some(val) => Some(Split::from_str(val.trim().trim_matches(['[', ']']))?),
+            None => None,
+        };
+
         Ok(Program {
             class: class.to_string(),
             workspaces,
             floating_window,
+            properties,
+            split,
             moved: false,
             float_moved: false,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::WorkspaceId;
+
+    fn round_trip(line: &str) -> Program {
+        let program = Program::from_str(line).unwrap();
+        assert_eq!(program.to_string(), line);
+        program
+    }
+
+    #[test]
+    fn history_only() {
+        let program = round_trip("kitty:[1;100,2;200]&[]");
+        assert_eq!(program.class, "kitty");
+        assert_eq!(program.workspaces.len(), 2);
+        assert!(program.floating_window.is_none());
+        assert!(!program.is_empty());
+    }
+
+    #[test]
+    fn floating_properties_and_split() {
+        let program = round_trip("pavucontrol:[3;100]&[10;20;300;400]&[alpha=0.9;opaque=1]&[v]");
+        assert_eq!(program.floating_window.unwrap().size, (300, 400));
+        assert_eq!(
+            program.properties.get("alpha").map(String::as_str),
+            Some("0.9")
+        );
+        assert_eq!(program.split, Some(Split::Vertical));
+        // A split alone still writes the empty property list before it
+        round_trip("foot:[]&[]&[]&[h]");
+    }
+
+    #[test]
+    fn empty_program() {
+        assert!(round_trip("kitty:[]&[]").is_empty());
+        assert!(Program::from_str("kitty:[]&[]&[alpha]").is_err());
+    }
+
+    #[test]
+    fn merge_keeps_newest_entries() {
+        let mut program = Program::from_str("kitty:[1;100,2;300]&[]").unwrap();
+        let other = Program::from_str("kitty:[3;200]&[1;1;1;1]&[alpha=1]").unwrap();
+        program.merge(other, 2);
+        assert_eq!(
+            program.to_string(),
+            "kitty:[3;200,2;300]&[1;1;1;1]&[alpha=1]"
+        );
+    }
+
+    #[test]
+    fn aggregate_folds_repeats() {
+        let mut program = Program::from_str("kitty:[1;100,2;100,1;100]&[]").unwrap();
+        program.aggregate(1000.0);
+        let weights: Vec<(i32, f64)> = program
+            .workspaces
+            .iter()
+            .map(|val| (val.workspace_id.0, val.weight))
+            .collect();
+        assert_eq!(weights, vec![(1, 2.0), (2, 1.0)]);
+    }
+
+    #[test]
+    fn add_weighted_decays_the_old_weight() {
+        let mut program = Program::from_str("kitty:[1;0]&[]").unwrap();
+        let mut workspace = Workspace::new(WorkspaceId(1), 1000);
+        workspace.weight = 0.5;
+        program.add_weighted(workspace, 1000.0);
+        // One tau later the old weight counts e^-1
+        assert_eq!(program.workspaces.len(), 1);
+        assert!((program.workspaces[0].weight - ((-1.0f64).exp() + 0.5)).abs() < 1e-9);
+        assert_eq!(program.workspaces[0].timestamp, 1000);
+        program.add_weighted(Workspace::new(WorkspaceId(2), 1000), 1000.0);
+        assert_eq!(program.workspaces.len(), 2);
+    }
+}
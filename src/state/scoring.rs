@@ -0,0 +1,64 @@
+use std::ops::RangeInclusive;
+
+use crate::{config::Config, state::WorkspaceId};
+
+// How a history is turned into workspace scores
+#[derive(Clone, Debug)]
+pub struct Scoring {
+    pub tau: f64,
+    // 0 turns time of day weighting off
+    pub time_of_day_weight: f64,
+    // Regular workspaces outside the range are never scored, special workspaces always are
+    pub range: RangeInclusive<i32>,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            tau: 604800.0,
+            time_of_day_weight: 0.0,
+            range: i32::MIN..=i32::MAX,
+        }
+    }
+}
+
+impl Scoring {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            tau: config.workspace.tau,
+            time_of_day_weight: config.workspace.time_of_day_weight,
+            range: config.workspace.range(),
+        }
+    }
+
+    pub fn allows(&self, workspace_id: WorkspaceId) -> bool {
+        workspace_id.is_special() || self.range.contains(&workspace_id.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_range_allows_every_workspace() {
+        let scoring = Scoring::new(&Config::default());
+        assert!(scoring.allows(WorkspaceId(0)));
+        assert!(scoring.allows(WorkspaceId(47)));
+        // Named workspaces get ids from -1337 down
+        assert!(scoring.allows(WorkspaceId(-1337)));
+    }
+
+    #[test]
+    fn range_limits_regular_workspaces() {
+        let mut config = Config::default();
+        config.workspace.min_id = 1;
+        config.workspace.max_id = 10;
+        let scoring = Scoring::new(&config);
+        assert!(!scoring.allows(WorkspaceId(0)));
+        assert!(scoring.allows(WorkspaceId(1)));
+        assert!(scoring.allows(WorkspaceId(10)));
+        assert!(!scoring.allows(WorkspaceId(11)));
+        assert!(scoring.allows(WorkspaceId(-98)));
+    }
+}
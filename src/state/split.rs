@@ -0,0 +1,62 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::state::{ClientInfo, MonitorInfo, ParseError, client_monitor};
+
+// Which way a tiled window was split off from its neighbour
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Split {
+    // Next to its neighbour
+    Horizontal,
+    // Above or below its neighbour
+    Vertical,
+}
+
+impl Split {
+    // Guessed from the shape of the window compared to its monitor, a window narrower than the
+    // monitor sits next to a neighbour. A window alone on its workspace says nothing
+    pub fn observe(
+        client: &ClientInfo,
+        clients: &[ClientInfo],
+        monitors: &[MonitorInfo],
+    ) -> Option<Self> {
+        if client.floating {
+            return None;
+        }
+        let tiled = clients
+            .iter()
+            .filter(|val| !val.floating && val.workspace == client.workspace)
+            .count();
+        if tiled < 2 {
+            return None;
+        }
+        let (_, _, width, height) = client_monitor(client, monitors)?;
+        let monitor_ratio = width as f32 / height.max(1) as f32;
+        let ratio = client.size.0 as f32 / client.size.1.max(1) as f32;
+        if ratio < monitor_ratio {
+            Some(Split::Horizontal)
+        } else {
+            Some(Split::Vertical)
+        }
+    }
+}
+
+impl Display for Split {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Split::Horizontal => write!(f, "h"),
+            Split::Vertical => write!(f, "v"),
+        }
+    }
+}
+
+impl FromStr for Split {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h" => Ok(Split::Horizontal),
+            "v" => Ok(Split::Vertical),
+            _ => Err(ParseError::InvalidFormat),
+        }
+    }
+}
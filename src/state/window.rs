@@ -1,8 +1,12 @@
 use chrono::{DateTime, Utc};
 
+use crate::state::WorkspaceId;
+
 #[derive(Clone, Debug)]
 pub struct Window {
     pub class: String,
     pub timestamp: DateTime<Utc>,
-    pub origin: i32,
+    pub origin: WorkspaceId,
+    // Monitor that was focused when the window opened, if it could be read
+    pub monitor: Option<String>,
 }
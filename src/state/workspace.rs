@@ -1,16 +1,85 @@
-use std::{fmt::Display, str::FromStr};
+use std::{f64, fmt::Display, str::FromStr};
 
-use crate::state::ParseError;
+use chrono::{DateTime, Local, Timelike};
+
+use crate::state::{OutputWorkspace, ParseError, WorkspaceId};
 
 #[derive(Clone, Debug)]
 pub struct Workspace {
-    pub workspace_id: i32,
+    pub workspace_id: WorkspaceId,
     pub timestamp: i64,
+    // Raw entries weigh 1, aggregated entries carry the decayed sum of every move they stand for
+    pub weight: f64,
+    pub output: Option<OutputWorkspace>,
+    // Name of a named workspace, so it can be moved to after hyprland has dropped it
+    pub name: Option<String>,
+    // Monitor the move was learned on, only set with workspace.per_monitor
+    pub monitor: Option<String>,
+}
+
+impl Workspace {
+    pub fn new(workspace_id: WorkspaceId, timestamp: i64) -> Self {
+        Self {
+            workspace_id,
+            timestamp,
+            weight: 1.0,
+            output: None,
+            name: None,
+            monitor: None,
+        }
+    }
+
+    // Aging function e^(-age / τ)
+    pub fn decay(&self, now: i64, tau: f64) -> f64 {
+        let age = (now - self.timestamp) as f64;
+        f64::powf(f64::consts::E, -age / tau)
+    }
+
+    // 1 when the move was made at the same time of day as `now`, 1 - weight when it was
+    // made twelve hours apart, in local time
+    pub fn time_of_day(&self, now: &DateTime<Local>, weight: f64) -> f64 {
+        let then = match DateTime::from_timestamp(self.timestamp, 0) {
+            Some(val) => val.with_timezone(&Local),
+            None => return 1.0,
+        };
+        let minutes = |time: &DateTime<Local>| (time.hour() * 60 + time.minute()) as f64;
+        let apart = (minutes(now) - minutes(&then)).abs();
+        let apart = apart.min(24.0 * 60.0 - apart);
+        1.0 - weight * apart / (12.0 * 60.0)
+    }
 }
 
 impl Display for Workspace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{};{}", self.workspace_id, self.timestamp)
+        if self.name.is_some() || self.monitor.is_some() {
+            let output = match &self.output {
+                Some(val) => val.to_string(),
+                None => String::new(),
+            };
+            let name = self.name.as_deref().unwrap_or_default();
+            write!(
+                f,
+                "{};{};{};{output};{name}",
+                self.workspace_id, self.timestamp, self.weight
+            )?;
+            if let Some(monitor) = &self.monitor {
+                write!(f, ";{monitor}")?;
+            }
+            return Ok(());
+        }
+        match &self.output {
+            Some(output) => write!(
+                f,
+                "{};{};{};{}",
+                self.workspace_id, self.timestamp, self.weight, output
+            ),
+            None if self.weight == 1.0 => write!(f, "{};{}", self.workspace_id, self.timestamp),
+            None => write!(
+                f,
+                "{};{};{}",
+                self.workspace_id, self.timestamp, self.weight
+            ),
+        }
     }
 }
 
@@ -22,16 +91,85 @@ impl FromStr for Workspace {
             return Err(ParseError::InvalidFormat);
         }
         let parts: Vec<&str> = s.split(";").collect();
-        if parts.len() != 2 {
+        if !(2..=6).contains(&parts.len()) {
             return Err(ParseError::InvalidFormat);
         }
 
-        let workspace_id: i32 = parts[0].parse()?;
+        let workspace_id: WorkspaceId = parts[0].parse()?;
         let timestamp: i64 = parts[1].parse()?;
+        let weight: f64 = match parts.get(2) {
+            Some(val) => val.parse()?,
+            None => 1.0,
+        };
+        // The output is left empty when only a name follows
+        let output = match parts.get(3) {
+            Some(val) if !val.is_empty() => Some(OutputWorkspace::from_str(val)?),
+            _ => None,
+        };
+        // The name is left empty when only a monitor follows
+        let name = parts
+            .get(4)
+            .filter(|val| !val.is_empty())
+            .map(|val| val.to_string());
+        let monitor = parts.get(5).map(|val| val.to_string());
 
         Ok(Workspace {
             workspace_id,
             timestamp,
+            weight,
+            output,
+            name,
+            monitor,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(line: &str) -> Workspace {
+        let workspace = Workspace::from_str(line).unwrap();
+        assert_eq!(workspace.to_string(), line);
+        workspace
+    }
+
+    #[test]
+    fn plain_entry() {
+        let workspace = round_trip("3;1700000000");
+        assert_eq!(workspace.workspace_id, WorkspaceId(3));
+        assert_eq!(workspace.timestamp, 1700000000);
+        assert_eq!(workspace.weight, 1.0);
+    }
+
+    #[test]
+    fn weighted_entry_with_output() {
+        let workspace = round_trip("2;1700000000;2.5;DP-1@1");
+        assert_eq!(workspace.weight, 2.5);
+        assert_eq!(
+            workspace.output,
+            Some(OutputWorkspace {
+                output: "DP-1".to_string(),
+                index: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn name_and_monitor_without_output() {
+        let workspace = round_trip("7;1700000000;1;;music;HDMI-A-1");
+        assert_eq!(workspace.output, None);
+        assert_eq!(workspace.name.as_deref(), Some("music"));
+        assert_eq!(workspace.monitor.as_deref(), Some("HDMI-A-1"));
+        let workspace = round_trip("7;1700000000;1;;;HDMI-A-1");
+        assert_eq!(workspace.name, None);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(Workspace::from_str("").is_err());
+        assert!(Workspace::from_str("3").is_err());
+        assert!(Workspace::from_str("x;1").is_err());
+        assert!(Workspace::from_str("1;2;3;;;;").is_err());
+    }
+}
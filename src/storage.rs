@@ -1,18 +1,50 @@
 use std::{
-    fs::{File, OpenOptions, create_dir_all},
-    io::{Read, Seek, SeekFrom, Write},
+    fs::{File, OpenOptions, create_dir_all, remove_file, rename},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::atomic::Ordering,
 };
 
-use log::error;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
 use thiserror::Error;
 
-use crate::state::{ParseError, Program};
+use crate::{
+    config::is_read_only,
+    state::{Counter, ParseError, Program, State},
+};
+
+// Version written at the top of the storage file, bump it and add a step to
+// `migrate` whenever the line format changes
+const VERSION: u32 = 4;
+const VERSION_PREFIX: &str = "#v";
+const COMPRESSED_EXTENSION: &str = "gz";
+const TEMP_EXTENSION: &str = "tmp";
 
 pub struct Storage {
-    file: File,
+    // None when opened read only and there was no file to read
+    file: Option<Store>,
+    path: PathBuf,
+    read_only: bool,
+    outdated: bool,
+    fsync: bool,
+    // Whether the open file is gzip compressed and whether it should be on the next write
+    compressed: bool,
+    compress: bool,
+}
+
+// Where the bytes of the storage live
+enum Store {
+    File(File),
+    #[cfg(test)]
+    Mem(MemStore),
 }
 
+// Storage kept in memory, clones share the same bytes so a test can read back what was written
+#[cfg(test)]
+#[derive(Clone, Debug, Default)]
+pub struct MemStore(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("could not find config directory")]
@@ -21,38 +53,148 @@ pub enum Error {
     IO(#[from] std::io::Error),
     #[error("parsing error: {0}")]
     ParseError(#[from] ParseError),
+    #[error("storage version {0} is newer than this version of nest supports")]
+    UnsupportedVersion(u32),
+    #[error("{0} is not writable, fix its permissions or start nest with --read-only")]
+    ReadOnly(PathBuf),
 }
 
 impl Storage {
     pub fn new(app_name: &str, file_name: &str) -> Result<Self, Error> {
-        let config_dir = match dirs::config_dir() {
-            Some(val) => val,
-            None => return Err(Error::MissingConfig),
+        let app_dir = Self::dir(app_name)?;
+        match create_dir_all(&app_dir) {
+            Ok(()) => (),
+            Err(err) if is_read_only(&err) => return Err(Error::ReadOnly(app_dir)),
+            Err(err) => return Err(Error::IO(err)),
+        }
+        Self::in_dir(&app_dir, file_name)
+    }
+
+    pub fn in_dir(app_dir: &Path, file_name: &str) -> Result<Self, Error> {
+        let (path, compressed) = Self::existing(app_dir, file_name);
+        let file = Self::open(&path, false)?;
+
+        Ok(Self {
+            file: Some(Store::File(file)),
+            path,
+            read_only: false,
+            outdated: false,
+            fsync: false,
+            compressed,
+            compress: compressed,
+        })
+    }
+
+    // Opens the storage without ever creating or writing it
+    pub fn read_only(app_name: &str, file_name: &str) -> Result<Self, Error> {
+        let (path, compressed) = Self::existing(&Self::dir(app_name)?, file_name);
+        let file = if path.exists() {
+            Some(Store::File(File::open(&path)?))
+        } else {
+            None
         };
-        let app_dir = config_dir.join(app_name);
-        create_dir_all(&app_dir)?;
-        let storage_path = app_dir.join(file_name);
+        Ok(Self {
+            file,
+            path,
+            read_only: true,
+            outdated: false,
+            fsync: false,
+            compressed,
+            compress: compressed,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn memory(store: MemStore) -> Self {
+        Self {
+            file: Some(Store::Mem(store)),
+            path: PathBuf::from("memory"),
+            read_only: false,
+            outdated: false,
+            fsync: false,
+            compressed: false,
+            compress: false,
+        }
+    }
+
+    // The compressed file is used if there is one, otherwise the plain text one
+    fn existing(app_dir: &Path, file_name: &str) -> (PathBuf, bool) {
+        let compressed = app_dir.join(format!("{file_name}.{COMPRESSED_EXTENSION}"));
+        if compressed.exists() {
+            (compressed, true)
+        } else {
+            (app_dir.join(file_name), false)
+        }
+    }
+
+    // Opens the file again, editors often replace it instead of writing to it
+    pub fn reopen(&mut self) -> Result<(), Error> {
+        self.file = if !self.read_only {
+            Some(Store::File(Self::open(&self.path, false)?))
+        } else if self.path.exists() {
+            Some(Store::File(File::open(&self.path)?))
+        } else {
+            None
+        };
+        Ok(())
+    }
 
-        let file = OpenOptions::new()
+    fn open(path: &Path, truncate: bool) -> Result<File, Error> {
+        match OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .truncate(false)
-            .open(storage_path)?;
+            .truncate(truncate)
+            .open(path)
+        {
+            Ok(val) => Ok(val),
+            Err(err) if is_read_only(&err) => Err(Error::ReadOnly(path.to_path_buf())),
+            Err(err) => Err(Error::IO(err)),
+        }
+    }
 
-        Ok(Self { file })
+    fn dir(app_name: &str) -> Result<PathBuf, Error> {
+        match dirs::config_dir() {
+            Some(val) => Ok(val.join(app_name)),
+            None => Err(Error::MissingConfig),
+        }
     }
 
     pub fn read(&mut self) -> Result<Vec<Program>, Error> {
+        let file = match &mut self.file {
+            Some(val) => val,
+            None => return Ok(Vec::new()),
+        };
+        let bytes = file.read_all()?;
         let mut buf = String::new();
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.read_to_string(&mut buf)?;
+        if self.compressed {
+            // A freshly created file is empty and not valid gzip yet
+            if !bytes.is_empty() {
+                GzDecoder::new(bytes.as_slice()).read_to_string(&mut buf)?;
+            }
+        } else {
+            buf = String::from_utf8(bytes)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        }
 
-        let lines: Vec<&str> = buf.lines().collect();
+        let mut lines: Vec<&str> = buf.lines().filter(|line| !line.is_empty()).collect();
+        // Files without a marker were written before versioning existed
+        let version = match lines
+            .first()
+            .and_then(|line| line.strip_prefix(VERSION_PREFIX))
+        {
+            Some(val) => {
+                lines.remove(0);
+                val.parse().map_err(ParseError::from)?
+            }
+            None => 1,
+        };
+        let lines = migrate(version, lines)?;
+        self.outdated = version < VERSION;
         let mut programs: Vec<Program> = Vec::with_capacity(lines.len());
 
         for line in lines {
-            let program = match Program::from_str(line) {
+            let program = match Program::from_str(&line) {
                 Ok(val) => val,
                 Err(err) => {
                     // error!("A program failed to parse: {err}");
@@ -64,16 +206,329 @@ impl Storage {
         Ok(programs)
     }
 
-    pub fn write(&mut self, programs: &Vec<Program>) -> Result<(), Error> {
-        self.file.set_len(0)?;
-        self.file.seek(SeekFrom::Start(0))?;
-        let mut content = String::new();
-        for program in programs {
+    pub fn write(&mut self, programs: &[Program]) -> Result<(), Error> {
+        if self.read_only || self.file.is_none() {
+            return Err(Error::ReadOnly(self.path.clone()));
+        }
+        let mut content = format!("{VERSION_PREFIX}{VERSION}\n");
+        // Programs only seen on special workspaces have nothing to store yet
+        for program in programs.iter().filter(|program| !program.is_empty()) {
             content.push_str(&program.to_string());
             content.push('\n');
         }
-        self.file.write_all(content.as_bytes())?;
-        self.file.flush()?;
+        let content = if self.compress {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(content.as_bytes())?;
+            encoder.finish()?
+        } else {
+            content.into_bytes()
+        };
+        if self.compress != self.compressed {
+            self.switch_format(&content)?;
+            self.outdated = false;
+            return Ok(());
+        }
+        let file = match &mut self.file {
+            Some(val) => val,
+            None => return Err(Error::ReadOnly(self.path.clone())),
+        };
+        file.replace(&content, self.fsync)?;
+        self.outdated = false;
+        Ok(())
+    }
+
+    // Moves over to the other file. The new file is written in full next to it and renamed
+    // into place before the old one is removed, so a failed write keeps the old history
+    fn switch_format(&mut self, content: &[u8]) -> Result<(), Error> {
+        let path = if self.compress {
+            with_suffix(&self.path, COMPRESSED_EXTENSION)
+        } else {
+            self.path.with_extension("")
+        };
+        let temp = with_suffix(&path, TEMP_EXTENSION);
+        let mut file = Self::open(&temp, true)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        rename(&temp, &path)?;
+        remove_file(&self.path)?;
+        self.file = Some(Store::File(file));
+        self.path = path;
+        self.compressed = self.compress;
+        Ok(())
+    }
+
+    // Makes every write wait until the file is on disk
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
+
+    // Picks the format of the next write, switching format replaces the file
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    // True if the file was written by an older version and should be rewritten
+    pub fn outdated(&self) -> bool {
+        self.outdated
+    }
+
+    // True if the file is in the other format than configured and is converted on the next save
+    pub fn converting(&self) -> bool {
+        self.compress != self.compressed
+    }
+
+    // Writes the current state to disk and marks it as saved. The flag is cleared before the
+    // snapshot is taken, so changes made while writing are picked up by the next save
+    pub async fn persist(&mut self, state: &State) -> Result<(), Error> {
+        let changed = state.changed.swap(false, Ordering::Relaxed);
+        let programs = state.get_programs().await;
+        if let Err(err) = self.write(&programs) {
+            state.changed.fetch_or(changed, Ordering::Relaxed);
+            state.count(Counter::SaveFailures);
+            return Err(err);
+        }
+        state.mark_saved();
         Ok(())
     }
 }
+
+impl Store {
+    fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            Store::File(file) => {
+                let mut bytes = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            #[cfg(test)]
+            Store::Mem(store) => Ok(store.0.lock().unwrap().clone()),
+        }
+    }
+
+    // Swaps the whole content for `content`
+    fn replace(&mut self, content: &[u8], fsync: bool) -> io::Result<()> {
+        match self {
+            Store::File(file) => {
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(content)?;
+                file.flush()?;
+                if fsync {
+                    file.sync_all()?;
+                }
+                Ok(())
+            }
+            #[cfg(test)]
+            Store::Mem(store) => {
+                *store.0.lock().unwrap() = content.to_vec();
+                Ok(())
+            }
+        }
+    }
+}
+
+// `storage.txt` with suffix `gz` is `storage.txt.gz`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{suffix}"));
+    PathBuf::from(name)
+}
+
+// Upgrades the lines of an older storage file to the current format
+fn migrate(version: u32, lines: Vec<&str>) -> Result<Vec<String>, Error> {
+    if version > VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    // v1 -> v2 only added optional fields (weights and outputs), so v1 lines are valid v2 lines.
+    // v2 -> v3 added an optional workspace name, so v2 lines are valid v3 lines too.
+    // v3 -> v4 added an optional monitor per entry, so v3 lines are valid v4 lines too.
+    // Future steps rewrite the lines here, oldest version first
+    Ok(lines.into_iter().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateBuilder;
+
+    fn storage(content: &str) -> (Storage, MemStore) {
+        let store = MemStore::default();
+        *store.0.lock().unwrap() = content.as_bytes().to_vec();
+        (Storage::memory(store.clone()), store)
+    }
+
+    #[test]
+    fn migrate_keeps_older_lines() {
+        let lines = vec!["kitty:[1;100]&[]"];
+        for version in 1..=VERSION {
+            assert_eq!(migrate(version, lines.clone()).unwrap(), lines);
+        }
+        assert!(matches!(
+            migrate(VERSION + 1, lines),
+            Err(Error::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn unversioned_file_is_outdated() {
+        let (mut storage, _) = storage("kitty:[1;100]&[]\n\nfoot:[2;200]&[]\n");
+        let programs = storage.read().unwrap();
+        assert_eq!(programs.len(), 2);
+        assert!(storage.outdated());
+    }
+
+    #[test]
+    fn newer_file_is_refused() {
+        let (mut storage, _) = storage(&format!("{VERSION_PREFIX}{}\n", VERSION + 1));
+        assert!(matches!(storage.read(), Err(Error::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn write_then_read() {
+        let (mut storage, store) = storage("#v1\nkitty:[1;100]&[]\n");
+        let programs = storage.read().unwrap();
+        storage.write(&programs).unwrap();
+        assert!(!storage.outdated());
+        let content = String::from_utf8(store.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            content,
+            format!("{VERSION_PREFIX}{VERSION}\nkitty:[1;100]&[]\n")
+        );
+    }
+
+    // An empty directory of its own, removed again by the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nest-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let dir = temp_dir("compressed");
+        let plain = dir.join("storage.txt");
+        let compressed = dir.join("storage.txt.gz");
+        let programs = vec![Program::from_str("kitty:[1;100]&[]").unwrap()];
+        let mut storage = Storage::in_dir(&dir, "storage.txt").unwrap();
+        storage.write(&programs).unwrap();
+
+        storage.set_compress(true);
+        assert!(storage.converting());
+        storage.write(&programs).unwrap();
+        assert!(!storage.converting());
+        // Renamed into place, the plain file is gone
+        assert!(!plain.exists());
+        assert!(!dir.join("storage.txt.gz.tmp").exists());
+        // Gzip magic bytes
+        assert_eq!(std::fs::read(&compressed).unwrap()[..2], [0x1f, 0x8b]);
+
+        // The next start picks the compressed file
+        let mut storage = Storage::in_dir(&dir, "storage.txt").unwrap();
+        assert!(!storage.converting());
+        assert_eq!(
+            storage.read().unwrap()[0].to_string(),
+            programs[0].to_string()
+        );
+
+        // And back to plain text
+        storage.set_compress(false);
+        storage.write(&programs).unwrap();
+        assert!(!compressed.exists());
+        assert_eq!(
+            std::fs::read_to_string(&plain).unwrap(),
+            format!("{VERSION_PREFIX}{VERSION}\nkitty:[1;100]&[]\n")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_reads_a_replaced_file() {
+        let dir = temp_dir("reopen");
+        let path = dir.join("storage.txt");
+        let mut storage = Storage::in_dir(&dir, "storage.txt").unwrap();
+        storage
+            .write(&[Program::from_str("kitty:[1;100]&[]").unwrap()])
+            .unwrap();
+        // Editors write a new file and rename it over the old one
+        let edited = dir.join("edited");
+        std::fs::write(
+            &edited,
+            format!("{VERSION_PREFIX}{VERSION}\nfoot:[2;200]&[]\n"),
+        )
+        .unwrap();
+        rename(&edited, &path).unwrap();
+
+        storage.reopen().unwrap();
+        let programs = storage.read().unwrap();
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].class, "foot");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn changed_state() -> State {
+        let state = StateBuilder::default().build();
+        state
+            .reload(vec![Program::from_str("kitty:[1;100]&[]").unwrap()], false)
+            .await;
+        state.changed.store(true, Ordering::Relaxed);
+        state
+    }
+
+    #[tokio::test]
+    async fn persist_clears_changed() {
+        let (mut storage, store) = storage("");
+        let state = changed_state().await;
+        storage.persist(&state).await.unwrap();
+        assert!(!state.changed.load(Ordering::Relaxed));
+        assert!(state.since_last_save().is_some());
+        let content = String::from_utf8(store.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            content,
+            format!("{VERSION_PREFIX}{VERSION}\nkitty:[1;100]&[]\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_persist_keeps_changed() {
+        let (mut storage, store) = storage("");
+        storage.read_only = true;
+        let state = changed_state().await;
+        assert!(matches!(
+            storage.persist(&state).await,
+            Err(Error::ReadOnly(_))
+        ));
+        // Still unsaved, the next save tries again
+        assert!(state.changed.load(Ordering::Relaxed));
+        assert!(state.since_last_save().is_none());
+        assert!(store.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn saves_are_byte_identical() {
+        let dir = temp_dir("identical");
+        let lines: Vec<String> = (0..20)
+            .map(|i| format!("class{i}:[{};{}]&[]", i % 5 + 1, 100 + i))
+            .collect();
+        let programs: Vec<Program> = lines.iter().map(|val| val.parse().unwrap()).collect();
+        let mut saved = Vec::new();
+        // The same programs learned in another order, each state hashes its classes differently
+        for programs in [programs.clone(), programs.into_iter().rev().collect()] {
+            let state = StateBuilder::default().build();
+            state.reload(programs, false).await;
+            let mut storage = Storage::in_dir(&dir, "storage.txt").unwrap();
+            for _ in 0..2 {
+                storage.persist(&state).await.unwrap();
+                saved.push(std::fs::read(dir.join("storage.txt")).unwrap());
+            }
+        }
+        assert!(saved.windows(2).all(|pair| pair[0] == pair[1]));
+        // Sorted by class
+        let content = String::from_utf8(saved.remove(0)).unwrap();
+        let mut sorted = lines.clone();
+        sorted.sort_by_key(|line| line.split(':').next().unwrap().to_string());
+        assert_eq!(content.lines().skip(1).collect::<Vec<_>>(), sorted);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
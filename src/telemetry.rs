@@ -0,0 +1,189 @@
+use std::{
+    fs::{OpenOptions, create_dir_all},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use chrono::Utc;
+use hyprland::shared::Address;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::TelemetryConfig,
+    state::{SafeMap, WorkspaceId},
+};
+
+const HEADER: &str = "timestamp,class,workspace,score,moved";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find config directory")]
+    MissingConfig,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+#[derive(Clone, Debug)]
+struct Decision {
+    class: String,
+    workspace_id: WorkspaceId,
+    score: f64,
+}
+
+// Writes one csv row per prediction, kept apart from the main log so it can be analysed later
+#[derive(Clone)]
+pub struct Telemetry {
+    file: Option<Arc<Mutex<std::fs::File>>>,
+    pending: SafeMap<Address, Decision>,
+}
+
+impl Telemetry {
+    pub fn new(app_name: &str, config: &TelemetryConfig) -> Result<Self, Error> {
+        if !config.enabled {
+            return Ok(Self::disabled());
+        }
+
+        let mut path = PathBuf::from(&config.path);
+        if path.is_relative() {
+            let config_dir = match dirs::config_dir() {
+                Some(val) => val,
+                None => return Err(Error::MissingConfig),
+            };
+            path = config_dir.join(app_name).join(path);
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{HEADER}")?;
+        }
+
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(file))),
+            pending: SafeMap::new(),
+        })
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            file: None,
+            pending: SafeMap::new(),
+        }
+    }
+
+    // Records the decision made when a window opened
+    pub async fn record_decision(
+        &self,
+        address: &Address,
+        class: &str,
+        workspace_id: WorkspaceId,
+        score: f64,
+    ) -> Result<(), Error> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        self.write_row(class, workspace_id, score, false).await?;
+        let decision = Decision {
+            class: class.to_string(),
+            workspace_id,
+            score,
+        };
+        self.pending
+            .0
+            .lock()
+            .await
+            .insert(address.clone(), decision);
+        Ok(())
+    }
+
+    // Records that the user moved a window after nest placed it, only the first move counts
+    pub async fn record_move(&self, address: &Address) -> Result<(), Error> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        let decision = match self.pending.0.lock().await.remove(address) {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        self.write_row(&decision.class, decision.workspace_id, decision.score, true)
+            .await
+    }
+
+    pub async fn forget(&self, address: &Address) {
+        self.pending.0.lock().await.remove(address);
+    }
+
+    async fn write_row(
+        &self,
+        class: &str,
+        workspace_id: WorkspaceId,
+        score: f64,
+        moved: bool,
+    ) -> Result<(), Error> {
+        let file = match &self.file {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        let row = format_row(Utc::now().timestamp(), class, workspace_id, score, moved);
+        let mut file = file.lock().await;
+        writeln!(file, "{row}")?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+pub fn format_row(
+    timestamp: i64,
+    class: &str,
+    workspace_id: WorkspaceId,
+    score: f64,
+    moved: bool,
+) -> String {
+    let class = if class.contains([',', '"', '\n']) {
+        format!("\"{}\"", class.replace('"', "\"\""))
+    } else {
+        class.to_string()
+    };
+    format!("{timestamp},{class},{workspace_id},{score:.6},{moved}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_row() {
+        assert_eq!(
+            format_row(1700000000, "kitty", WorkspaceId(3), 1.5, false),
+            "1700000000,kitty,3,1.500000,false"
+        );
+    }
+
+    #[test]
+    fn quotes_classes_csv_would_split() {
+        assert_eq!(
+            format_row(1, "a,b", WorkspaceId(1), 0.0, true),
+            "1,\"a,b\",1,0.000000,true"
+        );
+        assert_eq!(
+            format_row(1, "say \"hi\"", WorkspaceId(1), 0.0, true),
+            "1,\"say \"\"hi\"\"\",1,0.000000,true"
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_telemetry_keeps_nothing() {
+        let telemetry = Telemetry::disabled();
+        let address = Address::new("a");
+        telemetry
+            .record_decision(&address, "kitty", WorkspaceId(1), 1.0)
+            .await
+            .unwrap();
+        telemetry.record_move(&address).await.unwrap();
+        assert!(telemetry.pending.0.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::{File, create_dir_all, read_to_string},
+    io::Write,
+    path::PathBuf,
+};
+
+use chrono::DateTime;
+use hyprland::shared::Address;
+use log::warn;
+use thiserror::Error;
+
+use crate::state::{State, Window, WorkspaceId, fmt_address};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not find config directory")]
+    MissingConfig,
+    #[error("io operation failed: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+// Keeps the open windows on disk so restoring still works for windows opened before a restart
+#[derive(Clone)]
+pub struct WindowFile {
+    path: Option<PathBuf>,
+}
+
+impl WindowFile {
+    pub fn new(app_name: &str, file_name: &str, enabled: bool) -> Result<Self, Error> {
+        if !enabled {
+            return Ok(Self { path: None });
+        }
+        let path = match dirs::config_dir() {
+            Some(val) => val.join(app_name).join(file_name),
+            None => return Err(Error::MissingConfig),
+        };
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        Ok(Self { path: Some(path) })
+    }
+
+    pub fn read(&self) -> Result<Vec<(Address, Window)>, Error> {
+        let path = match &self.path {
+            Some(val) => val,
+            None => return Ok(Vec::new()),
+        };
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut windows = Vec::new();
+        for line in read_to_string(path)?
+            .lines()
+            .filter(|line| !line.is_empty())
+        {
+            match parse_line(line) {
+                Some(val) => windows.push(val),
+                None => warn!("Skipping invalid window line {line}"),
+            }
+        }
+        Ok(windows)
+    }
+
+    // Writes the windows the state currently tracks
+    pub async fn persist(&self, state: &State) -> Result<(), Error> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        self.write(&state.windows().await)
+    }
+
+    // Lines are `address;class;origin;timestamp` with the monitor appended when known
+    pub fn write(&self, windows: &[(Address, Window)]) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        let mut file = File::create(path)?;
+        for (address, window) in windows {
+            write!(
+                file,
+                "{};{};{};{}",
+                fmt_address(address),
+                window.class,
+                window.origin,
+                window.timestamp.timestamp()
+            )?;
+            if let Some(monitor) = &window.monitor {
+                write!(file, ";{monitor}")?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Address, Window)> {
+    let mut fields = line.split(';');
+    let address = fields.next()?;
+    let class = fields.next()?;
+    let origin: WorkspaceId = fields.next()?.parse().ok()?;
+    let timestamp = DateTime::from_timestamp(fields.next()?.parse().ok()?, 0)?;
+    // Events name windows without the `0x`, stored addresses have to match them
+    let address = Address::new(address.trim_start_matches("0x"));
+    Some((
+        address,
+        Window {
+            class: class.to_string(),
+            timestamp,
+            origin,
+            monitor: fields.next().map(str::to_string),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lines_with_and_without_monitor() {
+        let (address, window) = parse_line("0x55d1;kitty;3;1700000000").unwrap();
+        assert_eq!(address, Address::new("55d1"));
+        assert_eq!(window.class, "kitty");
+        assert_eq!(window.origin, WorkspaceId(3));
+        assert_eq!(window.timestamp.timestamp(), 1700000000);
+        assert_eq!(window.monitor, None);
+        let (_, window) = parse_line("55d1;kitty;-98;1700000000;DP-1").unwrap();
+        assert_eq!(window.origin, WorkspaceId(-98));
+        assert_eq!(window.monitor.as_deref(), Some("DP-1"));
+    }
+
+    #[test]
+    fn rejects_broken_lines() {
+        assert!(parse_line("55d1;kitty").is_none());
+        assert!(parse_line("55d1;kitty;one;1700000000").is_none());
+        assert!(parse_line("55d1;kitty;1;yesterday").is_none());
+    }
+
+    #[test]
+    fn write_then_read() {
+        let path = std::env::temp_dir().join(format!("nest-windows-{}", std::process::id()));
+        let file = WindowFile {
+            path: Some(path.clone()),
+        };
+        let (address, window) = parse_line("55d1;kitty;3;1700000000;DP-1").unwrap();
+        file.write(&[(address.clone(), window)]).unwrap();
+        let windows = file.read().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].0, address);
+        assert_eq!(windows[0].1.monitor.as_deref(), Some("DP-1"));
+    }
+}